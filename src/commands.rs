@@ -1,7 +1,13 @@
 pub mod accounts;
+pub mod anomalies;
+pub mod budget;
+pub mod envelopes;
+pub mod forecast;
 pub mod import;
 pub mod info;
 pub mod query;
+pub mod recurring;
+pub mod refunds;
 pub mod reset;
 pub mod tags;
 pub mod undo;
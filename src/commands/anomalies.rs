@@ -0,0 +1,241 @@
+use crate::commands::recurring::{median, normalize_description};
+use crate::database::{
+    AnomalyBaseline, AnomalyKind, AnomalyRow, ByTagRow, ByTagTimeRow, DebitsRow,
+};
+use chrono::NaiveDate;
+use std::collections::BTreeMap;
+
+/// The default number of months of history to compare against.
+pub const DEFAULT_HISTORY: u32 = 6;
+
+/// The default number of spreads above the median that makes an amount an anomaly.
+pub const DEFAULT_THRESHOLD: f64 = 3.0;
+
+/// Baselines need at least this many amounts to be meaningful.
+const MIN_SAMPLES: usize = 3;
+
+/// Scales the median absolute deviation so that it estimates the standard deviation of normally
+/// distributed amounts.
+const MAD_SCALE: f64 = 1.4826;
+
+/// The spread is at least this fraction of the median, so that series of identical amounts don't
+/// make every small change an anomaly.
+const MIN_SPREAD: f64 = 0.1;
+
+/// The baseline of a series of amounts, using their median and median absolute deviation, which
+/// unlike the mean and standard deviation aren't skewed by the anomalies themselves. There is no
+/// baseline for an empty series or one with a median of zero.
+pub fn baseline(amounts: &[f64]) -> Option<AnomalyBaseline> {
+    if amounts.is_empty() {
+        return None;
+    }
+    let amounts_median = median(amounts);
+    if amounts_median <= 0.0 {
+        return None;
+    }
+    let deviations: Vec<_> = amounts.iter().map(|a| (a - amounts_median).abs()).collect();
+    Some(AnomalyBaseline {
+        median: amounts_median,
+        spread: (median(&deviations) * MAD_SCALE).max(amounts_median * MIN_SPREAD),
+        samples: amounts.len(),
+    })
+}
+
+fn score(amount: f64, baseline: &AnomalyBaseline) -> f64 {
+    (amount - baseline.median) / baseline.spread
+}
+
+/// Find the tags whose monthly spend in the current window is more than `threshold` spreads above
+/// their monthly spend in `history_months`, the month ends of the trailing history. Months
+/// without spend on a tag count as zero for it, but months without any spend at all are assumed to
+/// have no data, e.g. because they are from before the first import.
+pub fn tag_anomalies(
+    history: &[ByTagTimeRow],
+    history_months: &[NaiveDate],
+    current: &[ByTagRow],
+    months_in_window: f64,
+    threshold: f64,
+) -> Vec<AnomalyRow> {
+    let mut monthly_amounts: BTreeMap<&str, BTreeMap<NaiveDate, f64>> = BTreeMap::new();
+    for row in history {
+        *monthly_amounts
+            .entry(&row.tag)
+            .or_default()
+            .entry(row.aggregation_window_end)
+            .or_default() += row.amount;
+    }
+
+    let history_months: Vec<_> = history_months
+        .iter()
+        .filter(|month| history.iter().any(|r| r.aggregation_window_end == **month))
+        .collect();
+    if history_months.len() < MIN_SAMPLES {
+        return vec![];
+    }
+
+    let mut anomalies = vec![];
+    for row in current {
+        let Some(amounts) = monthly_amounts.get(row.tag.as_str()) else {
+            continue;
+        };
+        let amounts: Vec<_> = history_months
+            .iter()
+            .map(|month| amounts.get(*month).copied().unwrap_or(0.0))
+            .collect();
+        let Some(baseline) = baseline(&amounts) else {
+            continue;
+        };
+        let amount = row.tag_debit / months_in_window;
+        let score = score(amount, &baseline);
+        if score > threshold {
+            anomalies.push(AnomalyRow {
+                kind: AnomalyKind::Tag,
+                subject: row.tag.clone(),
+                date: None,
+                amount,
+                baseline,
+                score,
+            });
+        }
+    }
+    anomalies
+}
+
+/// Find the debits in `current` more than `threshold` spreads above the previous debits with the
+/// same normalized description, as long as there are enough of those.
+pub fn transaction_anomalies(
+    history: &[DebitsRow],
+    current: &[DebitsRow],
+    threshold: f64,
+) -> Vec<AnomalyRow> {
+    let mut merchant_amounts: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for row in history {
+        merchant_amounts
+            .entry(normalize_description(&row.description))
+            .or_default()
+            .push(row.debit_amount);
+    }
+
+    let mut anomalies = vec![];
+    for row in current {
+        let Some(amounts) = merchant_amounts.get(&normalize_description(&row.description)) else {
+            continue;
+        };
+        if amounts.len() < MIN_SAMPLES {
+            continue;
+        }
+        let Some(baseline) = baseline(amounts) else {
+            continue;
+        };
+        let score = score(row.debit_amount, &baseline);
+        if score > threshold {
+            anomalies.push(AnomalyRow {
+                kind: AnomalyKind::Transaction,
+                subject: row.description.clone(),
+                date: Some(row.posted_date),
+                amount: row.debit_amount,
+                baseline,
+                score,
+            });
+        }
+    }
+    anomalies
+}
+
+#[cfg(test)]
+mod anomalies_tests {
+    use super::*;
+
+    #[test]
+    fn robust_baseline() {
+        // The outlier barely moves the median or the spread.
+        let baseline = baseline(&[10.0, 12.0, 11.0, 9.0, 500.0]).unwrap();
+        assert_eq!(baseline.median, 11.0);
+        assert!((baseline.spread - 1.4826).abs() < 1e-9);
+        assert_eq!(baseline.samples, 5);
+        // Identical amounts still have a spread.
+        assert_eq!(super::baseline(&[20.0, 20.0, 20.0]).unwrap().spread, 2.0);
+        assert!(super::baseline(&[0.0, 0.0, 5.0]).is_none());
+        assert!(super::baseline(&[]).is_none());
+    }
+
+    #[test]
+    fn tags() {
+        let months: Vec<_> = [31, 28, 31]
+            .iter()
+            .enumerate()
+            .map(|(i, day)| NaiveDate::from_ymd_opt(2021, i as u32 + 1, *day).unwrap())
+            .collect();
+        let history: Vec<_> = months
+            .iter()
+            .flat_map(|month| {
+                [
+                    ByTagTimeRow {
+                        tag: "groceries".to_string(),
+                        aggregation_window_end: *month,
+                        amount: 200.0,
+                    },
+                    ByTagTimeRow {
+                        tag: "pubs".to_string(),
+                        aggregation_window_end: *month,
+                        amount: 50.0,
+                    },
+                ]
+            })
+            .collect();
+        let current = vec![
+            ByTagRow {
+                tag: "groceries".to_string(),
+                tag_debit: 210.0,
+                ..Default::default()
+            },
+            ByTagRow {
+                tag: "pubs".to_string(),
+                tag_debit: 150.0,
+                ..Default::default()
+            },
+            ByTagRow {
+                tag: "travel".to_string(),
+                tag_debit: 900.0,
+                ..Default::default()
+            },
+        ];
+        let anomalies = tag_anomalies(&history, &months, &current, 1.0, 3.0);
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].subject, "pubs");
+        assert_eq!(anomalies[0].score, 20.0);
+
+        // The same spend over two months is half as much a month.
+        let anomalies = tag_anomalies(&history, &months, &current, 2.0, 3.0);
+        assert_eq!(anomalies[0].amount, 75.0);
+
+        // Two months aren't enough history.
+        assert!(tag_anomalies(&history, &months[1..], &current, 1.0, 3.0).is_empty());
+    }
+
+    #[test]
+    fn transactions() {
+        let debit = |description: &str, day: u32, amount: f64| DebitsRow {
+            description: description.to_string(),
+            posted_date: NaiveDate::from_ymd_opt(2021, 3, day).unwrap(),
+            debit_amount: amount,
+            ..Default::default()
+        };
+        let history = vec![
+            debit("COFFEE 1", 1, 3.0),
+            debit("COFFEE 2", 2, 3.5),
+            debit("COFFEE 3", 3, 3.2),
+            debit("BOOKS", 4, 10.0),
+        ];
+        let current = vec![
+            debit("COFFEE 4", 10, 3.4),
+            debit("COFFEE 5", 11, 30.0),
+            // Not enough history.
+            debit("BOOKS", 12, 100.0),
+        ];
+        let anomalies = transaction_anomalies(&history, &current, 3.0);
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].subject, "COFFEE 5");
+        assert_eq!(anomalies[0].baseline.median, 3.2);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::database::{model, NewOrExisting, StingyDatabase};
+use crate::output::format::ToOutputFormat;
+use anyhow::{bail, Result};
+
+#[derive(Debug)]
+pub struct ListBudgetsResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+pub fn budget_period_name(period: model::BudgetPeriod) -> &'static str {
+    match period {
+        model::BudgetPeriod::Monthly => "monthly",
+        model::BudgetPeriod::Yearly => "yearly",
+    }
+}
+
+/// Set the budget for a tag, replacing its existing budget, if any. Returns whether the budget is
+/// new.
+pub fn set_budget(
+    db: &Box<dyn StingyDatabase>,
+    tag: &str,
+    amount: f64,
+    period: model::BudgetPeriod,
+) -> Result<bool> {
+    if tag.is_empty() {
+        bail!("the tag of a budget can't be empty.");
+    }
+    if amount < 0.0 {
+        bail!("the amount of a budget can't be negative.");
+    }
+    let mut budget = model::Budget {
+        id: None,
+        tag: tag.to_string(),
+        amount,
+        period,
+    };
+    match db.insert(budget.clone())? {
+        NewOrExisting::New(_) => Ok(true),
+        NewOrExisting::Existing => {
+            let budgets: Vec<model::Budget> = db.get_all()?;
+            budget.id = budgets.iter().find(|b| b.tag == tag).unwrap().id;
+            db.update(&budget)?;
+            Ok(false)
+        }
+    }
+}
+
+pub fn list_budgets(db: &Box<dyn StingyDatabase>) -> Result<ListBudgetsResult> {
+    let mut budgets: Vec<model::Budget> = db.get_all()?;
+    budgets.sort_by(|a, b| a.tag.cmp(&b.tag));
+    let columns = vec![
+        "Tag".to_string(),
+        "Amount".to_string(),
+        "Period".to_string(),
+    ];
+    let rows = budgets
+        .iter()
+        .map(|budget| {
+            vec![
+                budget.tag.to_output_format(),
+                budget.amount.to_output_format(),
+                budget_period_name(budget.period).to_string(),
+            ]
+        })
+        .collect();
+    Ok(ListBudgetsResult { columns, rows })
+}
+
+pub fn delete_budget(db: &Box<dyn StingyDatabase>, tag: &str) -> Result<usize> {
+    let budgets: Vec<model::Budget> = db.get_all()?;
+    match budgets.into_iter().find(|b| b.tag == tag) {
+        Some(budget) => db.delete(budget),
+        None => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod budget_tests {
+    use super::*;
+    use crate::database::open_stingy_testing_database;
+
+    #[test]
+    fn set_list_and_delete() {
+        let db = open_stingy_testing_database();
+        assert!(set_budget(&db, "food/", 300.0, model::BudgetPeriod::Monthly).unwrap());
+        assert!(set_budget(&db, "travel", 1000.0, model::BudgetPeriod::Monthly).unwrap());
+        // Setting it again replaces it.
+        assert!(!set_budget(&db, "travel", 2000.0, model::BudgetPeriod::Yearly).unwrap());
+
+        let result = list_budgets(&db).unwrap();
+        assert_eq!(result.columns, vec!["Tag", "Amount", "Period"]);
+        assert_eq!(
+            result.rows,
+            vec![
+                vec!["food/", "300.00", "monthly"],
+                vec!["travel", "2000.00", "yearly"]
+            ]
+        );
+
+        assert_eq!(delete_budget(&db, "travel").unwrap(), 1);
+        assert_eq!(delete_budget(&db, "travel").unwrap(), 0);
+        assert_eq!(list_budgets(&db).unwrap().rows.len(), 1);
+    }
+
+    #[test]
+    fn invalid_budgets() {
+        let db = open_stingy_testing_database();
+        assert!(set_budget(&db, "", 300.0, model::BudgetPeriod::Monthly).is_err());
+        assert!(set_budget(&db, "food", -1.0, model::BudgetPeriod::Monthly).is_err());
+    }
+
+    #[test]
+    fn undo_set() {
+        let db = open_stingy_testing_database();
+        crate::commands::undo::begin_undo_step(&db, "set").unwrap();
+        set_budget(&db, "food", 300.0, model::BudgetPeriod::Monthly).unwrap();
+        crate::commands::undo::begin_undo_step(&db, "set again").unwrap();
+        set_budget(&db, "food", 100.0, model::BudgetPeriod::Yearly).unwrap();
+
+        crate::commands::undo::command_undo(&db).unwrap();
+        assert_eq!(
+            list_budgets(&db).unwrap().rows,
+            vec![vec!["food", "300.00", "monthly"]]
+        );
+        crate::commands::undo::command_undo(&db).unwrap();
+        assert_eq!(list_budgets(&db).unwrap().rows.len(), 0);
+    }
+}
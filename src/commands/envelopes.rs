@@ -0,0 +1,465 @@
+use crate::database::{model, NewOrExisting, QueryFilters, StingyDatabase};
+use crate::output::format::ToOutputFormat;
+use crate::TimeAggregation;
+use anyhow::{anyhow, bail, Result};
+use chrono::{Datelike, Days, Months, NaiveDate};
+use std::collections::HashMap;
+
+#[derive(Debug)]
+pub struct ListEnvelopesResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+fn get_envelope(db: &Box<dyn StingyDatabase>, tag: &str) -> Result<model::Envelope> {
+    let envelopes: Vec<model::Envelope> = db.get_all()?;
+    envelopes
+        .into_iter()
+        .find(|e| e.tag == tag)
+        .ok_or(anyhow!("there is no envelope for '{tag}'."))
+}
+
+fn month_name(month: NaiveDate) -> String {
+    month.format("%Y/%m").to_string()
+}
+
+pub fn create_envelope(
+    db: &Box<dyn StingyDatabase>,
+    tag: &str,
+    monthly_amount: f64,
+    rollover: bool,
+) -> Result<model::Envelope> {
+    if tag.is_empty() {
+        bail!("the tag of an envelope can't be empty.");
+    }
+    if monthly_amount < 0.0 {
+        bail!("the monthly amount of an envelope can't be negative.");
+    }
+    let envelope = model::Envelope {
+        id: None,
+        tag: tag.to_string(),
+        monthly_amount,
+        rollover,
+    };
+    match db.insert(envelope)? {
+        NewOrExisting::New(envelope) => Ok(envelope),
+        NewOrExisting::Existing => bail!("there is already an envelope for '{tag}'."),
+    }
+}
+
+pub fn delete_envelope(db: &Box<dyn StingyDatabase>, tag: &str) -> Result<usize> {
+    let envelope = get_envelope(db, tag)?;
+    let envelope_id = envelope.id.unwrap();
+    // Deleting the envelope would also delete its allocations and moves, but we delete them first
+    // so that undoing re-creates the envelope before them.
+    let allocations: Vec<model::EnvelopeAllocation> = db.get_all()?;
+    for allocation in allocations {
+        if allocation.envelope_id == envelope_id {
+            db.delete(allocation)?;
+        }
+    }
+    let moves: Vec<model::EnvelopeMove> = db.get_all()?;
+    for envelope_move in moves {
+        if envelope_move.from_envelope_id == envelope_id
+            || envelope_move.to_envelope_id == envelope_id
+        {
+            db.delete(envelope_move)?;
+        }
+    }
+    db.delete(envelope)
+}
+
+/// Allocate funds to the envelope for `tag` in a month, or to all envelopes if `tag` is `None`.
+/// Unless given, the amount is the envelope's monthly amount. An allocation replaces any previous
+/// allocation to the same envelope in that month.
+///
+/// Returns the allocations made.
+pub fn allocate(
+    db: &Box<dyn StingyDatabase>,
+    tag: Option<&str>,
+    amount: Option<f64>,
+    month: NaiveDate,
+) -> Result<Vec<(String, f64)>> {
+    let month = month.with_day(1).unwrap();
+    let envelopes = match tag {
+        Some(tag) => vec![get_envelope(db, tag)?],
+        None if amount.is_some() => bail!("an amount can only be allocated to one envelope."),
+        None => db.get_all()?,
+    };
+    if amount.is_some_and(|a| a < 0.0) {
+        bail!("the amount allocated can't be negative.");
+    }
+    let existing: Vec<model::EnvelopeAllocation> = db.get_all()?;
+    let mut allocations = vec![];
+    for envelope in envelopes {
+        let mut allocation = model::EnvelopeAllocation {
+            id: None,
+            envelope_id: envelope.id.unwrap(),
+            month,
+            amount: amount.unwrap_or(envelope.monthly_amount),
+        };
+        if let NewOrExisting::Existing = db.insert(allocation.clone())? {
+            allocation.id = existing
+                .iter()
+                .find(|a| a.envelope_id == allocation.envelope_id && a.month == month)
+                .unwrap()
+                .id;
+            db.update(&allocation)?;
+        }
+        allocations.push((envelope.tag, allocation.amount));
+    }
+    Ok(allocations)
+}
+
+/// Move funds from one envelope to another in a month, for example to cover overspending.
+pub fn move_funds(
+    db: &Box<dyn StingyDatabase>,
+    from_tag: &str,
+    to_tag: &str,
+    amount: f64,
+    month: NaiveDate,
+) -> Result<model::EnvelopeMove> {
+    if amount <= 0.0 {
+        bail!("the amount moved must be positive.");
+    }
+    if from_tag == to_tag {
+        bail!("can't move funds from an envelope to itself.");
+    }
+    let envelope_move = model::EnvelopeMove {
+        id: None,
+        from_envelope_id: get_envelope(db, from_tag)?.id.unwrap(),
+        to_envelope_id: get_envelope(db, to_tag)?.id.unwrap(),
+        month: month.with_day(1).unwrap(),
+        amount,
+    };
+    match db.insert(envelope_move)? {
+        NewOrExisting::New(envelope_move) => Ok(envelope_move),
+        NewOrExisting::Existing => unreachable!(),
+    }
+}
+
+/// The debits of the transactions with a tag in each month from `from` to `to`, keyed by the first
+/// day of the month.
+fn monthly_debits(
+    db: &Box<dyn StingyDatabase>,
+    tag: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<HashMap<NaiveDate, f64>> {
+    let filters = QueryFilters {
+        tags: vec![tag.to_string()],
+        date_from: Some(from),
+        date_to: Some(to + Months::new(1) - Days::new(1)),
+        ..Default::default()
+    };
+    let mut debits = HashMap::new();
+    for row in db.query_by_time(filters, &TimeAggregation::Month)?.rows {
+        *debits
+            .entry(row.aggregation_window_end.with_day(1).unwrap())
+            .or_insert(0.0) += row.debit_amount;
+    }
+    Ok(debits)
+}
+
+/// The balances of all envelopes in a month, from their allocations, moves and spending in that
+/// month and all previous ones.
+pub fn list_envelopes(
+    db: &Box<dyn StingyDatabase>,
+    month: NaiveDate,
+) -> Result<ListEnvelopesResult> {
+    let month = month.with_day(1).unwrap();
+    let mut envelopes: Vec<model::Envelope> = db.get_all()?;
+    envelopes.sort_by(|a, b| a.tag.cmp(&b.tag));
+    let allocations: Vec<model::EnvelopeAllocation> = db.get_all()?;
+    let moves: Vec<model::EnvelopeMove> = db.get_all()?;
+
+    let columns = vec![
+        "Tag".to_string(),
+        "Rolls Over".to_string(),
+        "Carried Over".to_string(),
+        "Allocated".to_string(),
+        "Moved".to_string(),
+        "Spent".to_string(),
+        "Balance".to_string(),
+    ];
+    let mut rows = vec![];
+    for envelope in envelopes {
+        let id = envelope.id;
+        let allocated_in = |m: NaiveDate| -> f64 {
+            allocations
+                .iter()
+                .filter(|a| Some(a.envelope_id) == id && a.month == m)
+                .fold(0.0, |total, a| total + a.amount)
+        };
+        let moved_in = |m: NaiveDate| -> f64 {
+            moves
+                .iter()
+                .filter(|mv| mv.month == m)
+                .fold(0.0, |total, mv| {
+                    if Some(mv.to_envelope_id) == id {
+                        total + mv.amount
+                    } else if Some(mv.from_envelope_id) == id {
+                        total - mv.amount
+                    } else {
+                        total
+                    }
+                })
+        };
+        // The envelope starts with its first allocation or move.
+        let first_month = allocations
+            .iter()
+            .filter(|a| Some(a.envelope_id) == id)
+            .map(|a| a.month)
+            .chain(
+                moves
+                    .iter()
+                    .filter(|mv| Some(mv.from_envelope_id) == id || Some(mv.to_envelope_id) == id)
+                    .map(|mv| mv.month),
+            )
+            .min()
+            .unwrap_or(month)
+            .min(month);
+        let debits = monthly_debits(db, &envelope.tag, first_month, month)?;
+
+        let mut m = first_month;
+        let mut carried_over = 0.0;
+        loop {
+            let (allocated, moved) = (allocated_in(m), moved_in(m));
+            let spent = debits.get(&m).copied().unwrap_or(0.0);
+            // Round to cents, so that amounts that add up to zero aren't shown as negative (adding
+            // 0.0 turns -0.0 into 0.0).
+            let balance =
+                ((carried_over + allocated + moved - spent) * 100.0).round() / 100.0 + 0.0;
+            if m == month {
+                rows.push(vec![
+                    envelope.tag.to_output_format(),
+                    if envelope.rollover { "yes" } else { "no" }.to_string(),
+                    carried_over.to_output_format(),
+                    allocated.to_output_format(),
+                    moved.to_output_format(),
+                    spent.to_output_format(),
+                    balance.to_output_format(),
+                ]);
+                break;
+            }
+            // Overspending is always carried over, but unspent funds only if the envelope rolls
+            // over.
+            carried_over = if envelope.rollover {
+                balance
+            } else {
+                balance.min(0.0)
+            };
+            m = m + Months::new(1);
+        }
+    }
+    Ok(ListEnvelopesResult { columns, rows })
+}
+
+/// The allocations and moves of all envelopes, or of the envelope for `tag`, by month.
+pub fn envelope_history(
+    db: &Box<dyn StingyDatabase>,
+    tag: Option<&str>,
+) -> Result<ListEnvelopesResult> {
+    let envelopes: Vec<model::Envelope> = db.get_all()?;
+    let tags: HashMap<_, _> = envelopes
+        .iter()
+        .map(|e| (e.id.unwrap(), e.tag.as_str()))
+        .collect();
+    let envelope_id = tag
+        .map(|tag| get_envelope(db, tag))
+        .transpose()?
+        .map(|e| e.id.unwrap());
+    let allocations: Vec<model::EnvelopeAllocation> = db.get_all()?;
+    let moves: Vec<model::EnvelopeMove> = db.get_all()?;
+
+    // (month, order within the month, row), so that allocations come before moves.
+    let mut history = vec![];
+    for allocation in allocations {
+        if envelope_id.is_some_and(|id| id != allocation.envelope_id) {
+            continue;
+        }
+        history.push((
+            allocation.month,
+            (0, allocation.id),
+            vec![
+                month_name(allocation.month),
+                "allocation".to_string(),
+                tags[&allocation.envelope_id].to_string(),
+                allocation.amount.to_output_format(),
+            ],
+        ));
+    }
+    for envelope_move in moves {
+        if envelope_id.is_some_and(|id| {
+            id != envelope_move.from_envelope_id && id != envelope_move.to_envelope_id
+        }) {
+            continue;
+        }
+        history.push((
+            envelope_move.month,
+            (1, envelope_move.id),
+            vec![
+                month_name(envelope_move.month),
+                "move".to_string(),
+                format!(
+                    "{} → {}",
+                    tags[&envelope_move.from_envelope_id], tags[&envelope_move.to_envelope_id]
+                ),
+                envelope_move.amount.to_output_format(),
+            ],
+        ));
+    }
+    history.sort_by_key(|(month, order, _)| (*month, *order));
+
+    let columns = vec![
+        "Month".to_string(),
+        "Type".to_string(),
+        "Envelope(s)".to_string(),
+        "Amount".to_string(),
+    ];
+    let rows = history.into_iter().map(|(_, _, row)| row).collect();
+    Ok(ListEnvelopesResult { columns, rows })
+}
+
+#[cfg(test)]
+mod envelopes_tests {
+    use super::*;
+    use crate::commands::tags::{add_tag_rule, TagRuleAttributes};
+    use crate::database::open_stingy_testing_database;
+
+    fn february() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2021, 02, 01).unwrap()
+    }
+
+    fn march() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2021, 03, 01).unwrap()
+    }
+
+    fn setup(db: &Box<dyn StingyDatabase>) {
+        db.insert_test_data();
+        for (tag, description) in [("food", "FOOD ORDER"), ("coffee", "COFFEE")] {
+            add_tag_rule(
+                db,
+                tag,
+                &TagRuleAttributes {
+                    description_contains: Some(description.to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        }
+        create_envelope(db, "food", 30.0, true).unwrap();
+        create_envelope(db, "coffee", 5.0, false).unwrap();
+    }
+
+    #[test]
+    fn create_checks_envelopes() {
+        let db = open_stingy_testing_database();
+        setup(&db);
+        assert!(create_envelope(&db, "food", 10.0, true).is_err());
+        assert!(create_envelope(&db, "", 10.0, true).is_err());
+        assert!(create_envelope(&db, "pub", -10.0, true).is_err());
+    }
+
+    #[test]
+    fn rollover_and_overspending() {
+        let db = open_stingy_testing_database();
+        setup(&db);
+        allocate(&db, None, None, february()).unwrap();
+        allocate(&db, None, None, march()).unwrap();
+
+        let result = list_envelopes(&db, february()).unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec!["coffee", "no", "0.00", "5.00", "0.00", "3.74", "1.26"],
+                vec!["food", "yes", "0.00", "30.00", "0.00", "32.50", "-2.50"],
+            ]
+        );
+
+        // Coffee's unspent funds don't roll over, but food's overspending does.
+        let result = list_envelopes(&db, march()).unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec!["coffee", "no", "0.00", "5.00", "0.00", "2.99", "2.01"],
+                vec!["food", "yes", "-2.50", "30.00", "0.00", "25.15", "2.35"],
+            ]
+        );
+    }
+
+    #[test]
+    fn move_covers_overspending() {
+        let db = open_stingy_testing_database();
+        setup(&db);
+        allocate(&db, None, None, february()).unwrap();
+        move_funds(&db, "coffee", "food", 1.26, february()).unwrap();
+        assert!(move_funds(&db, "food", "food", 1.0, february()).is_err());
+        assert!(move_funds(&db, "food", "coffee", 0.0, february()).is_err());
+        assert!(move_funds(&db, "food", "pub", 1.0, february()).is_err());
+
+        let result = list_envelopes(&db, march()).unwrap();
+        assert_eq!(
+            result.rows,
+            vec![
+                vec!["coffee", "no", "0.00", "0.00", "0.00", "2.99", "-2.99"],
+                vec!["food", "yes", "-1.24", "0.00", "0.00", "25.15", "-26.39"],
+            ]
+        );
+    }
+
+    #[test]
+    fn allocation_replaces_previous_one() {
+        let db = open_stingy_testing_database();
+        setup(&db);
+        allocate(&db, None, None, february()).unwrap();
+        assert_eq!(
+            allocate(&db, Some("food"), Some(50.0), february()).unwrap(),
+            vec![("food".to_string(), 50.0)]
+        );
+        assert!(allocate(&db, None, Some(50.0), february()).is_err());
+
+        let result = list_envelopes(&db, february()).unwrap();
+        assert_eq!(result.rows[1][3], "50.00");
+    }
+
+    #[test]
+    fn history() {
+        let db = open_stingy_testing_database();
+        setup(&db);
+        allocate(&db, None, None, march()).unwrap();
+        allocate(&db, Some("food"), None, february()).unwrap();
+        move_funds(&db, "coffee", "food", 1.0, march()).unwrap();
+
+        let result = envelope_history(&db, None).unwrap();
+        assert_eq!(
+            result.columns,
+            vec!["Month", "Type", "Envelope(s)", "Amount"]
+        );
+        assert_eq!(
+            result.rows,
+            vec![
+                vec!["2021/02", "allocation", "food", "30.00"],
+                vec!["2021/03", "allocation", "food", "30.00"],
+                vec!["2021/03", "allocation", "coffee", "5.00"],
+                vec!["2021/03", "move", "coffee → food", "1.00"],
+            ]
+        );
+        assert_eq!(envelope_history(&db, Some("coffee")).unwrap().rows.len(), 2);
+    }
+
+    #[test]
+    fn delete_and_undo() {
+        let db = open_stingy_testing_database();
+        setup(&db);
+        allocate(&db, None, None, february()).unwrap();
+        move_funds(&db, "coffee", "food", 1.0, february()).unwrap();
+
+        crate::commands::undo::begin_undo_step(&db, "delete").unwrap();
+        assert_eq!(delete_envelope(&db, "food").unwrap(), 1);
+        assert_eq!(envelope_history(&db, None).unwrap().rows.len(), 1);
+
+        crate::commands::undo::command_undo(&db).unwrap();
+        assert_eq!(envelope_history(&db, None).unwrap().rows.len(), 3);
+    }
+}
@@ -0,0 +1,241 @@
+use crate::commands::recurring::{normalize_description, nth_occurrence};
+use crate::database::{CreditsRow, DebitsRow, ForecastEvent, ForecastRow, RecurringRow};
+use chrono::{Days, NaiveDate};
+use std::collections::{BTreeMap, HashSet};
+
+/// Where the forecast of an account starts: the date of its last transaction, and the balance
+/// after it.
+#[derive(Debug, Clone)]
+pub struct ForecastStart {
+    pub account_name: String,
+    pub date: NaiveDate,
+    pub balance: f64,
+}
+
+/// Credits rows as debits rows, so that recurring credits (like salaries) can be found the same
+/// way as recurring debits.
+pub fn credits_as_debits(rows: &[CreditsRow]) -> Vec<DebitsRow> {
+    rows.iter()
+        .map(|r| DebitsRow {
+            account_name: r.account_name.clone(),
+            transaction_id: r.transaction_id,
+            tags: r.tags.clone(),
+            debit_amount: r.credit_amount,
+            description: r.description.clone(),
+            posted_date: r.posted_date,
+            ..Default::default()
+        })
+        .collect()
+}
+
+/// The average amount a day, per account and tag, of the debits that aren't part of a recurring
+/// series, over `days` days. Debits with more than one tag count towards the first one only, so
+/// that they aren't projected more than once.
+pub fn daily_averages_per_tag(
+    rows: &[DebitsRow],
+    recurring: &[RecurringRow],
+    days: i64,
+) -> BTreeMap<(String, String), f64> {
+    let recurring: HashSet<_> = recurring
+        .iter()
+        .map(|r| {
+            (
+                r.account_name.as_str(),
+                normalize_description(&r.description),
+            )
+        })
+        .collect();
+    let mut averages = BTreeMap::new();
+    for row in rows {
+        if recurring.contains(&(
+            row.account_name.as_str(),
+            normalize_description(&row.description),
+        )) {
+            continue;
+        }
+        let tag = row.tags.first().cloned().unwrap_or_default();
+        *averages
+            .entry((row.account_name.clone(), tag))
+            .or_insert(0.0) += row.debit_amount / days as f64;
+    }
+    averages
+}
+
+/// Project the balance of each account day by day, until `until`, by adding up the average daily
+/// credits minus debits of each of its tags, and the recurring debits and credits on the days they
+/// are expected. Recurring debits of at least `large_outflow` are marked as large outflows.
+pub fn project(
+    starts: &[ForecastStart],
+    recurring_debits: &[RecurringRow],
+    recurring_credits: &[RecurringRow],
+    daily_debits: &BTreeMap<(String, String), f64>,
+    daily_credits: &BTreeMap<(String, String), f64>,
+    until: NaiveDate,
+    large_outflow: f64,
+) -> Vec<ForecastRow> {
+    let mut rows = vec![];
+    for start in starts {
+        let mut events: BTreeMap<NaiveDate, Vec<ForecastEvent>> = BTreeMap::new();
+        for (recurring, sign) in recurring_debits
+            .iter()
+            .map(|r| (r, -1.0))
+            .chain(recurring_credits.iter().map(|r| (r, 1.0)))
+            .filter(|(r, _)| r.account_name == start.account_name)
+        {
+            let mut n = 1;
+            loop {
+                let date = nth_occurrence(recurring.cadence, recurring.last_date, n);
+                if date > until {
+                    break;
+                }
+                if date > start.date {
+                    events.entry(date).or_default().push(ForecastEvent {
+                        description: recurring.description.clone(),
+                        amount: sign * recurring.last_amount,
+                        large_outflow: sign < 0.0 && recurring.last_amount >= large_outflow,
+                    });
+                }
+                n += 1;
+            }
+        }
+        let daily_amount = |averages: &BTreeMap<(String, String), f64>| -> f64 {
+            averages
+                .iter()
+                .filter(|((account_name, _), _)| *account_name == start.account_name)
+                .map(|(_, amount)| amount)
+                .sum()
+        };
+        let daily_amount = daily_amount(daily_credits) - daily_amount(daily_debits);
+
+        let mut balance = start.balance;
+        let mut date = start.date;
+        while date < until {
+            date = date + Days::new(1);
+            let events = events.remove(&date).unwrap_or_default();
+            balance += daily_amount + events.iter().map(|e| e.amount).sum::<f64>();
+            rows.push(ForecastRow {
+                account_name: start.account_name.clone(),
+                date,
+                events,
+                balance,
+            });
+        }
+    }
+    rows
+}
+
+/// The first day on which the projected balance of each account is below `floor`.
+pub fn first_below_floor(rows: &[ForecastRow], floor: f64) -> Vec<&ForecastRow> {
+    let mut accounts = HashSet::new();
+    rows.iter()
+        .filter(|r| r.balance < floor && accounts.insert(r.account_name.as_str()))
+        .collect()
+}
+
+#[cfg(test)]
+mod forecast_tests {
+    use super::*;
+    use crate::database::Cadence;
+
+    fn date(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2021, month, day).unwrap()
+    }
+
+    fn recurring(
+        description: &str,
+        cadence: Cadence,
+        last_date: NaiveDate,
+        amount: f64,
+    ) -> RecurringRow {
+        RecurringRow {
+            account_name: "000000 - 00000000".to_string(),
+            description: description.to_string(),
+            cadence,
+            occurrences: 3,
+            typical_amount: amount,
+            last_amount: amount,
+            last_date,
+            next_date: last_date,
+            annualized_amount: 0.0,
+            price_increase: None,
+        }
+    }
+
+    #[test]
+    fn averages_without_recurring() {
+        let debit = |description: &str, tag: &str, amount: f64| DebitsRow {
+            account_name: "000000 - 00000000".to_string(),
+            description: description.to_string(),
+            tags: vec![tag.to_string()],
+            debit_amount: amount,
+            ..Default::default()
+        };
+        let rows = vec![
+            debit("GROCERIES", "food", 30.0),
+            debit("RESTAURANT", "food", 60.0),
+            debit("RENT 1", "home", 1000.0),
+        ];
+        let averages = daily_averages_per_tag(
+            &rows,
+            &[recurring("RENT 2", Cadence::Monthly, date(3, 1), 1000.0)],
+            30,
+        );
+        assert_eq!(
+            averages,
+            BTreeMap::from([(("000000 - 00000000".to_string(), "food".to_string()), 3.0)])
+        );
+    }
+
+    #[test]
+    fn projection() {
+        let starts = vec![ForecastStart {
+            account_name: "000000 - 00000000".to_string(),
+            date: date(3, 3),
+            balance: 1500.0,
+        }];
+        let rows = project(
+            &starts,
+            &[
+                recurring("RENT", Cadence::Monthly, date(3, 1), 1000.0),
+                recurring("NETFLIX", Cadence::Monthly, date(2, 28), 10.0),
+            ],
+            &[recurring("SALARY", Cadence::Monthly, date(2, 25), 900.0)],
+            &BTreeMap::from([(("000000 - 00000000".to_string(), "food".to_string()), 5.0)]),
+            &BTreeMap::new(),
+            date(4, 30),
+            500.0,
+        );
+        assert_eq!(rows.len(), 58);
+        assert_eq!(rows[0].date, date(3, 4));
+        assert_eq!(rows[0].balance, 1495.0);
+
+        let events: Vec<_> = rows
+            .iter()
+            .flat_map(|r| {
+                r.events
+                    .iter()
+                    .map(move |e| (r.date, e.description.as_str(), e.large_outflow))
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (date(3, 25), "SALARY", false),
+                (date(3, 28), "NETFLIX", false),
+                (date(4, 1), "RENT", true),
+                (date(4, 25), "SALARY", false),
+                (date(4, 28), "NETFLIX", false),
+            ]
+        );
+        // 1500 - 58 days of food + 2 salaries - 2 subscriptions - rent.
+        assert!(
+            (rows.last().unwrap().balance - (1500.0 - 290.0 + 1800.0 - 20.0 - 1000.0)).abs() < 1e-9
+        );
+
+        // The rent takes the balance below the floor.
+        let below = first_below_floor(&rows, 1300.0);
+        assert_eq!(below.len(), 1);
+        assert_eq!(below[0].date, date(4, 1));
+        assert_eq!(below[0].balance, 1245.0);
+    }
+}
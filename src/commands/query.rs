@@ -1,11 +1,133 @@
+use crate::commands::{anomalies, forecast, recurring};
 use crate::database;
 use crate::database::model;
+use crate::output::format::ToOutputFormat;
 use crate::output::{chart, table, Output, OutputForTesting};
-use crate::PreparedQuery;
-use anyhow::Result;
-use chrono::NaiveDate;
+use crate::{DetailQueryArgs, PreparedQuery, SortBy};
+use anyhow::{anyhow, bail, Result};
+use chrono::{Datelike, Days, Months, NaiveDate};
+use std::collections::BTreeMap;
 use std::io::Write;
 
+fn model_transaction_types(
+    transaction_type: &crate::TransactionType,
+) -> Vec<model::TransactionType> {
+    match transaction_type {
+        crate::TransactionType::debit => vec![
+            model::TransactionType::Debit,
+            model::TransactionType::DirectDebit,
+        ],
+        crate::TransactionType::credit => vec![model::TransactionType::Credit],
+        crate::TransactionType::direct_debit => vec![model::TransactionType::DirectDebit],
+    }
+}
+
+/// The period that a compare query compares `from`-`to` against: either a period in the same
+/// format as `--period`, or "last-year" for the same period a year earlier.
+fn compare_against_period(
+    against: &str,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> Result<(Option<NaiveDate>, Option<NaiveDate>)> {
+    if against != "last-year" {
+        return crate::parse_period(Some(against));
+    }
+    let a_year_earlier = |date: NaiveDate| {
+        date.checked_sub_months(Months::new(12)).ok_or(anyhow!(
+            "the period has no start or end to compare against."
+        ))
+    };
+    Ok((Some(a_year_earlier(from)?), Some(a_year_earlier(to)?)))
+}
+
+/// Put together the by-tag rows of two periods, sorted by their largest change.
+fn compare_by_tag_rows(
+    rows: Vec<database::ByTagRow>,
+    rows_against: Vec<database::ByTagRow>,
+) -> Vec<database::CompareRow> {
+    let mut compare_rows = BTreeMap::new();
+    for (row, is_against) in rows
+        .into_iter()
+        .map(|r| (r, false))
+        .chain(rows_against.into_iter().map(|r| (r, true)))
+    {
+        let compare_row = compare_rows
+            .entry(row.tag.clone())
+            .or_insert(database::CompareRow {
+                tag: row.tag,
+                ..Default::default()
+            });
+        if is_against {
+            compare_row.debit_amount_against = row.tag_debit;
+            compare_row.credit_amount_against = row.tag_credit;
+        } else {
+            compare_row.debit_amount = row.tag_debit;
+            compare_row.credit_amount = row.tag_credit;
+        }
+    }
+    let mut compare_rows: Vec<_> = compare_rows.into_values().collect();
+    compare_rows.sort_by(|a, b| b.largest_change().total_cmp(&a.largest_change()));
+    compare_rows
+}
+
+/// How many budget periods (months or years) there are from `from` to `to`, counting partial ones
+/// by their share of days.
+fn budget_periods_in(period: model::BudgetPeriod, from: NaiveDate, to: NaiveDate) -> f64 {
+    let mut periods = 0.0;
+    let mut start = from;
+    while start <= to {
+        let (period_start, period_end) = match period {
+            model::BudgetPeriod::Monthly => {
+                let period_start = start.with_day(1).unwrap();
+                (period_start, period_start + Months::new(1) - Days::new(1))
+            }
+            model::BudgetPeriod::Yearly => (
+                start.with_ordinal(1).unwrap(),
+                NaiveDate::from_ymd_opt(start.year(), 12, 31).unwrap(),
+            ),
+        };
+        let end = period_end.min(to);
+        periods += ((end - start).num_days() + 1) as f64
+            / ((period_end - period_start).num_days() + 1) as f64;
+        start = period_end + Days::new(1);
+    }
+    periods
+}
+
+/// The period from `from` to `to`, or the current month if there is none. Open-ended periods end
+/// today, and periods without a start are not supported.
+fn period_or_current_month(
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    today: NaiveDate,
+) -> Option<(NaiveDate, NaiveDate)> {
+    match (from, to) {
+        (None, None) => {
+            let from = today.with_day(1).unwrap();
+            Some((from, from + Months::new(1) - Days::new(1)))
+        }
+        (Some(from), Some(to)) if from != NaiveDate::MIN => {
+            Some((from, if to == NaiveDate::MAX { today } else { to }))
+        }
+        _ => None,
+    }
+}
+
+/// The order and page of the rows of a detail query. Without --sort or --asc, this is the query's
+/// own order: by `default`, and ascending if `default_ascending`.
+fn query_order(
+    detail: &DetailQueryArgs,
+    default: SortBy,
+    default_ascending: bool,
+) -> database::QueryOrder {
+    database::QueryOrder {
+        sort: detail.sort.unwrap_or(default),
+        ascending: detail.asc || (detail.sort.is_none() && default_ascending),
+        limit: detail.limit,
+        offset: detail.offset,
+    }
+}
+
 pub fn command_query<W>(
     db: &Box<dyn database::StingyDatabase>,
     writer: &mut W,
@@ -27,7 +149,7 @@ where
         tags: tags.to_vec(),
         not_tags: not_tags.to_vec(),
         description_contains: description_contains.map(|dc| dc.to_string()),
-        amount_min: amount_min,
+        amount_min: database::amount_range_min(amount_min),
         amount_max: amount_max,
         date_from: from,
         date_to: to,
@@ -37,24 +159,41 @@ where
     match query {
         PreparedQuery::Debits {
             show_transaction_id,
+            detail,
         } => {
-            let query_result = db.query_debits(filters)?;
+            let order = query_order(detail, SortBy::amount, false);
+            let query_result = db.query_debits(filters, &order)?;
             if query_result.rows.len() == 0 {
                 return Ok(None);
             }
             let mut to = table::TableOutput::new(writer, None);
-            to.render_debits(&query_result.rows, *show_transaction_id)
+            to.render_debits(&query_result.rows, *show_transaction_id, &order)
                 .and_then(|o| Ok(Some(o)))
         }
         PreparedQuery::Credits {
             show_transaction_id,
+            detail,
+        } => {
+            let order = query_order(detail, SortBy::amount, false);
+            let query_result = db.query_credits(filters, &order)?;
+            if query_result.rows.len() == 0 {
+                return Ok(None);
+            }
+            let mut to = table::TableOutput::new(writer, None);
+            to.render_credits(&query_result.rows, *show_transaction_id, &order)
+                .and_then(|o| Ok(Some(o)))
+        }
+        PreparedQuery::Transactions {
+            show_transaction_id,
+            detail,
         } => {
-            let query_result = db.query_credits(filters)?;
+            let order = query_order(detail, SortBy::date, true);
+            let query_result = db.query_transactions(filters, &order)?;
             if query_result.rows.len() == 0 {
                 return Ok(None);
             }
             let mut to = table::TableOutput::new(writer, None);
-            to.render_credits(&query_result.rows, *show_transaction_id)
+            to.render_transactions(&query_result.rows, *show_transaction_id, &order)
                 .and_then(|o| Ok(Some(o)))
         }
         PreparedQuery::ByTime { aggregate, table } => {
@@ -80,29 +219,279 @@ where
         }
         PreparedQuery::ByTag {
             transaction_type,
+            depth,
+            tree,
             table,
         } => {
             filters.transaction_types = match transaction_type {
-                Some(crate::TransactionType::debit) => vec![
-                    model::TransactionType::Debit,
-                    model::TransactionType::DirectDebit,
-                ],
-                Some(crate::TransactionType::credit) => {
-                    vec![model::TransactionType::Credit]
-                }
+                Some(transaction_type) => model_transaction_types(transaction_type),
                 None => Vec::new(),
             };
-            let query_result = db.query_by_tag(filters)?;
+            let depth = depth.map(|d| d as usize);
+            if *table && !*tree {
+                let query_result = db.query_by_tag(filters, depth)?;
+                if query_result.rows.len() == 0 {
+                    return Ok(None);
+                }
+                let mut to = table::TableOutput::new(writer, None);
+                return to
+                    .render_by_tag(&query_result.rows)
+                    .and_then(|o| Ok(Some(o)));
+            }
+            let query_result = db.query_by_tag_tree(filters, depth)?;
+            if query_result.rows.len() == 0 {
+                return Ok(None);
+            }
+            if *tree {
+                let mut to = table::TableOutput::new(writer, None);
+                to.render_by_tag_tree(&query_result.rows)
+                    .and_then(|o| Ok(Some(o)))
+            } else {
+                let mut co = chart::ChartOutput::new(writer, None);
+                co.render_by_tag_tree(&query_result.rows)
+                    .and_then(|o| Ok(Some(o)))
+            }
+        }
+        PreparedQuery::Compare {
+            against,
+            depth,
+            table,
+        } => {
+            let (Some(from), Some(to)) = (from, to) else {
+                bail!("use --period to choose the period to compare.");
+            };
+            let mut filters_against = filters.clone();
+            (filters_against.date_from, filters_against.date_to) =
+                compare_against_period(against, from, to)?;
+            let depth = depth.map(|d| d as usize);
+            let rows = compare_by_tag_rows(
+                db.query_by_tag(filters.clone(), depth)?.rows,
+                db.query_by_tag(filters_against.clone(), depth)?.rows,
+            );
+            if rows.len() == 0 {
+                return Ok(None);
+            }
+            // Adding up the tags would count transactions with more than one tag more than once,
+            // so the overall amounts come from a by-time query instead.
+            let mut overall = database::CompareRow {
+                tag: "Total".to_string(),
+                ..Default::default()
+            };
+            for row in db
+                .query_by_time(filters, &crate::TimeAggregation::Year)?
+                .rows
+            {
+                overall.debit_amount += row.debit_amount;
+                overall.credit_amount += row.credit_amount;
+            }
+            for row in db
+                .query_by_time(filters_against, &crate::TimeAggregation::Year)?
+                .rows
+            {
+                overall.debit_amount_against += row.debit_amount;
+                overall.credit_amount_against += row.credit_amount;
+            }
+            if *table {
+                let mut to = table::TableOutput::new(writer, None);
+                to.render_compare(&rows, &overall).and_then(|o| Ok(Some(o)))
+            } else {
+                let mut co = chart::ChartOutput::new(writer, None);
+                co.render_compare(&rows, &overall).and_then(|o| Ok(Some(o)))
+            }
+        }
+        PreparedQuery::Recurring { tolerance } => {
+            let query_result = db.query_debits(filters, &database::QueryOrder::default())?;
+            let rows = recurring::find_recurring(&query_result.rows, *tolerance);
+            if rows.len() == 0 {
+                return Ok(None);
+            }
+            let mut to = table::TableOutput::new(writer, None);
+            to.render_recurring(&rows).and_then(|o| Ok(Some(o)))
+        }
+        PreparedQuery::Forecast {
+            until,
+            floor,
+            large_outflow,
+            table,
+        } => {
+            let (month, year) = crate::parse_month(until)?;
+            let until = crate::first_day_of_month(year, month)? + Months::new(1) - Days::new(1);
+            let order = database::QueryOrder::default();
+            let debits = db.query_debits(filters.clone(), &order)?.rows;
+            let credits =
+                forecast::credits_as_debits(&db.query_credits(filters.clone(), &order)?.rows);
+            let history = db
+                .query_by_time(filters, &crate::TimeAggregation::Month)?
+                .rows;
+            let dates = debits.iter().chain(credits.iter()).map(|r| r.posted_date);
+            let (Some(first), Some(last)) = (dates.clone().min(), dates.max()) else {
+                return Ok(None);
+            };
+            let days = (last - first).num_days() + 1;
+
+            // Each account starts from its last balance, on the day of its last transaction.
+            let mut starts: BTreeMap<&str, forecast::ForecastStart> = BTreeMap::new();
+            for row in &history {
+                let start = starts
+                    .entry(&row.account_name)
+                    .or_insert(forecast::ForecastStart {
+                        account_name: row.account_name.clone(),
+                        date: NaiveDate::MIN,
+                        balance: row.balance,
+                    });
+                if row.aggregation_window_end > start.date {
+                    (start.date, start.balance) = (row.aggregation_window_end, row.balance);
+                }
+            }
+            for start in starts.values_mut() {
+                start.date = debits
+                    .iter()
+                    .chain(credits.iter())
+                    .filter(|r| r.account_name == start.account_name)
+                    .map(|r| r.posted_date)
+                    .max()
+                    .unwrap_or(start.date);
+            }
+
+            let recurring_debits = recurring::find_recurring(&debits, recurring::DEFAULT_TOLERANCE);
+            let recurring_credits =
+                recurring::find_recurring(&credits, recurring::DEFAULT_TOLERANCE);
+            let rows = forecast::project(
+                &starts.into_values().collect::<Vec<_>>(),
+                &recurring_debits,
+                &recurring_credits,
+                &forecast::daily_averages_per_tag(&debits, &recurring_debits, days),
+                &forecast::daily_averages_per_tag(&credits, &recurring_credits, days),
+                until,
+                *large_outflow,
+            );
+            if rows.len() == 0 {
+                return Ok(None);
+            }
+            let output = if *table {
+                let mut to = table::TableOutput::new(&mut *writer, None);
+                to.render_forecast(&history, &rows, *floor)?
+            } else {
+                let mut co = chart::ChartOutput::new(&mut *writer, None);
+                co.render_forecast(&history, &rows, *floor)?
+            };
+            for row in forecast::first_below_floor(&rows, *floor) {
+                writeln!(
+                    writer,
+                    "{} The balance of {} is projected to drop below {} on {} ({}).",
+                    crate::WARN,
+                    row.account_name,
+                    floor.to_output_format(),
+                    row.date.to_output_format(),
+                    row.balance.to_output_format()
+                )?;
+            }
+            Ok(Some(output))
+        }
+        PreparedQuery::Anomalies { history, threshold } => {
+            let Some((from, to)) = period_or_current_month(from, to, crate::now().date_naive())
+            else {
+                bail!("anomalies can only be found in a period with a start.");
+            };
+            (filters.date_from, filters.date_to) = (Some(from), Some(to));
+            filters.transaction_types = model_transaction_types(&crate::TransactionType::debit);
+            // The history is made of whole months, ending before the month of the period.
+            let history_to = from.with_day(1).unwrap();
+            let history_from = history_to - Months::new(*history);
+            let history_months: Vec<_> = (1..=*history)
+                .map(|i| history_from + Months::new(i) - Days::new(1))
+                .collect();
+            let mut history_filters = filters.clone();
+            (history_filters.date_from, history_filters.date_to) =
+                (Some(history_from), Some(history_to - Days::new(1)));
+
+            let mut rows = anomalies::tag_anomalies(
+                &db.query_by_tag_time(
+                    history_filters.clone(),
+                    &crate::TimeAggregation::Month,
+                    None,
+                )?
+                .rows,
+                &history_months,
+                &db.query_by_tag(filters.clone(), None)?.rows,
+                budget_periods_in(model::BudgetPeriod::Monthly, from, to),
+                *threshold,
+            );
+            rows.extend(anomalies::transaction_anomalies(
+                &db.query_debits(history_filters, &database::QueryOrder::default())?
+                    .rows,
+                &db.query_debits(filters, &database::QueryOrder::default())?
+                    .rows,
+                *threshold,
+            ));
+            if rows.len() == 0 {
+                return Ok(None);
+            }
+            rows.sort_by(|a, b| b.score.total_cmp(&a.score));
+            let mut to = table::TableOutput::new(writer, None);
+            to.render_anomalies(&rows).and_then(|o| Ok(Some(o)))
+        }
+        PreparedQuery::Budget {} => {
+            let today = crate::now().date_naive();
+            let Some((from, to)) = period_or_current_month(from, to, today) else {
+                bail!("budgets can only be compared against a period with a start.");
+            };
+            (filters.date_from, filters.date_to) = (Some(from), Some(to));
+            let mut budgets: Vec<model::Budget> = db.get_all()?;
+            if budgets.len() == 0 {
+                return Ok(None);
+            }
+            budgets.sort_by(|a, b| a.tag.cmp(&b.tag));
+            let mut rows = vec![];
+            for budget in budgets {
+                let mut budget_filters = filters.clone();
+                budget_filters.tags = vec![budget.tag.clone()];
+                let debit_amount = db
+                    .query_by_time(budget_filters, &crate::TimeAggregation::Year)?
+                    .rows
+                    .iter()
+                    .map(|r| r.debit_amount)
+                    .sum();
+                let expected_debit_amount = if today < from {
+                    0.0
+                } else {
+                    budget.amount * budget_periods_in(budget.period, from, today.min(to))
+                };
+                rows.push(database::BudgetRow {
+                    tag: budget.tag,
+                    period: budget.period,
+                    budget_amount: budget.amount * budget_periods_in(budget.period, from, to),
+                    debit_amount,
+                    expected_debit_amount,
+                });
+            }
+            let mut to = table::TableOutput::new(writer, None);
+            to.render_budget(&rows).and_then(|o| Ok(Some(o)))
+        }
+        PreparedQuery::ByTagTime {
+            aggregate,
+            transaction_type,
+            depth,
+            table,
+        } => {
+            // Debits and credits can't be added up, so only one of them is considered.
+            filters.transaction_types = model_transaction_types(
+                transaction_type
+                    .as_ref()
+                    .unwrap_or(&crate::TransactionType::debit),
+            );
+            let query_result =
+                db.query_by_tag_time(filters, aggregate, depth.map(|d| d as usize))?;
             if query_result.rows.len() == 0 {
                 return Ok(None);
             }
             if *table {
                 let mut to = table::TableOutput::new(writer, None);
-                to.render_by_tag(&query_result.rows)
+                to.render_by_tag_time(&query_result.rows, aggregate)
                     .and_then(|o| Ok(Some(o)))
             } else {
                 let mut co = chart::ChartOutput::new(writer, None);
-                co.render_by_tag(&query_result.rows)
+                co.render_by_tag_time(&query_result.rows, aggregate)
                     .and_then(|o| Ok(Some(o)))
             }
         }
@@ -125,6 +514,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec![],
@@ -164,6 +554,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: true,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec![],
@@ -200,25 +591,19 @@ mod debits_tests {
         crate::commands::tags::add_tag_rule(
             &db,
             "coffee",
-            None,
-            Some("coffee"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("coffee".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         crate::commands::tags::add_tag_rule(
             &db,
             "pub",
-            None,
-            Some("pub"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("pub".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         let output_for_testing = command_query(
@@ -226,6 +611,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec!["coffee".to_string(), "pub".to_string()],
             &vec![],
@@ -254,25 +640,19 @@ mod debits_tests {
         crate::commands::tags::add_tag_rule(
             &db,
             "coffee",
-            None,
-            Some("coffee"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("coffee".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         crate::commands::tags::add_tag_rule(
             &db,
             "coffee",
-            None,
-            Some("cof"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("cof".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         let output_for_testing = command_query(
@@ -280,6 +660,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec![],
@@ -307,13 +688,10 @@ mod debits_tests {
         crate::commands::tags::add_tag_rule(
             &db,
             "daily/coffee",
-            None,
-            Some("coffee"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("coffee".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         let output_for_testing = command_query(
@@ -321,6 +699,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec!["daily/".to_string()],
             &vec![],
@@ -353,6 +732,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec![],
@@ -383,6 +763,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec![],
@@ -415,6 +796,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec![],
@@ -438,6 +820,40 @@ mod debits_tests {
         }
     }
 
+    #[test]
+    fn amount_range_signed() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+
+        // Debits of more than 20, but none of the credits.
+        let output_for_testing = command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::ByTag {
+                transaction_type: None,
+                depth: None,
+                tree: false,
+                table: true,
+            },
+            &vec![],
+            &vec![],
+            None,
+            Some(f64::MIN),
+            Some(-20.0),
+            None,
+            None,
+            vec!["000000 - 00000000"],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Table((_, rows))) = output_for_testing {
+            assert_eq!(rows.len(), 1);
+            assert_eq!(rows[0][1], "83.63");
+            assert_eq!(rows[0][3], "0.00");
+        } else {
+            unimplemented!()
+        }
+    }
+
     #[test]
     fn from() {
         let db = open_stingy_testing_database();
@@ -448,6 +864,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec![],
@@ -478,6 +895,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec![],
@@ -510,6 +928,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec![],
@@ -547,6 +966,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec![],
@@ -574,6 +994,50 @@ mod debits_tests {
         }
     }
 
+    #[test]
+    fn sort_by_date_with_limit_and_offset() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+
+        let output_for_testing = command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::Debits {
+                show_transaction_id: false,
+                detail: crate::DetailQueryArgs {
+                    sort: Some(crate::SortBy::date),
+                    asc: true,
+                    limit: Some(2),
+                    offset: Some(1),
+                },
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            vec!["000000 - 00000000"],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Table((columns, rows))) = output_for_testing {
+            assert_eq!(columns[2], "Debit Amount");
+            assert_eq!(columns[4], "Date ↓");
+            // The cumulative columns count the skipped debit, in date order.
+            let rows: Vec<_> = rows.iter().map(|r| r[2..].to_vec()).collect();
+            assert_eq!(
+                rows,
+                vec![
+                    vec!["10.00", "FOOD ORDER 1", "2021/02/26", "13.74", "9.82"],
+                    vec!["22.50", "FOOD ORDER 2", "2021/02/26", "36.24", "25.89"],
+                ]
+            );
+        } else {
+            unimplemented!()
+        }
+    }
+
     #[test]
     fn date_format() {
         let db = open_stingy_testing_database();
@@ -584,6 +1048,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec![],
@@ -616,6 +1081,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec![],
@@ -642,13 +1108,10 @@ mod debits_tests {
         crate::commands::tags::add_tag_rule(
             &db,
             "daily/coffee",
-            None,
-            Some("coffee"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("coffee".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         let output_for_testing = command_query(
@@ -656,6 +1119,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec!["daily/cof".to_string()],
@@ -682,13 +1146,10 @@ mod debits_tests {
         crate::commands::tags::add_tag_rule(
             &db,
             "daily/coffee",
-            None,
-            Some("coffee"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("coffee".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         let output_for_testing = command_query(
@@ -696,6 +1157,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec!["daily/cof".to_string()],
             &vec!["daily/cof".to_string()],
@@ -718,25 +1180,19 @@ mod debits_tests {
         crate::commands::tags::add_tag_rule(
             &db,
             "drinks",
-            None,
-            Some("pub"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("pub".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         crate::commands::tags::add_tag_rule(
             &db,
             "fun",
-            None,
-            Some("pub"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("pub".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
 
@@ -745,6 +1201,7 @@ mod debits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Debits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec![],
@@ -783,6 +1240,7 @@ mod credits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Credits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec![],
@@ -822,6 +1280,7 @@ mod credits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Credits {
                 show_transaction_id: true,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
             &vec![],
@@ -854,7 +1313,7 @@ mod credits_tests {
     }
 
     #[test]
-    fn date_format() {
+    fn sort_by_description() {
         let db = open_stingy_testing_database();
         db.insert_test_data();
 
@@ -863,6 +1322,10 @@ mod credits_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::Credits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs {
+                    sort: Some(crate::SortBy::description),
+                    ..Default::default()
+                },
             },
             &vec![],
             &vec![],
@@ -871,51 +1334,91 @@ mod credits_tests {
             None,
             None,
             None,
-            vec!["000000 - 00000000"],
+            vec![],
         )
         .unwrap();
-        if let Some(OutputForTesting::Table((_, rows))) = output_for_testing {
-            assert_eq!(rows[0][4], "2021/02/25");
+        if let Some(OutputForTesting::Table((columns, rows))) = output_for_testing {
+            assert_eq!(columns[3], "Description ↑");
+            let rows: Vec<_> = rows
+                .iter()
+                .map(|r| (r[3].as_str(), r[4].as_str(), r[5].as_str()))
+                .collect();
+            assert_eq!(
+                rows,
+                vec![
+                    ("INTEREST PAYMENT", "2021/03/01", "1.00"),
+                    ("INTEREST PAYMENT", "2021/02/01", "2.00"),
+                    ("INSURANCE REPAYMENT", "2021/03/01", "102.00"),
+                    ("INCOMING TRANSFER", "2021/02/25", "1102.00"),
+                ]
+            );
         } else {
             unimplemented!()
         }
     }
 
     #[test]
-    fn not_tags() {
+    fn date_format() {
         let db = open_stingy_testing_database();
         db.insert_test_data();
 
-        crate::commands::tags::add_tag_rule(
-            &db,
-            "insurance",
-            None,
-            Some("insurance"),
-            None,
-            None,
-            None,
-            None,
-            None,
-        )
-        .unwrap();
         let output_for_testing = command_query(
             &db,
             &mut Cursor::new(vec![]),
             &PreparedQuery::Credits {
                 show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
             },
             &vec![],
-            &vec!["insur".to_string()],
+            &vec![],
             None,
             None,
             None,
             None,
             None,
-            vec![],
+            vec!["000000 - 00000000"],
         )
         .unwrap();
         if let Some(OutputForTesting::Table((_, rows))) = output_for_testing {
-            println!("{:#?}", rows);
+            assert_eq!(rows[0][4], "2021/02/25");
+        } else {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn not_tags() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+
+        crate::commands::tags::add_tag_rule(
+            &db,
+            "insurance",
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("insurance".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let output_for_testing = command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::Credits {
+                show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
+            },
+            &vec![],
+            &vec!["insur".to_string()],
+            None,
+            None,
+            None,
+            None,
+            None,
+            vec![],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Table((_, rows))) = output_for_testing {
+            println!("{:#?}", rows);
             assert_eq!(rows.len(), 3);
         } else {
             unimplemented!()
@@ -923,6 +1426,157 @@ mod credits_tests {
     }
 }
 
+#[cfg(test)]
+mod transactions_tests {
+    use super::*;
+    use crate::database::open_stingy_testing_database;
+    use std::io::Cursor;
+
+    #[test]
+    fn statement() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+
+        let output_for_testing = command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::Transactions {
+                show_transaction_id: false,
+                detail: crate::DetailQueryArgs::default(),
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            NaiveDate::from_ymd_opt(2021, 2, 26),
+            NaiveDate::from_ymd_opt(2021, 3, 1),
+            vec!["000000 - 00000000"],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Table((columns, rows))) = output_for_testing {
+            assert_eq!(
+                columns,
+                vec![
+                    "Account",
+                    "Date ↓",
+                    "Description",
+                    "Amount",
+                    "Balance",
+                    "Running Total",
+                    "Tag(s)"
+                ]
+            );
+            let rows: Vec<_> = rows.iter().map(|r| r[1..6].to_vec()).collect();
+            assert_eq!(
+                rows,
+                vec![
+                    vec!["2021/02/26", "FOOD ORDER 1", "-10.00", "9986.16", "-10.00"],
+                    vec!["2021/02/26", "FOOD ORDER 2", "-22.50", "9963.50", "-32.50"],
+                    vec!["2021/02/26", "GROCERIES", "-35.98", "9927.52", "-68.48"],
+                    vec!["2021/03/01", "GROCERIES", "-15.99", "9911.53", "-84.47"],
+                    vec!["2021/03/01", "PUB", "-16.00", "9895.53", "-100.47"],
+                    vec!["2021/03/01", "COFFEE", "-2.99", "9885.54", "-103.46"],
+                ]
+            );
+        } else {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn running_totals_per_account() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+
+        let output_for_testing = command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::Transactions {
+                show_transaction_id: true,
+                detail: crate::DetailQueryArgs::default(),
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            NaiveDate::from_ymd_opt(2021, 3, 1),
+            NaiveDate::from_ymd_opt(2021, 3, 1),
+            vec![],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Table((columns, rows))) = output_for_testing {
+            assert_eq!(columns[1], "ID");
+            let rows: Vec<_> = rows
+                .iter()
+                .map(|r| (r[0].as_str(), r[3].as_str(), r[4].as_str(), r[6].as_str()))
+                .collect();
+            assert_eq!(
+                rows,
+                vec![
+                    ("000000 - 00000000", "GROCERIES", "-15.99", "-15.99"),
+                    ("000000 - 00000000", "PUB", "-16.00", "-31.99"),
+                    ("000000 - 00000000", "COFFEE", "-2.99", "-34.98"),
+                    (
+                        "111111 - 11111111",
+                        "INSURANCE REPAYMENT",
+                        "100.00",
+                        "100.00"
+                    ),
+                    ("222222 - 22222222", "INTEREST PAYMENT", "1.00", "1.00"),
+                ]
+            );
+        } else {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn sort_by_amount_with_limit() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+
+        let output_for_testing = command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::Transactions {
+                show_transaction_id: false,
+                detail: crate::DetailQueryArgs {
+                    sort: Some(crate::SortBy::amount),
+                    limit: Some(3),
+                    ..Default::default()
+                },
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            vec!["000000 - 00000000"],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Table((columns, rows))) = output_for_testing {
+            assert_eq!(columns[1], "Date");
+            assert_eq!(columns[3], "Amount ↑");
+            // The running totals are still those of the statement, in date order.
+            let rows: Vec<_> = rows.iter().map(|r| r[2..6].to_vec()).collect();
+            assert_eq!(
+                rows,
+                vec![
+                    vec!["INCOMING TRANSFER", "1000.00", "10000.00", "1000.00"],
+                    vec!["COFFEE", "-2.99", "9885.54", "892.80"],
+                    vec!["COFFEE", "-3.74", "9996.16", "996.26"],
+                ]
+            );
+        } else {
+            unimplemented!()
+        }
+    }
+}
+
 #[cfg(test)]
 mod by_month_tests {
     use super::*;
@@ -977,13 +1631,10 @@ mod by_month_tests {
         crate::commands::tags::add_tag_rule(
             &db,
             "coffee",
-            None,
-            Some("coffee"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("coffee".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
 
@@ -1406,25 +2057,19 @@ mod by_month_tests {
         crate::commands::tags::add_tag_rule(
             &db,
             "going to the coffee shop",
-            None,
-            Some("cof"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("cof".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         crate::commands::tags::add_tag_rule(
             &db,
             "ordering a coffee",
-            None,
-            Some("coffee"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("coffee".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         let output_for_testing = command_query(
@@ -1461,13 +2106,10 @@ mod by_month_tests {
         crate::commands::tags::add_tag_rule(
             &db,
             "daily/coffee",
-            None,
-            Some("cof"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("cof".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         let output_for_testing = command_query(
@@ -1522,6 +2164,8 @@ mod by_tag_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::ByTag {
                 transaction_type: None,
+                depth: None,
+                tree: false,
                 table: true,
             },
             &vec![],
@@ -1557,25 +2201,19 @@ mod by_tag_tests {
         crate::commands::tags::add_tag_rule(
             &db,
             "coffee",
-            None,
-            Some("coffee"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("coffee".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         crate::commands::tags::add_tag_rule(
             &db,
             "pub",
-            None,
-            Some("pub"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("pub".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         let output_for_testing = command_query(
@@ -1583,6 +2221,8 @@ mod by_tag_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::ByTag {
                 transaction_type: None,
+                depth: None,
+                tree: false,
                 table: true,
             },
             &vec![],
@@ -1612,13 +2252,10 @@ mod by_tag_tests {
         crate::commands::tags::add_tag_rule(
             &db,
             "credit",
-            None,
-            None,
-            Some(database::model::TransactionType::Credit),
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                transaction_type: Some(database::model::TransactionType::Credit),
+                ..Default::default()
+            },
         )
         .unwrap();
         let output_for_testing = command_query(
@@ -1626,6 +2263,8 @@ mod by_tag_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::ByTag {
                 transaction_type: None,
+                depth: None,
+                tree: false,
                 table: true,
             },
             &vec![],
@@ -1658,6 +2297,8 @@ mod by_tag_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::ByTag {
                 transaction_type: Some(crate::TransactionType::debit),
+                depth: None,
+                tree: false,
                 table: true,
             },
             &vec![],
@@ -1687,25 +2328,19 @@ mod by_tag_tests {
         crate::commands::tags::add_tag_rule(
             &db,
             "pub",
-            None,
-            Some("pub"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("pub".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         crate::commands::tags::add_tag_rule(
             &db,
             "pub",
-            None,
-            Some("pu"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("pu".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         let output_for_testing = command_query(
@@ -1713,6 +2348,8 @@ mod by_tag_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::ByTag {
                 transaction_type: None,
+                depth: None,
+                tree: false,
                 table: true,
             },
             &vec![],
@@ -1740,25 +2377,19 @@ mod by_tag_tests {
         crate::commands::tags::add_tag_rule(
             &db,
             "coffee",
-            None,
-            Some("coffee"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("coffee".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         crate::commands::tags::add_tag_rule(
             &db,
             "pub",
-            None,
-            Some("pub"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("pub".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
         let output_for_testing = command_query(
@@ -1766,6 +2397,8 @@ mod by_tag_tests {
             &mut Cursor::new(vec![]),
             &PreparedQuery::ByTag {
                 transaction_type: None,
+                depth: None,
+                tree: false,
                 table: true,
             },
             &vec![],
@@ -1786,27 +2419,40 @@ mod by_tag_tests {
             unimplemented!()
         }
     }
-}
-
-#[cfg(test)]
-mod chart_tests {
-    use super::*;
-    use crate::database::open_stingy_testing_database;
-    use crate::TimeAggregation;
-    use serde_json;
-    use std::io::Cursor;
 
-    #[test]
-    fn by_month_simple() {
-        let db = open_stingy_testing_database();
-        db.insert_test_data();
+    fn add_hierarchical_tag_rules(db: &Box<dyn database::StingyDatabase>) {
+        for (tag, description) in [
+            ("food", "PUB"),
+            ("food/takeaway", "FOOD ORDER"),
+            ("food/takeaway/late", "FOOD ORDER 1"),
+            ("food/groceries", "GROCERIES"),
+            ("drinks/coffee", "COFFEE"),
+        ] {
+            crate::commands::tags::add_tag_rule(
+                db,
+                tag,
+                &crate::commands::tags::TagRuleAttributes {
+                    description_contains: Some(description.to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        }
+    }
 
+    fn query_by_tag(
+        db: &Box<dyn database::StingyDatabase>,
+        depth: Option<u32>,
+        tree: bool,
+    ) -> Vec<Vec<String>> {
         let output_for_testing = command_query(
-            &db,
+            db,
             &mut Cursor::new(vec![]),
-            &PreparedQuery::ByTime {
-                aggregate: TimeAggregation::Month,
-                table: false,
+            &PreparedQuery::ByTag {
+                transaction_type: None,
+                depth,
+                tree,
+                table: true,
             },
             &vec![],
             &vec![],
@@ -1815,32 +2461,982 @@ mod chart_tests {
             None,
             None,
             None,
-            vec![],
+            vec!["000000 - 00000000"],
         )
         .unwrap();
-        if let Some(OutputForTesting::Chart(chart_json)) = output_for_testing {
-            let chart = serde_json::from_str::<serde_json::Value>(&chart_json).unwrap();
-            assert_eq!(chart.get("xAxis").unwrap().as_array().unwrap().len(), 2);
-            assert_eq!(chart.get("yAxis").unwrap().as_array().unwrap().len(), 2);
+        if let Some(OutputForTesting::Table((_, rows))) = output_for_testing {
+            rows
         } else {
             unimplemented!()
         }
     }
 
     #[test]
-    fn by_month_hide_balance_when_filtering_by_tag() {
+    fn depth() {
         let db = open_stingy_testing_database();
         db.insert_test_data();
-        crate::commands::tags::add_tag_rule(
-            &db,
-            "coffee",
-            None,
-            Some("coffee"),
-            None,
-            None,
-            None,
-            None,
-            None,
+        add_hierarchical_tag_rules(&db);
+
+        let rows = query_by_tag(&db, Some(1), false);
+        assert_eq!(rows.len(), 3);
+        // "FOOD ORDER 1" is tagged twice under "food", but only counted once.
+        assert_eq!(rows[0][..2], ["food", "125.62"]);
+        assert_eq!(rows[1][..2], ["", "7.63"]);
+        assert_eq!(rows[2][..2], ["drinks", "6.73"]);
+
+        let rows = query_by_tag(&db, Some(2), false);
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[0][..2], ["food/takeaway", "57.65"]);
+        assert_eq!(rows[1][..2], ["food/groceries", "51.97"]);
+        assert_eq!(rows[2][..2], ["food", "16.00"]);
+    }
+
+    #[test]
+    fn tree() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_hierarchical_tag_rules(&db);
+
+        let rows = query_by_tag(&db, None, true);
+        assert_eq!(
+            rows,
+            vec![
+                vec!["food", "16.00", "125.62", "0.00", "0.00"],
+                vec!["· food/takeaway", "57.65", "57.65", "0.00", "0.00"],
+                vec!["· · food/takeaway/late", "10.00", "10.00", "0.00", "0.00"],
+                vec!["· food/groceries", "51.97", "51.97", "0.00", "0.00"],
+                vec!["", "7.63", "7.63", "1000.00", "1000.00"],
+                vec!["drinks", "0.00", "6.73", "0.00", "0.00"],
+                vec!["· drinks/coffee", "6.73", "6.73", "0.00", "0.00"],
+            ]
+        );
+
+        let rows = query_by_tag(&db, Some(1), true);
+        assert_eq!(
+            rows,
+            vec![
+                vec!["food", "125.62", "125.62", "0.00", "0.00"],
+                vec!["", "7.63", "7.63", "1000.00", "1000.00"],
+                vec!["drinks", "6.73", "6.73", "0.00", "0.00"],
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod by_tag_time_tests {
+    use super::*;
+    use crate::database::open_stingy_testing_database;
+    use crate::TimeAggregation;
+    use std::io::Cursor;
+
+    fn query_by_tag_time(
+        db: &Box<dyn database::StingyDatabase>,
+        transaction_type: Option<crate::TransactionType>,
+        depth: Option<u32>,
+    ) -> (Vec<String>, Vec<Vec<String>>) {
+        let output_for_testing = command_query(
+            db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::ByTagTime {
+                aggregate: TimeAggregation::Month,
+                transaction_type,
+                depth,
+                table: true,
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            vec!["000000 - 00000000"],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Table(table)) = output_for_testing {
+            table
+        } else {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn matrix_with_totals() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        for (tag, description) in [
+            ("food/takeaway", "FOOD ORDER"),
+            ("food/groceries", "GROCERIES"),
+            ("food/pub", "PUB"),
+            ("coffee", "COFFEE"),
+        ] {
+            crate::commands::tags::add_tag_rule(
+                &db,
+                tag,
+                &crate::commands::tags::TagRuleAttributes {
+                    description_contains: Some(description.to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        }
+
+        let (columns, rows) = query_by_tag_time(&db, None, Some(1));
+        assert_eq!(columns, vec!["Tag", "2021/02", "2021/03", "Total ↑"]);
+        assert_eq!(
+            rows,
+            vec![
+                vec!["food", "68.48", "57.14", "125.62"],
+                vec!["", "0.00", "7.63", "7.63"],
+                vec!["coffee", "3.74", "2.99", "6.73"],
+                vec!["Total", "72.22", "67.76", "139.98"],
+            ]
+        );
+
+        let (_, rows) = query_by_tag_time(&db, None, None);
+        assert_eq!(rows.len(), 6);
+        assert_eq!(rows[0], vec!["food/takeaway", "32.50", "25.15", "57.65"]);
+        assert_eq!(rows[5], vec!["Total", "72.22", "67.76", "139.98"]);
+    }
+
+    #[test]
+    fn credits() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+
+        let (columns, rows) = query_by_tag_time(&db, Some(crate::TransactionType::credit), None);
+        assert_eq!(columns, vec!["Tag", "2021/02", "Total ↑"]);
+        assert_eq!(
+            rows,
+            vec![
+                vec!["", "1000.00", "1000.00"],
+                vec!["Total", "1000.00", "1000.00"],
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+mod compare_tests {
+    use super::*;
+    use crate::database::open_stingy_testing_database;
+    use std::io::Cursor;
+
+    #[test]
+    fn against_last_year() {
+        let from = NaiveDate::from_ymd_opt(2024, 02, 01).unwrap();
+        let to = NaiveDate::from_ymd_opt(2024, 02, 29).unwrap();
+        assert_eq!(
+            compare_against_period("last-year", from, to).unwrap(),
+            (
+                Some(NaiveDate::from_ymd_opt(2023, 02, 01).unwrap()),
+                Some(NaiveDate::from_ymd_opt(2023, 02, 28).unwrap())
+            )
+        );
+        assert!(compare_against_period("last-year", NaiveDate::MIN, to).is_err());
+        assert_eq!(
+            compare_against_period("2023/06/01-2023/06/30", from, to).unwrap(),
+            (
+                Some(NaiveDate::from_ymd_opt(2023, 06, 01).unwrap()),
+                Some(NaiveDate::from_ymd_opt(2023, 06, 30).unwrap())
+            )
+        );
+    }
+
+    #[test]
+    fn per_tag_and_overall() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        for (tag, description) in [("coffee", "COFFEE"), ("food", "FOOD ORDER")] {
+            crate::commands::tags::add_tag_rule(
+                &db,
+                tag,
+                &crate::commands::tags::TagRuleAttributes {
+                    description_contains: Some(description.to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        }
+
+        let output_for_testing = command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::Compare {
+                against: "2021/02/01-2021/02/28".to_string(),
+                depth: None,
+                table: true,
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            Some(NaiveDate::from_ymd_opt(2021, 03, 01).unwrap()),
+            Some(NaiveDate::from_ymd_opt(2021, 03, 31).unwrap()),
+            vec!["000000 - 00000000"],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Table((columns, rows))) = output_for_testing {
+            assert_eq!(columns.len(), 9);
+            assert_eq!(
+                rows,
+                vec![
+                    vec![
+                        "", "35.98", "39.62", "3.64", "10.12", "1000.00", "0.00", "-1000.00",
+                        "-100.00"
+                    ],
+                    vec!["food", "32.50", "25.15", "-7.35", "-22.62", "0.00", "0.00", "0.00", "-"],
+                    vec!["coffee", "3.74", "2.99", "-0.75", "-20.05", "0.00", "0.00", "0.00", "-"],
+                    vec![
+                        "Total", "72.22", "67.76", "-4.46", "-6.18", "1000.00", "0.00", "-1000.00",
+                        "-100.00"
+                    ],
+                ]
+            );
+        } else {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn requires_period() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        assert!(command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::Compare {
+                against: "last-year".to_string(),
+                depth: None,
+                table: true,
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            vec![],
+        )
+        .is_err());
+    }
+}
+
+#[cfg(test)]
+mod budget_tests {
+    use super::*;
+    use crate::commands::budget::set_budget;
+    use crate::database::open_stingy_testing_database;
+    use std::io::Cursor;
+
+    #[test]
+    fn budget_periods() {
+        let date = |y, m, d| NaiveDate::from_ymd_opt(y, m, d).unwrap();
+        let monthly = model::BudgetPeriod::Monthly;
+        let yearly = model::BudgetPeriod::Yearly;
+        assert_eq!(
+            budget_periods_in(monthly, date(2021, 2, 1), date(2021, 3, 31)),
+            2.0
+        );
+        assert_eq!(
+            budget_periods_in(monthly, date(2021, 2, 15), date(2021, 2, 28)),
+            0.5
+        );
+        assert_eq!(
+            budget_periods_in(yearly, date(2021, 1, 1), date(2021, 12, 31)),
+            1.0
+        );
+        assert_eq!(
+            budget_periods_in(yearly, date(2021, 3, 1), date(2021, 3, 31)),
+            31.0 / 365.0
+        );
+    }
+
+    fn query_budget(
+        db: &Box<dyn database::StingyDatabase>,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> Vec<Vec<String>> {
+        let output_for_testing = command_query(
+            db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::Budget {},
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            from,
+            to,
+            vec!["000000 - 00000000"],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Table((_, rows))) = output_for_testing {
+            rows
+        } else {
+            unimplemented!()
+        }
+    }
+
+    fn add_budgets(db: &Box<dyn database::StingyDatabase>) {
+        for (tag, description) in [
+            ("food/takeaway", "FOOD ORDER"),
+            ("food/pub", "PUB"),
+            ("coffee", "COFFEE"),
+        ] {
+            crate::commands::tags::add_tag_rule(
+                db,
+                tag,
+                &crate::commands::tags::TagRuleAttributes {
+                    description_contains: Some(description.to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        }
+        set_budget(db, "food/", 100.0, model::BudgetPeriod::Monthly).unwrap();
+        set_budget(db, "coffee", 365.0, model::BudgetPeriod::Yearly).unwrap();
+    }
+
+    #[test]
+    fn current_month() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_budgets(&db);
+
+        // It's the first of March, so one day of the month has passed.
+        assert_eq!(
+            query_budget(&db, None, None),
+            vec![
+                vec![
+                    "coffee",
+                    "yearly",
+                    "31.00",
+                    "2.99",
+                    "28.01",
+                    "1.00",
+                    "over (299%)"
+                ],
+                vec![
+                    "food/",
+                    "monthly",
+                    "100.00",
+                    "41.15",
+                    "58.85",
+                    "3.23",
+                    "over (1276%)"
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn period() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_budgets(&db);
+
+        let rows = query_budget(
+            &db,
+            Some(NaiveDate::from_ymd_opt(2021, 02, 01).unwrap()),
+            Some(NaiveDate::from_ymd_opt(2021, 03, 31).unwrap()),
+        );
+        assert_eq!(
+            rows[1],
+            vec![
+                "food/",
+                "monthly",
+                "200.00",
+                "73.65",
+                "126.35",
+                "103.23",
+                "under (71%)"
+            ]
+        );
+
+        // Up to today, the first of March.
+        let rows = query_budget(
+            &db,
+            Some(NaiveDate::from_ymd_opt(2021, 02, 01).unwrap()),
+            Some(NaiveDate::MAX),
+        );
+        assert_eq!(rows[1][2], "103.23");
+    }
+
+    #[test]
+    fn no_budgets() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        assert!(command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::Budget {},
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            vec![],
+        )
+        .unwrap()
+        .is_none());
+    }
+}
+
+#[cfg(test)]
+mod recurring_tests {
+    use super::*;
+    use crate::database::{open_stingy_testing_database, NewOrExisting};
+    use std::io::Cursor;
+
+    fn insert_debit(
+        db: &Box<dyn database::StingyDatabase>,
+        date: NaiveDate,
+        description: &str,
+        amount: f64,
+    ) {
+        let transaction = model::Transaction {
+            id: None,
+            account_name: "000000 - 00000000".to_string(),
+            posted_date: date,
+            description: description.to_string(),
+            debit_amount: amount,
+            credit_amount: 0.0,
+            balance: 9000.0,
+            transaction_type: model::TransactionType::DirectDebit,
+            currency: "EUR".to_string(),
+        };
+        match db.insert(transaction).unwrap() {
+            NewOrExisting::New(_) => {}
+            NewOrExisting::Existing => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn subscriptions() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        for (month, amount) in [(1, 12.99), (2, 12.99), (3, 15.99)] {
+            insert_debit(
+                &db,
+                NaiveDate::from_ymd_opt(2021, month, 5).unwrap(),
+                &format!("NETFLIX.COM 100{month}"),
+                amount,
+            );
+        }
+        for day in [1, 8, 15, 22] {
+            insert_debit(
+                &db,
+                NaiveDate::from_ymd_opt(2021, 2, day).unwrap(),
+                "GYM",
+                10.0,
+            );
+        }
+
+        let output_for_testing = command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::Recurring { tolerance: 10.0 },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            vec!["000000 - 00000000"],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Table((columns, rows))) = output_for_testing {
+            assert_eq!(columns.len(), 9);
+            assert_eq!(
+                rows,
+                vec![
+                    vec![
+                        "000000 - 00000000",
+                        "GYM",
+                        "weekly",
+                        "  4",
+                        "10.00",
+                        "2021/02/22",
+                        "2021/03/01",
+                        "520.00",
+                        ""
+                    ],
+                    vec![
+                        "000000 - 00000000",
+                        "NETFLIX.COM 1003",
+                        "monthly",
+                        "  3",
+                        "12.99",
+                        "2021/03/05",
+                        "2021/04/05",
+                        "191.88",
+                        "12.99 → 15.99 (2021/03/05)"
+                    ],
+                ]
+            );
+        } else {
+            unimplemented!()
+        }
+    }
+}
+
+#[cfg(test)]
+mod anomalies_tests {
+    use super::*;
+    use crate::database::{open_stingy_testing_database, NewOrExisting};
+    use std::io::Cursor;
+
+    fn insert_debit(
+        db: &Box<dyn database::StingyDatabase>,
+        date: NaiveDate,
+        description: &str,
+        amount: f64,
+    ) {
+        let transaction = model::Transaction {
+            id: None,
+            account_name: "000000 - 00000000".to_string(),
+            posted_date: date,
+            description: description.to_string(),
+            debit_amount: amount,
+            credit_amount: 0.0,
+            balance: 9000.0,
+            transaction_type: model::TransactionType::Debit,
+            currency: "EUR".to_string(),
+        };
+        match db.insert(transaction).unwrap() {
+            NewOrExisting::New(_) => {}
+            NewOrExisting::Existing => unreachable!(),
+        }
+    }
+
+    fn query_anomalies(
+        db: &Box<dyn database::StingyDatabase>,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    ) -> Result<Option<OutputForTesting>> {
+        command_query(
+            db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::Anomalies {
+                history: 2,
+                threshold: 3.0,
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            from,
+            to,
+            vec!["000000 - 00000000"],
+        )
+    }
+
+    #[test]
+    fn large_debit() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        insert_debit(
+            &db,
+            NaiveDate::from_ymd_opt(2021, 1, 4).unwrap(),
+            "COFFEE",
+            3.0,
+        );
+        insert_debit(
+            &db,
+            NaiveDate::from_ymd_opt(2021, 1, 11).unwrap(),
+            "COFFEE",
+            3.5,
+        );
+        insert_debit(
+            &db,
+            NaiveDate::from_ymd_opt(2021, 2, 1).unwrap(),
+            "COFFEE",
+            3.2,
+        );
+        insert_debit(
+            &db,
+            NaiveDate::from_ymd_opt(2021, 3, 2).unwrap(),
+            "COFFEE",
+            30.0,
+        );
+
+        // The current month by default.
+        let output_for_testing = query_anomalies(&db, None, None).unwrap();
+        if let Some(OutputForTesting::Table((columns, rows))) = output_for_testing {
+            assert_eq!(columns.len(), 6);
+            assert_eq!(
+                rows,
+                vec![vec![
+                    "transaction",
+                    "COFFEE",
+                    "2021/03/02",
+                    "30.00",
+                    "3.35 ± 0.37 over 4 debits",
+                    "71.9"
+                ]]
+            );
+        } else {
+            unimplemented!()
+        }
+
+        // Nothing stands out in February.
+        assert!(query_anomalies(
+            &db,
+            NaiveDate::from_ymd_opt(2021, 2, 1),
+            NaiveDate::from_ymd_opt(2021, 2, 28)
+        )
+        .unwrap()
+        .is_none());
+        assert!(query_anomalies(
+            &db,
+            Some(NaiveDate::MIN),
+            NaiveDate::from_ymd_opt(2021, 2, 28)
+        )
+        .is_err());
+    }
+}
+
+#[cfg(test)]
+mod forecast_tests {
+    use super::*;
+    use crate::database::{open_stingy_testing_database, NewOrExisting};
+    use std::io::Cursor;
+
+    #[test]
+    fn rent_below_floor() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        for (year, month) in [(2020, 12), (2021, 1), (2021, 2)] {
+            let transaction = model::Transaction {
+                id: None,
+                account_name: "000000 - 00000000".to_string(),
+                posted_date: NaiveDate::from_ymd_opt(year, month, 1).unwrap(),
+                description: "RENT".to_string(),
+                debit_amount: 1000.0,
+                credit_amount: 0.0,
+                balance: 9000.0,
+                transaction_type: model::TransactionType::DirectDebit,
+                currency: "EUR".to_string(),
+            };
+            match db.insert(transaction).unwrap() {
+                NewOrExisting::New(_) => {}
+                NewOrExisting::Existing => unreachable!(),
+            }
+        }
+
+        let mut writer = Cursor::new(vec![]);
+        let output_for_testing = command_query(
+            &db,
+            &mut writer,
+            &PreparedQuery::Forecast {
+                until: "2021/04".to_string(),
+                floor: 9200.0,
+                large_outflow: 500.0,
+                table: true,
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            vec!["000000 - 00000000"],
+        )
+        .unwrap();
+        // The rent is the only recurring debit, and the rest average (1000 - 139.98) / 93 days.
+        if let Some(OutputForTesting::Table((columns, rows))) = output_for_testing {
+            assert_eq!(columns.len(), 6);
+            assert_eq!(
+                rows,
+                vec![
+                    vec![
+                        "000000 - 00000000",
+                        "2021/04/01",
+                        "RENT",
+                        "-1000.00",
+                        "9120.94",
+                        "large outflow, below floor"
+                    ],
+                    vec![
+                        "000000 - 00000000",
+                        "2021/04/30",
+                        "End of forecast",
+                        "",
+                        "9389.12",
+                        ""
+                    ],
+                ]
+            );
+        } else {
+            unimplemented!()
+        }
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+        assert!(output.contains(
+            "The balance of 000000 - 00000000 is projected to drop below 9200.00 on 2021/04/01 (9120.94)."
+        ));
+    }
+
+    #[test]
+    fn no_transactions() {
+        let db = open_stingy_testing_database();
+        assert!(command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::Forecast {
+                until: "2021/04".to_string(),
+                floor: 0.0,
+                large_outflow: 500.0,
+                table: true,
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            vec![],
+        )
+        .unwrap()
+        .is_none());
+    }
+}
+
+#[cfg(test)]
+mod chart_tests {
+    use super::*;
+    use crate::database::open_stingy_testing_database;
+    use crate::TimeAggregation;
+    use serde_json;
+    use std::io::Cursor;
+
+    #[test]
+    fn by_month_simple() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+
+        let output_for_testing = command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::ByTime {
+                aggregate: TimeAggregation::Month,
+                table: false,
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            vec![],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Chart(chart_json)) = output_for_testing {
+            let chart = serde_json::from_str::<serde_json::Value>(&chart_json).unwrap();
+            assert_eq!(chart.get("xAxis").unwrap().as_array().unwrap().len(), 2);
+            assert_eq!(chart.get("yAxis").unwrap().as_array().unwrap().len(), 2);
+        } else {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn forecast_dashed_projection() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+
+        let output_for_testing = command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::Forecast {
+                until: "2021/04".to_string(),
+                floor: 0.0,
+                large_outflow: 500.0,
+                table: false,
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            vec!["000000 - 00000000"],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Chart(chart_json)) = output_for_testing {
+            let chart = serde_json::from_str::<serde_json::Value>(&chart_json).unwrap();
+            assert_eq!(
+                chart["xAxis"][0]["data"],
+                serde_json::json!(["Feb/2021", "Mar/2021", "Apr/2021"])
+            );
+            let series = chart["series"].as_array().unwrap();
+            assert_eq!(series.len(), 2);
+            assert_eq!(series[0]["data"][0], 9927.52);
+            assert_eq!(series[0]["data"][1], 9852.76);
+            // The projection starts from the last balance.
+            assert_eq!(series[1]["data"][1], 9852.76);
+            assert_eq!(series[1]["lineStyle"]["type"], "dashed");
+        } else {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn by_tag_sunburst() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        crate::commands::tags::add_tag_rule(
+            &db,
+            "food/takeaway",
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("food order".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let output_for_testing = command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::ByTag {
+                transaction_type: None,
+                depth: None,
+                tree: false,
+                table: false,
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            vec!["000000 - 00000000"],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Chart(chart_json)) = output_for_testing {
+            let chart = serde_json::from_str::<serde_json::Value>(&chart_json).unwrap();
+            let series = chart.get("series").unwrap().as_array().unwrap();
+            assert_eq!(series.len(), 2);
+            assert_eq!(series[0]["type"], "sunburst");
+            let debits = series[0]["data"].as_array().unwrap();
+            assert_eq!(debits[1]["name"], "food\n(57.65)");
+            assert_eq!(debits[1]["children"][0]["name"], "takeaway\n(57.65)");
+        } else {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn by_tag_time_stacked_bars() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        crate::commands::tags::add_tag_rule(
+            &db,
+            "coffee",
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("coffee".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        let output_for_testing = command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::ByTagTime {
+                aggregate: TimeAggregation::Month,
+                transaction_type: None,
+                depth: None,
+                table: false,
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            vec!["000000 - 00000000"],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Chart(chart_json)) = output_for_testing {
+            let chart = serde_json::from_str::<serde_json::Value>(&chart_json).unwrap();
+            assert_eq!(
+                chart["xAxis"][0]["data"],
+                serde_json::json!(["Feb/2021", "Mar/2021"])
+            );
+            let series = chart.get("series").unwrap().as_array().unwrap();
+            assert_eq!(series.len(), 2);
+            assert_eq!(series[0]["name"], "(untagged)");
+            assert_eq!(series[0]["stack"], "tags");
+            assert_eq!(series[1]["name"], "coffee");
+            assert_eq!(series[1]["data"], serde_json::json!([3.74, 2.99]));
+        } else {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn compare_diverging_bars() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+
+        let output_for_testing = command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::Compare {
+                against: "february".to_string(),
+                depth: None,
+                table: false,
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            Some(NaiveDate::from_ymd_opt(2021, 03, 01).unwrap()),
+            Some(NaiveDate::from_ymd_opt(2021, 03, 31).unwrap()),
+            vec!["000000 - 00000000"],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Chart(chart_json)) = output_for_testing {
+            let chart = serde_json::from_str::<serde_json::Value>(&chart_json).unwrap();
+            assert_eq!(
+                chart["yAxis"][0]["data"],
+                serde_json::json!(["(untagged)", "Total"])
+            );
+            let series = chart.get("series").unwrap().as_array().unwrap();
+            assert_eq!(series.len(), 2);
+            assert_eq!(series[1]["name"], "Credits");
+            assert_eq!(series[1]["data"], serde_json::json!([-1000.0, -1000.0]));
+        } else {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn by_month_hide_balance_when_filtering_by_tag() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        crate::commands::tags::add_tag_rule(
+            &db,
+            "coffee",
+            &crate::commands::tags::TagRuleAttributes {
+                description_contains: Some("coffee".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
 
@@ -0,0 +1,198 @@
+use crate::database::{Cadence, DebitsRow, RecurringRow};
+use chrono::{Days, Months, NaiveDate};
+use std::collections::BTreeMap;
+
+/// The default percentage by which the amounts of a series may differ.
+pub const DEFAULT_TOLERANCE: f64 = 10.0;
+
+/// Normalize a description so that the debits in a series compare equal, even if they include
+/// references or dates that change every time: "NETFLIX.COM 4829" becomes "netflix com".
+pub fn normalize_description(description: &str) -> String {
+    description
+        .to_lowercase()
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+pub fn cadence_name(cadence: Cadence) -> &'static str {
+    match cadence {
+        Cadence::Weekly => "weekly",
+        Cadence::Monthly => "monthly",
+        Cadence::Yearly => "yearly",
+    }
+}
+
+pub fn occurrences_per_year(cadence: Cadence) -> f64 {
+    match cadence {
+        Cadence::Weekly => 52.0,
+        Cadence::Monthly => 12.0,
+        Cadence::Yearly => 1.0,
+    }
+}
+
+/// The `n`th occurrence after `date`. Counting from `date` rather than from the previous
+/// occurrence keeps monthly series from drifting to earlier days after short months.
+pub fn nth_occurrence(cadence: Cadence, date: NaiveDate, n: u32) -> NaiveDate {
+    match cadence {
+        Cadence::Weekly => date + Days::new(7 * n as u64),
+        Cadence::Monthly => date + Months::new(n),
+        Cadence::Yearly => date + Months::new(12 * n),
+    }
+}
+
+pub fn next_occurrence(cadence: Cadence, date: NaiveDate) -> NaiveDate {
+    nth_occurrence(cadence, date, 1)
+}
+
+pub fn median(values: &[f64]) -> f64 {
+    let mut values = values.to_vec();
+    values.sort_by(f64::total_cmp);
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+/// The cadence of a series of dates, if the intervals between them are all about a week, a month
+/// or a year. Weekly and monthly series need at least three dates, and yearly ones two.
+fn cadence_of(dates: &[NaiveDate]) -> Option<Cadence> {
+    let intervals: Vec<i64> = dates.windows(2).map(|w| (w[1] - w[0]).num_days()).collect();
+    [
+        (Cadence::Weekly, 5..=9, 3),
+        (Cadence::Monthly, 25..=35, 3),
+        (Cadence::Yearly, 350..=380, 2),
+    ]
+    .into_iter()
+    .find(|(_, days, min_dates)| {
+        dates.len() >= *min_dates && intervals.iter().all(|i| days.contains(i))
+    })
+    .map(|(cadence, _, _)| cadence)
+}
+
+/// Find the series of debits with the same account and normalized description, a regular cadence
+/// and a stable amount, sorted by their annualized cost.
+///
+/// The amount is stable if it changes by at most `tolerance_pct` percent from one debit to the
+/// next, except for price increases, as long as most changes are within the tolerance. Increases
+/// of more than 1% are reported as price increases.
+pub fn find_recurring(rows: &[DebitsRow], tolerance_pct: f64) -> Vec<RecurringRow> {
+    let mut series: BTreeMap<(&str, String), Vec<&DebitsRow>> = BTreeMap::new();
+    for row in rows {
+        series
+            .entry((&row.account_name, normalize_description(&row.description)))
+            .or_default()
+            .push(row);
+    }
+
+    let mut recurring = vec![];
+    for (_, mut debits) in series {
+        debits.sort_by_key(|d| (d.posted_date, d.transaction_id));
+        let dates: Vec<_> = debits.iter().map(|d| d.posted_date).collect();
+        let Some(cadence) = cadence_of(&dates) else {
+            continue;
+        };
+
+        let amounts: Vec<_> = debits.iter().map(|d| d.debit_amount).collect();
+        let within_tolerance = |a: f64, b: f64| (b - a).abs() <= a * tolerance_pct / 100.0;
+        let changes: Vec<_> = amounts.windows(2).map(|w| (w[0], w[1])).collect();
+        let large_changes: Vec<_> = changes
+            .iter()
+            .filter(|(a, b)| !within_tolerance(*a, *b))
+            .collect();
+        if large_changes.iter().any(|(a, b)| b < a) || large_changes.len() * 2 > changes.len() {
+            continue;
+        }
+
+        let price_increase = debits
+            .windows(2)
+            .rev()
+            .find(|w| w[1].debit_amount > w[0].debit_amount * 1.01)
+            .map(|w| (w[0].debit_amount, w[1].debit_amount, w[1].posted_date));
+        let last = debits.last().unwrap();
+        recurring.push(RecurringRow {
+            account_name: last.account_name.clone(),
+            description: last.description.clone(),
+            cadence,
+            occurrences: debits.len(),
+            typical_amount: median(&amounts),
+            last_amount: last.debit_amount,
+            last_date: last.posted_date,
+            next_date: next_occurrence(cadence, last.posted_date),
+            annualized_amount: last.debit_amount * occurrences_per_year(cadence),
+            price_increase,
+        });
+    }
+    recurring.sort_by(|a, b| b.annualized_amount.total_cmp(&a.annualized_amount));
+    recurring
+}
+
+#[cfg(test)]
+mod recurring_tests {
+    use super::*;
+
+    fn debit(description: &str, date: (i32, u32, u32), amount: f64) -> DebitsRow {
+        DebitsRow {
+            account_name: "000000 - 00000000".to_string(),
+            description: description.to_string(),
+            posted_date: NaiveDate::from_ymd_opt(date.0, date.1, date.2).unwrap(),
+            debit_amount: amount,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_description("NETFLIX.COM 4829"), "netflix com");
+        assert_eq!(normalize_description("Spotify P0A1B2"), "spotify p a b");
+    }
+
+    #[test]
+    fn cadences() {
+        let rows = vec![
+            debit("GYM", (2021, 2, 1), 10.0),
+            debit("GYM", (2021, 2, 8), 10.0),
+            debit("GYM", (2021, 2, 15), 10.0),
+            debit("RENT", (2021, 1, 28), 1000.0),
+            debit("RENT", (2021, 2, 26), 1000.0),
+            debit("RENT", (2021, 3, 29), 1000.0),
+            debit("INSURANCE", (2020, 3, 10), 300.0),
+            debit("INSURANCE", (2021, 3, 9), 300.0),
+            // Two monthly debits aren't enough to tell.
+            debit("PHONE", (2021, 2, 1), 20.0),
+            debit("PHONE", (2021, 3, 1), 20.0),
+        ];
+        let recurring = find_recurring(&rows, 10.0);
+        let found: Vec<_> = recurring
+            .iter()
+            .map(|r| (r.description.as_str(), r.cadence))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("RENT", Cadence::Monthly),
+                ("GYM", Cadence::Weekly),
+                ("INSURANCE", Cadence::Yearly),
+            ]
+        );
+        assert_eq!(
+            recurring[1].next_date,
+            NaiveDate::from_ymd_opt(2021, 2, 22).unwrap()
+        );
+    }
+
+    #[test]
+    fn unstable_amounts() {
+        let rows = vec![
+            debit("SUPERMARKET", (2021, 1, 1), 50.0),
+            debit("SUPERMARKET", (2021, 1, 8), 20.0),
+            debit("SUPERMARKET", (2021, 1, 15), 80.0),
+            debit("SUPERMARKET", (2021, 1, 22), 35.0),
+        ];
+        assert!(find_recurring(&rows, 10.0).is_empty());
+        assert_eq!(find_recurring(&rows, 300.0).len(), 1);
+    }
+}
@@ -0,0 +1,418 @@
+use crate::database::{model, NewOrExisting, StingyDatabase};
+use crate::output::format::ToOutputFormat;
+use anyhow::{anyhow, bail, Result};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug)]
+pub struct ListRefundsResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub struct RefundCandidate {
+    pub purchase: model::Transaction,
+    pub refund: model::Transaction,
+}
+
+fn describe_transaction(transaction: &model::Transaction) -> String {
+    format!(
+        "{} {} ({})",
+        transaction.posted_date.to_output_format(),
+        transaction.description,
+        if transaction.transaction_type == model::TransactionType::Credit {
+            transaction.credit_amount.to_output_format()
+        } else {
+            transaction.debit_amount.to_output_format()
+        }
+    )
+}
+
+fn get_transaction(
+    transactions: &[model::Transaction],
+    transaction_id: i64,
+) -> Result<&model::Transaction> {
+    transactions
+        .iter()
+        .find(|t| t.id == Some(transaction_id))
+        .ok_or(anyhow!("transaction {transaction_id} not found."))
+}
+
+/// The amount of each debit that hasn't been refunded yet by the credits linked to it.
+fn unrefunded_amounts(
+    transactions: &[model::Transaction],
+    refunds: &[model::Refund],
+) -> Result<HashMap<i64, f64>> {
+    let mut amounts: HashMap<i64, f64> = transactions
+        .iter()
+        .filter(|t| t.transaction_type != model::TransactionType::Credit)
+        .map(|t| (t.id.unwrap(), t.debit_amount))
+        .collect();
+    for refund in refunds {
+        let credit = get_transaction(transactions, refund.credit_transaction_id)?;
+        if let Some(amount) = amounts.get_mut(&refund.debit_transaction_id) {
+            *amount -= credit.credit_amount;
+        }
+    }
+    Ok(amounts)
+}
+
+/// Whether `amount` fits in `available`, comparing whole cents so that rounding errors in the sums
+/// of amounts don't matter.
+fn fits(amount: f64, available: f64) -> bool {
+    (amount * 100.0).round() <= (available * 100.0).round()
+}
+
+pub fn list_refunds(db: &Box<dyn StingyDatabase>) -> Result<ListRefundsResult> {
+    let transactions: Vec<model::Transaction> = db.get_all()?;
+    let mut refunds: Vec<model::Refund> = db.get_all()?;
+    refunds.sort_by_key(|r| r.id);
+    let columns = vec![
+        "ID".to_string(),
+        "Purchase".to_string(),
+        "Refund".to_string(),
+    ];
+    let mut rows = vec![];
+    for refund in &refunds {
+        rows.push(vec![
+            format!("{}", refund.id.unwrap()),
+            describe_transaction(get_transaction(&transactions, refund.debit_transaction_id)?),
+            describe_transaction(get_transaction(
+                &transactions,
+                refund.credit_transaction_id,
+            )?),
+        ]);
+    }
+    Ok(ListRefundsResult { columns, rows })
+}
+
+/// Propose links between credits and the debits they likely refund.
+///
+/// A credit is a candidate refund for a debit with the same description (ignoring case), an equal
+/// or larger amount than what is left to refund of it, posted at most `max_days` before it. When
+/// more than one debit qualifies, the most recent one is proposed. Credits that are already linked
+/// are not considered, and debits are never proposed more refunds than their amount.
+pub fn find_refund_candidates(
+    db: &Box<dyn StingyDatabase>,
+    max_days: i64,
+) -> Result<Vec<RefundCandidate>> {
+    let transactions: Vec<model::Transaction> = db.get_all()?;
+    let refunds: Vec<model::Refund> = db.get_all()?;
+    let linked_credits: HashSet<i64> = refunds.iter().map(|r| r.credit_transaction_id).collect();
+    let mut unrefunded = unrefunded_amounts(&transactions, &refunds)?;
+
+    let mut candidates = vec![];
+    for credit in &transactions {
+        if credit.transaction_type != model::TransactionType::Credit
+            || linked_credits.contains(&credit.id.unwrap())
+        {
+            continue;
+        }
+        let description = credit.description.to_lowercase();
+        let purchase = transactions
+            .iter()
+            .filter(|debit| {
+                let days = (credit.posted_date - debit.posted_date).num_days();
+                debit.transaction_type != model::TransactionType::Credit
+                    && debit.description.to_lowercase() == description
+                    && fits(credit.credit_amount, unrefunded[&debit.id.unwrap()])
+                    && days >= 0
+                    && days <= max_days
+            })
+            .max_by_key(|debit| (debit.posted_date, debit.id));
+        if let Some(purchase) = purchase {
+            *unrefunded.get_mut(&purchase.id.unwrap()).unwrap() -= credit.credit_amount;
+            candidates.push(RefundCandidate {
+                purchase: purchase.clone(),
+                refund: credit.clone(),
+            });
+        }
+    }
+    Ok(candidates)
+}
+
+pub fn link_refund(
+    db: &Box<dyn StingyDatabase>,
+    purchase_id: i64,
+    refund_id: i64,
+) -> Result<model::Refund> {
+    let transactions: Vec<model::Transaction> = db.get_all()?;
+    let purchase = get_transaction(&transactions, purchase_id)?;
+    let refund = get_transaction(&transactions, refund_id)?;
+    if purchase.transaction_type == model::TransactionType::Credit {
+        bail!("transaction {purchase_id} is not a debit.");
+    }
+    if refund.transaction_type != model::TransactionType::Credit {
+        bail!("transaction {refund_id} is not a credit.");
+    }
+    // If the refund is already linked, inserting it fails below.
+    let refunds: Vec<model::Refund> = db.get_all()?;
+    let refunds: Vec<_> = refunds
+        .into_iter()
+        .filter(|r| r.credit_transaction_id != refund_id)
+        .collect();
+    let unrefunded = unrefunded_amounts(&transactions, &refunds)?[&purchase_id];
+    if !fits(refund.credit_amount, unrefunded) {
+        bail!(
+            "transaction {refund_id} is larger than what is left to refund of transaction {purchase_id} ({}).",
+            unrefunded.to_output_format()
+        );
+    }
+    let model = model::Refund {
+        id: None,
+        debit_transaction_id: purchase_id,
+        credit_transaction_id: refund_id,
+    };
+    match db.insert(model)? {
+        NewOrExisting::New(model) => Ok(model),
+        NewOrExisting::Existing => bail!("transaction {refund_id} is already linked as a refund."),
+    }
+}
+
+pub fn unlink_refund(db: &Box<dyn StingyDatabase>, id: &str) -> Result<usize> {
+    let mut model = model::Refund::default();
+    model.id = Some(id.parse().map_err(|_| anyhow!("id is not a number"))?);
+    db.delete(model)
+}
+
+#[cfg(test)]
+mod refunds_tests {
+    use super::*;
+    use crate::commands::query::command_query;
+    use crate::commands::tags::{add_tag_rule, TagRuleAttributes};
+    use crate::database::open_stingy_testing_database;
+    use crate::output::OutputForTesting;
+    use crate::PreparedQuery;
+    use chrono::NaiveDate;
+    use std::io::Cursor;
+
+    fn insert_refund(db: &Box<dyn StingyDatabase>, description: &str, amount: f64) -> i64 {
+        let transaction = model::Transaction {
+            id: None,
+            account_name: "000000 - 00000000".to_string(),
+            posted_date: NaiveDate::from_ymd_opt(2021, 03, 02).unwrap(),
+            description: description.to_string(),
+            debit_amount: 0.0,
+            credit_amount: amount,
+            balance: 9000.0,
+            transaction_type: model::TransactionType::Credit,
+            currency: "EUR".to_string(),
+        };
+        match db.insert(transaction).unwrap() {
+            NewOrExisting::New(transaction) => transaction.id.unwrap(),
+            NewOrExisting::Existing => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn find_candidates() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let refund_id = insert_refund(&db, "food order 1", 5.0);
+        // Larger than the purchase, so not a refund.
+        insert_refund(&db, "FOOD ORDER 2", 30.0);
+
+        let candidates = find_refund_candidates(&db, 30).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].purchase.id, Some(3));
+        assert_eq!(candidates[0].refund.id, Some(refund_id));
+
+        // The purchase was 4 days before the refund.
+        assert_eq!(find_refund_candidates(&db, 3).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn find_candidates_prefers_most_recent_purchase() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let refund_id = insert_refund(&db, "GROCERIES", 10.0);
+
+        let candidates = find_refund_candidates(&db, 30).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].purchase.id, Some(6));
+        assert_eq!(candidates[0].refund.id, Some(refund_id));
+    }
+
+    #[test]
+    fn linked_refunds_are_not_candidates() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let refund_id = insert_refund(&db, "FOOD ORDER 1", 5.0);
+
+        link_refund(&db, 3, refund_id).unwrap();
+        assert_eq!(find_refund_candidates(&db, 30).unwrap().len(), 0);
+        // A refund can only be linked once.
+        assert!(link_refund(&db, 4, refund_id).is_err());
+    }
+
+    #[test]
+    fn partially_refunded_purchases_are_candidates_for_the_rest() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let first_refund_id = insert_refund(&db, "FOOD ORDER 1", 6.0);
+        let second_refund_id = insert_refund(&db, "FOOD ORDER 1", 5.5);
+
+        // Both refunds together are more than the purchase, so only the first one is proposed.
+        let candidates = find_refund_candidates(&db, 30).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].refund.id, Some(first_refund_id));
+
+        link_refund(&db, 3, first_refund_id).unwrap();
+        assert_eq!(find_refund_candidates(&db, 30).unwrap().len(), 0);
+        let third_refund_id = insert_refund(&db, "FOOD ORDER 1", 4.0);
+        let candidates = find_refund_candidates(&db, 30).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].refund.id, Some(third_refund_id));
+
+        // The purchase is fully refunded.
+        link_refund(&db, 3, third_refund_id).unwrap();
+        insert_refund(&db, "FOOD ORDER 1", 1.0);
+        assert_eq!(find_refund_candidates(&db, 30).unwrap().len(), 0);
+        assert!(link_refund(&db, 3, second_refund_id).is_err());
+    }
+
+    #[test]
+    fn link_rejects_refunds_larger_than_the_purchase() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let first_refund_id = insert_refund(&db, "FOOD ORDER 1", 7.0);
+        let second_refund_id = insert_refund(&db, "FOOD ORDER 1", 3.1);
+        let third_refund_id = insert_refund(&db, "FOOD ORDER 1", 3.0);
+        assert!(link_refund(&db, 3, insert_refund(&db, "FOOD ORDER 1", 10.01)).is_err());
+
+        link_refund(&db, 3, first_refund_id).unwrap();
+        assert!(link_refund(&db, 3, second_refund_id).is_err());
+        link_refund(&db, 3, third_refund_id).unwrap();
+        assert_eq!(list_refunds(&db).unwrap().rows.len(), 2);
+    }
+
+    #[test]
+    fn link_checks_transaction_types() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let refund_id = insert_refund(&db, "FOOD ORDER 1", 5.0);
+
+        assert!(link_refund(&db, refund_id, 3).is_err());
+        assert!(link_refund(&db, 3, 4).is_err());
+        assert!(link_refund(&db, 3, 1000).is_err());
+    }
+
+    #[test]
+    fn list_and_unlink() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let refund_id = insert_refund(&db, "FOOD ORDER 1", 5.0);
+        link_refund(&db, 3, refund_id).unwrap();
+
+        let result = list_refunds(&db).unwrap();
+        assert_eq!(result.columns, vec!["ID", "Purchase", "Refund"]);
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                "1",
+                "2021/02/26 FOOD ORDER 1 (10.00)",
+                "2021/03/02 FOOD ORDER 1 (5.00)"
+            ]]
+        );
+
+        assert_eq!(unlink_refund(&db, "1").unwrap(), 1);
+        assert_eq!(list_refunds(&db).unwrap().rows.len(), 0);
+        assert!(unlink_refund(&db, "one").is_err());
+    }
+
+    #[test]
+    fn by_tag_nets_refund_against_purchase_tag() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_tag_rule(
+            &db,
+            "food",
+            &TagRuleAttributes {
+                description_contains: Some("FOOD ORDER".to_string()),
+                transaction_type: Some(model::TransactionType::Debit),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let refund_id = insert_refund(&db, "FOOD ORDER 1", 5.0);
+        link_refund(&db, 3, refund_id).unwrap();
+
+        let output_for_testing = command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::ByTag {
+                transaction_type: None,
+                depth: None,
+                tree: false,
+                table: true,
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            vec!["000000 - 00000000"],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Table((_, rows))) = output_for_testing {
+            assert_eq!(rows.len(), 2);
+            // The refund is no longer a credit, and reduces the food debits.
+            assert_eq!(rows[0], vec!["", "82.33", "60.99", "1000.00", "100.00"]);
+            assert_eq!(rows[1], vec!["food", "52.65", "39.01", "0.00", "0.00"]);
+        } else {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn by_time_nets_refund_against_purchase_window() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let refund_id = insert_refund(&db, "FOOD ORDER 1", 5.0);
+        link_refund(&db, 3, refund_id).unwrap();
+
+        let output_for_testing = command_query(
+            &db,
+            &mut Cursor::new(vec![]),
+            &PreparedQuery::ByTime {
+                aggregate: crate::TimeAggregation::Month,
+                table: true,
+            },
+            &vec![],
+            &vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            vec!["000000 - 00000000"],
+        )
+        .unwrap();
+        if let Some(OutputForTesting::Table((_, rows))) = output_for_testing {
+            assert_eq!(rows.len(), 2);
+            // The refund is in March, but it reduces February's debits.
+            assert_eq!(rows[0][1], "2021/03");
+            assert_eq!(rows[0][2], "0.00");
+            assert_eq!(rows[0][3], "67.76");
+            assert_eq!(rows[1][1], "2021/02");
+            assert_eq!(rows[1][2], "1000.00");
+            assert_eq!(rows[1][3], "67.22");
+        } else {
+            unimplemented!()
+        }
+    }
+
+    #[test]
+    fn undo_link() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let refund_id = insert_refund(&db, "FOOD ORDER 1", 5.0);
+
+        crate::commands::undo::begin_undo_step(&db, "link").unwrap();
+        link_refund(&db, 3, refund_id).unwrap();
+        crate::commands::undo::command_undo(&db).unwrap();
+        assert_eq!(list_refunds(&db).unwrap().rows.len(), 0);
+    }
+}
@@ -1,6 +1,15 @@
-use crate::database::{model, NewOrExisting, StingyDatabase};
+use crate::database::{
+    amount_range_min, is_signed_amount_range, model, NewOrExisting, QueryFilters, QueryOrder,
+    StingyDatabase,
+};
+use crate::output::format::ToOutputFormat;
 use anyhow::{anyhow, bail, Result};
-use chrono::NaiveDate;
+use chrono::{NaiveDate, Weekday};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
 
 #[derive(Debug)]
 pub struct ListTagRulesResult {
@@ -48,40 +57,230 @@ pub enum AddTagRuleResult {
     },
 }
 
-// FIXME This would look better with a builder object instead of a bunch
-// of Optionals.
-pub fn add_tag_rule(
+/// A condition in a condition group of a composite tag rule.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Condition {
+    DescriptionContains(String),
+    DescriptionRegex(String),
+    Account(String),
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    /// Parse a condition written as '<attribute>=<value>', e.g. 'description-contains=TESCO'.
+    fn from_str(condition: &str) -> Result<Self> {
+        match condition.split_once('=') {
+            Some(("description-contains", value)) => {
+                Ok(Condition::DescriptionContains(value.to_string()))
+            }
+            Some(("description-regex", value)) => {
+                Ok(Condition::DescriptionRegex(value.to_string()))
+            }
+            Some(("account", value)) => Ok(Condition::Account(value.to_string())),
+            _ => bail!(
+                "'{condition}' is not a condition. Use 'description-contains=<text>', \
+                 'description-regex=<regex>' or 'account=<account>'."
+            ),
+        }
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::DescriptionContains(value) => write!(f, "description-contains={value}"),
+            Condition::DescriptionRegex(value) => write!(f, "description-regex={value}"),
+            Condition::Account(value) => write!(f, "account={value}"),
+        }
+    }
+}
+
+/// Parse comma-separated conditions, e.g. 'description-contains=TESCO,account=joint'. Commas
+/// that aren't followed by another condition are part of the value, as in 'description-regex=\d{1,3}'.
+pub fn parse_conditions(conditions: &str) -> Result<Vec<Condition>> {
+    let mut parts: Vec<String> = vec![];
+    for part in conditions.split(',') {
+        let starts_condition = ["description-contains=", "description-regex=", "account="]
+            .iter()
+            .any(|prefix| part.starts_with(prefix));
+        match parts.last_mut() {
+            Some(last) if !starts_condition => {
+                last.push(',');
+                last.push_str(part);
+            }
+            _ => parts.push(part.to_string()),
+        }
+    }
+    parts.iter().map(|p| Condition::from_str(p)).collect()
+}
+
+/// A group of conditions in a composite tag rule. A composite tag rule only matches a transaction
+/// if all of its condition groups match.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConditionGroup {
+    /// Matches if at least one of the conditions matches.
+    AnyOf(Vec<Condition>),
+    /// Matches if none of the conditions match.
+    NoneOf(Vec<Condition>),
+}
+
+// The rule stores the account name, so it keeps working if the alias changes.
+fn resolve_account(db: &Box<dyn StingyDatabase>, account_or_alias: &str) -> Result<model::Account> {
+    let accounts = crate::commands::accounts::get_account_or_selected(db, Some(account_or_alias))?;
+    if accounts.len() > 1 {
+        bail!("'{account_or_alias}' matches more than one account.");
+    }
+    Ok(accounts[0].clone())
+}
+
+fn validate_regex(regex: &str) -> Result<()> {
+    // Fail early, rather than when the tag rule is evaluated.
+    Regex::new(regex)
+        .map(|_| ())
+        .map_err(|e| anyhow!("invalid regular expression: {e}"))
+}
+
+fn weekday_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "Monday",
+        Weekday::Tue => "Tuesday",
+        Weekday::Wed => "Wednesday",
+        Weekday::Thu => "Thursday",
+        Weekday::Fri => "Friday",
+        Weekday::Sat => "Saturday",
+        Weekday::Sun => "Sunday",
+    }
+}
+
+/// Resolve a condition into the attribute and value stored in the database, and its description.
+fn resolve_condition(
+    db: &Box<dyn StingyDatabase>,
+    condition: &Condition,
+    negated: bool,
+) -> Result<(&'static str, String, String)> {
+    match (condition, negated) {
+        (Condition::DescriptionContains(dc), false) => Ok((
+            "description_contains",
+            dc.clone(),
+            format!("the description contains '{dc}'"),
+        )),
+        (Condition::DescriptionContains(dc), true) => Ok((
+            "description_contains",
+            dc.clone(),
+            format!("the description doesn't contain '{dc}'"),
+        )),
+        (Condition::DescriptionRegex(dr), _) => {
+            validate_regex(dr)?;
+            Ok((
+                "description_regex",
+                dr.clone(),
+                if negated {
+                    format!("the description doesn't match the regex '{dr}'")
+                } else {
+                    format!("the description matches the regex '{dr}'")
+                },
+            ))
+        }
+        (Condition::Account(account_or_alias), _) => {
+            let account = resolve_account(db, account_or_alias)?;
+            let name = account.alias.as_ref().unwrap_or(&account.name);
+            Ok((
+                "account",
+                account.name.clone(),
+                if negated {
+                    format!("the account isn't '{name}'")
+                } else {
+                    format!("the account is '{name}'")
+                },
+            ))
+        }
+    }
+}
+
+/// The attributes of a tag rule, all of which must match a transaction for the tag rule to tag
+/// it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TagRuleAttributes {
+    pub transaction_id: Option<usize>,
+    pub account: Option<String>,
+    pub description_contains: Option<String>,
+    pub description_regex: Option<String>,
+    pub description_not_contains: Option<String>,
+    pub description_not_regex: Option<String>,
+    pub transaction_type: Option<model::TransactionType>,
+    pub amount_min: Option<f64>,
+    pub amount_max: Option<f64>,
+    pub from_date: Option<NaiveDate>,
+    pub to_date: Option<NaiveDate>,
+    /// A range of days of the month, which wraps around the end of the month when the minimum is
+    /// larger than the maximum.
+    pub day_of_month_min: Option<u32>,
+    pub day_of_month_max: Option<u32>,
+    pub day_of_week: Option<Weekday>,
+    /// Months are counted from the start of the period, or from January if there is none.
+    pub every_n_months: Option<u32>,
+    pub condition_groups: Vec<ConditionGroup>,
+}
+
+/// The condition groups of a tag rule, as stored in the database: an operator, and the attribute
+/// and value of each condition.
+type ResolvedConditionGroups = Vec<(&'static str, Vec<(&'static str, String)>)>;
+
+/// Build the model for a tag rule, with its human-readable description, and its condition groups.
+fn build_tag_rule(
     db: &Box<dyn StingyDatabase>,
     tag: &str,
-    transaction_id: Option<usize>,
-    description_contains: Option<&str>,
-    transaction_type: Option<model::TransactionType>,
-    amount_min: Option<f64>,
-    amount_max: Option<f64>,
-    from: Option<NaiveDate>,
-    to: Option<NaiveDate>,
-) -> Result<AddTagRuleResult> {
+    attributes: &TagRuleAttributes,
+) -> Result<(model::TagRule, ResolvedConditionGroups)> {
     let mut human_readable = Vec::new();
 
-    if let Some(tid) = transaction_id {
+    if let Some(tid) = attributes.transaction_id {
         human_readable.push(format!("the transaction id is '{tid}'"));
     }
 
-    if let Some(dc) = description_contains {
+    let account = match attributes.account {
+        Some(ref account_or_alias) => {
+            let account = resolve_account(db, account_or_alias)?;
+            human_readable.push(format!(
+                "the account is '{}'",
+                account.alias.as_ref().unwrap_or(&account.name)
+            ));
+            Some(account)
+        }
+        None => None,
+    };
+
+    if let Some(ref dc) = attributes.description_contains {
         human_readable.push(format!("the description contains '{dc}'"));
     }
+    if let Some(ref dr) = attributes.description_regex {
+        validate_regex(dr)?;
+        human_readable.push(format!("the description matches the regex '{dr}'"));
+    }
+    if let Some(ref dnc) = attributes.description_not_contains {
+        human_readable.push(format!("the description doesn't contain '{dnc}'"));
+    }
+    if let Some(ref dnr) = attributes.description_not_regex {
+        validate_regex(dnr)?;
+        human_readable.push(format!("the description doesn't match the regex '{dnr}'"));
+    }
+    let amount_min = amount_range_min(attributes.amount_min);
+    let amount = if is_signed_amount_range(amount_min, attributes.amount_max) {
+        "the signed amount (negative for debits)"
+    } else {
+        "the amount"
+    };
     if let Some(amin) = amount_min {
-        if amin != f64::MIN {
-            human_readable.push(format!("the amount is larger or equal to '{amin}'"));
-        }
+        human_readable.push(format!("{amount} is larger or equal to '{amin}'"));
     }
-    if let Some(amax) = amount_max {
+    if let Some(amax) = attributes.amount_max {
         if amax != f64::MAX {
-            human_readable.push(format!("the amount is smaller than '{amax}'"));
+            human_readable.push(format!("{amount} is smaller than '{amax}'"));
         }
     }
 
-    match (from, to) {
+    match (attributes.from_date, attributes.to_date) {
         (Some(from), None) => {
             if from != NaiveDate::MIN {
                 human_readable.push(format!("the date is after {}", from.format("%Y/%m/%d")));
@@ -102,10 +301,71 @@ pub fn add_tag_rule(
         _ => {}
     }
 
+    match (attributes.day_of_month_min, attributes.day_of_month_max) {
+        (None, None) => {}
+        (Some(min), Some(max)) if (1..=31).contains(&min) && (1..=31).contains(&max) => {
+            human_readable.push(if min == max {
+                format!("the day of the month is {min}")
+            } else if min < max {
+                format!("the day of the month is between {min} and {max}")
+            } else {
+                format!("the day of the month is {min} or later, or {max} or earlier")
+            });
+        }
+        _ => bail!("days of the month must be between 1 and 31."),
+    }
+    if let Some(day_of_week) = attributes.day_of_week {
+        human_readable.push(format!(
+            "the day of the week is {}",
+            weekday_name(day_of_week)
+        ));
+    }
+    match attributes.every_n_months {
+        None => {}
+        Some(0) => bail!("the number of months must be at least 1."),
+        Some(n) => human_readable.push(format!(
+            "the month is every {n} month(s) counting from {}",
+            match attributes.from_date {
+                Some(from) if from != NaiveDate::MIN => from.format("%Y/%m").to_string(),
+                _ => "January".to_string(),
+            }
+        )),
+    }
+
+    let mut groups = vec![];
+    for condition_group in &attributes.condition_groups {
+        let (operator, conditions, negated) = match condition_group {
+            ConditionGroup::AnyOf(conditions) => ("any", conditions, false),
+            ConditionGroup::NoneOf(conditions) => ("none", conditions, true),
+        };
+        if conditions.is_empty() {
+            bail!("condition groups can't be empty.");
+        }
+        let conditions = conditions
+            .iter()
+            .map(|c| resolve_condition(db, c, negated))
+            .collect::<Result<Vec<_>>>()?;
+        let descriptions: Vec<&str> = conditions.iter().map(|(_, _, d)| d.as_str()).collect();
+        human_readable.push(match (operator, descriptions.len()) {
+            ("any", 1) | ("none", _) => descriptions.join(", and "),
+            _ => format!("either {}", descriptions.join(", or ")),
+        });
+        groups.push((
+            operator,
+            conditions
+                .into_iter()
+                .map(|(attribute, value, _)| (attribute, value))
+                .collect(),
+        ));
+    }
+
     let human_readable = format!(
         "Apply tag '{tag}' to {} transactions where {}.",
-        if let Some(ref tt) = transaction_type {
-            format!("{tt:?} ")
+        if let Some(ref tt) = attributes.transaction_type {
+            match tt {
+                model::TransactionType::DirectDebit => "Direct Debit".to_string(),
+                _ => format!("{tt:?}"),
+            }
         } else {
             "any".to_string()
         },
@@ -116,15 +376,71 @@ pub fn add_tag_rule(
         id: None,
         tag: tag.to_string(),
         human_readable: human_readable,
-        transaction_id: transaction_id.map(|t| t as i64),
-        description_contains: description_contains.map(|s| s.to_string()),
-        transaction_type: transaction_type,
-        amount_min: amount_min,
-        amount_max: amount_max,
-        from_date: from,
-        to_date: to,
+        transaction_id: attributes.transaction_id.map(|t| t as i64),
+        account: account.map(|a| a.name),
+        description_contains: attributes.description_contains.clone(),
+        description_regex: attributes.description_regex.clone(),
+        description_not_contains: attributes.description_not_contains.clone(),
+        description_not_regex: attributes.description_not_regex.clone(),
+        transaction_type: attributes.transaction_type.clone(),
+        amount_min,
+        amount_max: attributes.amount_max,
+        from_date: attributes.from_date,
+        to_date: attributes.to_date,
+        day_of_month_min: attributes.day_of_month_min.map(|d| d as i64),
+        day_of_month_max: attributes.day_of_month_max.map(|d| d as i64),
+        day_of_week: attributes
+            .day_of_week
+            .map(|d| d.num_days_from_sunday() as i64),
+        every_n_months: attributes.every_n_months.map(|n| n as i64),
     };
+    Ok((model, groups))
+}
+
+fn insert_condition_groups(
+    db: &Box<dyn StingyDatabase>,
+    tag_rule_id: i64,
+    groups: ResolvedConditionGroups,
+) -> Result<()> {
+    for (operator, conditions) in groups {
+        let group = model::TagRuleConditionGroup {
+            id: None,
+            tag_rule_id,
+            operator: operator.to_string(),
+        };
+        let NewOrExisting::New(group) = db.insert(group)? else {
+            unreachable!("condition groups have no unique constraints");
+        };
+        for (attribute, value) in conditions {
+            db.insert(model::TagRuleCondition {
+                id: None,
+                group_id: group.id.unwrap(),
+                attribute: attribute.to_string(),
+                value,
+            })?;
+        }
+    }
+    Ok(())
+}
+
+fn delete_condition_groups(db: &Box<dyn StingyDatabase>, tag_rule_id: i64) -> Result<()> {
+    let groups: Vec<model::TagRuleConditionGroup> = db.get_all()?;
+    let conditions: Vec<model::TagRuleCondition> = db.get_all()?;
+    for group in groups.into_iter().filter(|g| g.tag_rule_id == tag_rule_id) {
+        for condition in conditions.iter().filter(|c| Some(c.group_id) == group.id) {
+            db.delete(condition.clone())?;
+        }
+        db.delete(group)?;
+    }
+    Ok(())
+}
 
+pub fn add_tag_rule(
+    db: &Box<dyn StingyDatabase>,
+    tag: &str,
+    attributes: &TagRuleAttributes,
+) -> Result<AddTagRuleResult> {
+    let (model, groups) = build_tag_rule(db, tag, attributes)?;
     match db.lookup_tag_rule(&model)? {
         Some(tag_rule_id) => Ok(AddTagRuleResult::NotUnique {
             tag_rule_id: tag_rule_id,
@@ -132,6 +448,7 @@ pub fn add_tag_rule(
         None => {
             if let NewOrExisting::New(model) = db.insert(model)? {
                 let tag_rule_id = model.id.unwrap();
+                insert_condition_groups(db, tag_rule_id, groups)?;
                 let tagged_transactions =
                     db.count_matching_transactions(&format!("{tag_rule_id}"))?;
                 Ok(AddTagRuleResult::Added {
@@ -145,449 +462,3340 @@ pub fn add_tag_rule(
     }
 }
 
+/// The attributes of a tag rule that can be cleared when editing it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TagRuleAttribute {
+    TransactionId,
+    Account,
+    DescriptionContains,
+    DescriptionRegex,
+    DescriptionNotContains,
+    DescriptionNotRegex,
+    TransactionType,
+    AmountRange,
+    Period,
+    DayOfMonth,
+    DayOfWeek,
+    EveryNMonths,
+    ConditionGroups,
+}
+
+#[derive(PartialEq, Debug)]
+pub enum EditTagRuleResult {
+    Edited { tagged_transactions: usize },
+    NotUnique { tag_rule_id: i64 },
+}
+
+/// Get the attributes of an existing tag rule, including its condition groups.
+pub fn get_tag_rule_attributes(
+    db: &Box<dyn StingyDatabase>,
+    tag_rule: &model::TagRule,
+) -> Result<TagRuleAttributes> {
+    let mut groups: Vec<model::TagRuleConditionGroup> = db.get_all()?;
+    groups.retain(|g| Some(g.tag_rule_id) == tag_rule.id);
+    groups.sort_by_key(|g| g.id);
+    let mut conditions: Vec<model::TagRuleCondition> = db.get_all()?;
+    conditions.sort_by_key(|c| c.id);
+
+    let mut condition_groups = vec![];
+    for group in groups {
+        let group_conditions = conditions
+            .iter()
+            .filter(|c| Some(c.group_id) == group.id)
+            .map(|c| match c.attribute.as_str() {
+                "description_contains" => Ok(Condition::DescriptionContains(c.value.clone())),
+                "description_regex" => Ok(Condition::DescriptionRegex(c.value.clone())),
+                "account" => Ok(Condition::Account(c.value.clone())),
+                attribute => Err(anyhow!("unknown condition attribute '{attribute}'")),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        condition_groups.push(if group.operator == "any" {
+            ConditionGroup::AnyOf(group_conditions)
+        } else {
+            ConditionGroup::NoneOf(group_conditions)
+        });
+    }
+
+    Ok(TagRuleAttributes {
+        transaction_id: tag_rule.transaction_id.map(|t| t as usize),
+        account: tag_rule.account.clone(),
+        description_contains: tag_rule.description_contains.clone(),
+        description_regex: tag_rule.description_regex.clone(),
+        description_not_contains: tag_rule.description_not_contains.clone(),
+        description_not_regex: tag_rule.description_not_regex.clone(),
+        transaction_type: tag_rule.transaction_type.clone(),
+        amount_min: tag_rule.amount_min,
+        amount_max: tag_rule.amount_max,
+        from_date: tag_rule.from_date,
+        to_date: tag_rule.to_date,
+        day_of_month_min: tag_rule.day_of_month_min.map(|d| d as u32),
+        day_of_month_max: tag_rule.day_of_month_max.map(|d| d as u32),
+        day_of_week: tag_rule
+            .day_of_week
+            .map(|d| Weekday::try_from(((d + 6) % 7) as u8))
+            .transpose()
+            .map_err(|_| anyhow!("invalid day of the week in tag rule"))?,
+        every_n_months: tag_rule.every_n_months.map(|n| n as u32),
+        condition_groups,
+    })
+}
+
+/// Replace the tag rule with the given id, and its condition groups, keeping the id.
+fn replace_tag_rule(
+    db: &Box<dyn StingyDatabase>,
+    id: i64,
+    mut model: model::TagRule,
+    groups: ResolvedConditionGroups,
+) -> Result<()> {
+    model.id = Some(id);
+    // Updating the tag rule and its condition groups re-evaluates it.
+    delete_condition_groups(db, id)?;
+    db.update(&model)?;
+    insert_condition_groups(db, id, groups)
+}
+
+/// Edit a tag rule in place, setting the attributes that are set in `set` and removing the ones
+/// in `clear`. The other attributes are kept.
+pub fn edit_tag_rule(
+    db: &Box<dyn StingyDatabase>,
+    id: &str,
+    tag: Option<&str>,
+    set: &TagRuleAttributes,
+    clear: &[TagRuleAttribute],
+) -> Result<EditTagRuleResult> {
+    let id: i64 = id.parse().map_err(|_| anyhow!("id is not a number"))?;
+    let tag_rules: Vec<model::TagRule> = db.get_all()?;
+    let tag_rule = tag_rules
+        .into_iter()
+        .find(|tr| tr.id == Some(id))
+        .ok_or(anyhow!("tag rule {id} not found."))?;
+
+    let mut attributes = get_tag_rule_attributes(db, &tag_rule)?;
+    for attribute in clear {
+        match attribute {
+            TagRuleAttribute::TransactionId => attributes.transaction_id = None,
+            TagRuleAttribute::Account => attributes.account = None,
+            TagRuleAttribute::DescriptionContains => attributes.description_contains = None,
+            TagRuleAttribute::DescriptionRegex => attributes.description_regex = None,
+            TagRuleAttribute::DescriptionNotContains => attributes.description_not_contains = None,
+            TagRuleAttribute::DescriptionNotRegex => attributes.description_not_regex = None,
+            TagRuleAttribute::TransactionType => attributes.transaction_type = None,
+            TagRuleAttribute::AmountRange => {
+                attributes.amount_min = None;
+                attributes.amount_max = None;
+            }
+            TagRuleAttribute::Period => {
+                attributes.from_date = None;
+                attributes.to_date = None;
+            }
+            TagRuleAttribute::DayOfMonth => {
+                attributes.day_of_month_min = None;
+                attributes.day_of_month_max = None;
+            }
+            TagRuleAttribute::DayOfWeek => attributes.day_of_week = None,
+            TagRuleAttribute::EveryNMonths => attributes.every_n_months = None,
+            TagRuleAttribute::ConditionGroups => attributes.condition_groups.clear(),
+        }
+    }
+
+    if set.transaction_id.is_some() {
+        attributes.transaction_id = set.transaction_id;
+    }
+    if set.account.is_some() {
+        attributes.account = set.account.clone();
+    }
+    if set.description_contains.is_some() {
+        attributes.description_contains = set.description_contains.clone();
+    }
+    if set.description_regex.is_some() {
+        attributes.description_regex = set.description_regex.clone();
+    }
+    if set.description_not_contains.is_some() {
+        attributes.description_not_contains = set.description_not_contains.clone();
+    }
+    if set.description_not_regex.is_some() {
+        attributes.description_not_regex = set.description_not_regex.clone();
+    }
+    if set.transaction_type.is_some() {
+        attributes.transaction_type = set.transaction_type.clone();
+    }
+    // Ranges are replaced as a whole, as they are passed.
+    if set.amount_min.is_some() || set.amount_max.is_some() {
+        attributes.amount_min = set.amount_min;
+        attributes.amount_max = set.amount_max;
+    }
+    if set.from_date.is_some() || set.to_date.is_some() {
+        attributes.from_date = set.from_date;
+        attributes.to_date = set.to_date;
+    }
+    if set.day_of_month_min.is_some() || set.day_of_month_max.is_some() {
+        attributes.day_of_month_min = set.day_of_month_min;
+        attributes.day_of_month_max = set.day_of_month_max;
+    }
+    if set.day_of_week.is_some() {
+        attributes.day_of_week = set.day_of_week;
+    }
+    if set.every_n_months.is_some() {
+        attributes.every_n_months = set.every_n_months;
+    }
+    if !set.condition_groups.is_empty() {
+        attributes.condition_groups = set.condition_groups.clone();
+    }
+    if attributes == TagRuleAttributes::default() {
+        bail!("a tag rule needs at least one attribute.");
+    }
+
+    let (model, groups) = build_tag_rule(db, tag.unwrap_or(&tag_rule.tag), &attributes)?;
+    match db.lookup_tag_rule(&model)? {
+        Some(tag_rule_id) if tag_rule_id != id => {
+            return Ok(EditTagRuleResult::NotUnique { tag_rule_id });
+        }
+        // The tag rule already has these attributes, so there is nothing to change.
+        Some(_) => {}
+        None => replace_tag_rule(db, id, model, groups)?,
+    }
+    Ok(EditTagRuleResult::Edited {
+        tagged_transactions: db.count_matching_transactions(&format!("{id}"))?,
+    })
+}
+
 pub fn delete_tag_rule(db: &Box<dyn StingyDatabase>, id: &str) -> Result<usize> {
     let mut model = model::TagRule::default();
     model.id = Some(id.parse().map_err(|_| anyhow!("id is not a number"))?);
+
+    // Deleting the tag rule would also delete its condition groups, but we delete them first so
+    // that undoing re-creates the tag rule before them.
+    delete_condition_groups(db, model.id.unwrap())?;
     db.delete(model)
 }
 
-#[cfg(test)]
-mod tags_tests {
-    use super::*;
-    use crate::database::open_stingy_testing_database;
+#[derive(Debug)]
+pub struct TestTagRuleResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+    /// The id of an existing tag rule with the same attributes, if there is one.
+    pub existing_tag_rule_id: Option<i64>,
+    pub matching: usize,
+    /// The number of matching transactions that already have a conflicting tag.
+    pub conflicting: usize,
+    pub debit_total: f64,
+    pub credit_total: f64,
+}
 
-    #[test]
-    fn add_tag_rule_description_contains() {
-        let db = open_stingy_testing_database();
-        db.insert_test_data();
-        let result = add_tag_rule(
-            &db,
-            "test",
-            None,
-            Some("GRoCeRiEs"), // Should be case-insensitive.
-            None,
-            None,
-            None,
-            None,
-            None,
-        )
-        .unwrap();
-        let expected = AddTagRuleResult::Added {
-            tag_rule_id: 1,
-            tagged_transactions: 2,
+/// Whether two tags are in different hierarchies, like 'groceries' and 'travel/food'. A
+/// transaction with both is likely tagged by mistake.
+pub fn tags_conflict(tag: &str, other: &str) -> bool {
+    let top_level = |tag: &str| tag.split('/').next().unwrap_or(tag).to_lowercase();
+    top_level(tag) != top_level(other)
+}
+
+/// List the transactions that a tag rule would tag, with their current tags, without adding it.
+pub fn test_tag_rule(
+    db: &Box<dyn StingyDatabase>,
+    tag: &str,
+    attributes: &TagRuleAttributes,
+) -> Result<TestTagRuleResult> {
+    let mut transactions: Vec<model::Transaction> = db.get_all()?;
+    let accounts: Vec<model::Account> = db.get_all()?;
+    let transactions_tags = db.get_transactions_tags()?;
+
+    let mut existing_tag_rule_id = None;
+    let mut matching_ids = vec![];
+    db.dry_run(&mut || {
+        let tag_rule_id = match add_tag_rule(db, tag, attributes)? {
+            AddTagRuleResult::Added { tag_rule_id, .. } => tag_rule_id,
+            AddTagRuleResult::NotUnique { tag_rule_id } => {
+                existing_tag_rule_id = Some(tag_rule_id);
+                tag_rule_id
+            }
         };
-        assert_eq!(result, expected);
-    }
+        matching_ids = db
+            .get_transactions_tags()?
+            .into_iter()
+            .filter(|tt| tt.tag_rule_id == tag_rule_id)
+            .map(|tt| tt.transaction_id)
+            .collect();
+        Ok(())
+    })?;
 
-    #[test]
-    fn add_tag_rule_transaction_type() {
-        let db = open_stingy_testing_database();
-        db.insert_test_data();
-        let result = add_tag_rule(
-            &db,
-            "test",
-            None,
-            None,
-            Some(model::TransactionType::Debit),
-            None,
-            None,
-            None,
-            None,
-        )
-        .unwrap();
-        let expected = AddTagRuleResult::Added {
-            tag_rule_id: 1,
-            tagged_transactions: 9, // Also matches direct debit.
+    transactions.retain(|t| matching_ids.contains(&t.id.unwrap()));
+    transactions.sort_by_key(|t| (t.posted_date, t.id));
+    let columns = vec![
+        "ID".to_string(),
+        "Date".to_string(),
+        "Account".to_string(),
+        "Description".to_string(),
+        "Type".to_string(),
+        "Amount".to_string(),
+        "Current tags".to_string(),
+    ];
+    let mut result = TestTagRuleResult {
+        columns,
+        rows: vec![],
+        existing_tag_rule_id,
+        matching: transactions.len(),
+        conflicting: 0,
+        debit_total: 0.0,
+        credit_total: 0.0,
+    };
+    for transaction in &transactions {
+        let mut current_tags: Vec<String> = transactions_tags
+            .iter()
+            .filter(|tt| {
+                Some(tt.transaction_id) == transaction.id
+                    && Some(tt.tag_rule_id) != existing_tag_rule_id
+            })
+            .map(|tt| tt.tag.clone())
+            .collect();
+        current_tags.sort();
+        current_tags.dedup();
+        let conflicting = current_tags.iter().any(|t| tags_conflict(tag, t));
+        let amount = if transaction.transaction_type == model::TransactionType::Credit {
+            result.credit_total += transaction.credit_amount;
+            transaction.credit_amount
+        } else {
+            result.debit_total += transaction.debit_amount;
+            transaction.debit_amount
         };
-        assert_eq!(result, expected);
+        let account = accounts
+            .iter()
+            .find(|a| a.name == transaction.account_name)
+            .and_then(|a| a.alias.clone())
+            .unwrap_or(transaction.account_name.clone());
+        let mut current_tags = current_tags.join(", ");
+        if conflicting {
+            result.conflicting += 1;
+            current_tags = format!("{}{current_tags}", crate::WARN);
+        }
+        result.rows.push(vec![
+            format!("{}", transaction.id.unwrap()),
+            transaction.posted_date.to_output_format(),
+            account,
+            transaction.description.clone(),
+            format!("{:?}", transaction.transaction_type),
+            amount.to_output_format(),
+            current_tags,
+        ]);
     }
+    Ok(result)
+}
 
-    #[test]
-    fn add_tag_rule_amount_min() {
-        let db = open_stingy_testing_database();
-        db.insert_test_data();
-        let result =
-            add_tag_rule(&db, "test", None, None, None, Some(20.0), None, None, None).unwrap();
-        let expected = AddTagRuleResult::Added {
-            tag_rule_id: 1,
-            tagged_transactions: 5, // Matches only the top transactions, of any type.
-        };
-        assert_eq!(result, expected);
+/// A tag suggested for untagged transactions from the same merchant.
+#[derive(Debug, PartialEq)]
+pub struct TagSuggestion {
+    /// The merchant, which can be used as the description in a tag rule.
+    pub description_contains: String,
+    pub tag: String,
+    /// The probability of the tag, between 0 and 1.
+    pub confidence: f64,
+    pub transactions: usize,
+    pub amount: f64,
+}
+
+/// The merchant in a description, as its leading words until one with digits, which are usually
+/// references or dates. For example, 'FOOD ORDER 1' and 'FOOD ORDER 22/01' are both 'FOOD ORDER'.
+pub fn merchant(description: &str) -> String {
+    description
+        .split_whitespace()
+        .take_while(|word| !word.chars().any(|c| c.is_ascii_digit()))
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_uppercase()
+}
+
+/// The lowercase words in a description, excluding ones with digits.
+fn tokens(description: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = description
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty() && !token.chars().any(|c| c.is_ascii_digit()))
+        .map(|token| token.to_lowercase())
+        .collect();
+    tokens.sort();
+    tokens.dedup();
+    tokens
+}
+
+/// Suggest tags for untagged transactions, grouped by merchant. The tags are predicted by a naive
+/// Bayes classifier on the words in the descriptions, trained on the tagged transactions.
+pub fn suggest_tags(db: &Box<dyn StingyDatabase>) -> Result<Vec<TagSuggestion>> {
+    let transactions: Vec<model::Transaction> = db.get_all()?;
+    let transactions_tags = db.get_transactions_tags()?;
+
+    // For each tag: the number of transactions with it, and the number of times each word appears
+    // in their descriptions.
+    let mut tag_counts: HashMap<&str, (usize, HashMap<String, usize>)> = HashMap::new();
+    let mut vocabulary: HashSet<String> = HashSet::new();
+    let mut groups: Vec<(String, Vec<&model::Transaction>)> = vec![];
+    for transaction in &transactions {
+        let mut tags: Vec<&str> = transactions_tags
+            .iter()
+            .filter(|tt| Some(tt.transaction_id) == transaction.id)
+            .map(|tt| tt.tag.as_str())
+            .collect();
+        tags.sort();
+        tags.dedup();
+        if tags.is_empty() {
+            let merchant = merchant(&transaction.description);
+            if merchant.is_empty() {
+                continue;
+            }
+            match groups.iter_mut().find(|(m, _)| *m == merchant) {
+                Some((_, group)) => group.push(transaction),
+                None => groups.push((merchant, vec![transaction])),
+            }
+            continue;
+        }
+        for tag in tags {
+            let (count, token_counts) = tag_counts.entry(tag).or_default();
+            *count += 1;
+            for token in tokens(&transaction.description) {
+                *token_counts.entry(token.clone()).or_default() += 1;
+                vocabulary.insert(token);
+            }
+        }
     }
+    let tagged: usize = tag_counts.values().map(|(count, _)| count).sum();
 
-    #[test]
-    fn add_tag_rule_amount_max() {
-        let db = open_stingy_testing_database();
-        db.insert_test_data();
-        let result =
-            add_tag_rule(&db, "test", None, None, None, None, Some(10.0), None, None).unwrap();
-        let expected = AddTagRuleResult::Added {
-            tag_rule_id: 1,
-            tagged_transactions: 5, // 3 Debits, 2 Credits in different accounts.
-        };
-        assert_eq!(result, expected);
+    let mut suggestions = vec![];
+    for (merchant, group) in groups {
+        let group_tokens = tokens(&merchant);
+        if !group_tokens.iter().any(|t| vocabulary.contains(t)) {
+            // There's nothing to learn from.
+            continue;
+        }
+        // The log-probability of each tag, with Laplace smoothing for unseen words.
+        let mut scores: Vec<(&str, f64)> = tag_counts
+            .iter()
+            .map(|(tag, (count, token_counts))| {
+                let total: usize = token_counts.values().sum();
+                let mut score = (*count as f64 / tagged as f64).ln();
+                for token in &group_tokens {
+                    let token_count = token_counts.get(token).copied().unwrap_or(0);
+                    score += ((token_count + 1) as f64 / (total + vocabulary.len()) as f64).ln();
+                }
+                (*tag, score)
+            })
+            .collect();
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1).then(a.0.cmp(b.0)));
+        let (tag, best) = scores[0];
+        let confidence = 1.0 / scores.iter().map(|(_, s)| (s - best).exp()).sum::<f64>();
+        suggestions.push(TagSuggestion {
+            description_contains: merchant,
+            tag: tag.to_string(),
+            confidence,
+            transactions: group.len(),
+            amount: group
+                .iter()
+                .map(|t| t.debit_amount.max(t.credit_amount))
+                .sum(),
+        });
     }
+    suggestions.sort_by(|a, b| b.amount.total_cmp(&a.amount));
+    Ok(suggestions)
+}
 
-    #[test]
-    fn add_tag_rule_from() {
-        let db = open_stingy_testing_database();
-        db.insert_test_data();
-        let result = add_tag_rule(
-            &db,
-            "test",
-            None,
-            None,
-            None,
-            None,
-            None,
-            Some(NaiveDate::from_ymd_opt(2021, 02, 26).unwrap()),
-            None,
-        )
-        .unwrap();
-        let expected = AddTagRuleResult::Added {
-            tag_rule_id: 1,
-            tagged_transactions: 10,
-        };
-        assert_eq!(result, expected);
+/// Add tag rules for the transactions returned by a query with `filters`: a single tag rule with
+/// the same conditions as the filters or, with `snapshot`, a transaction ID tag rule for each
+/// transaction the query currently returns.
+pub fn add_tag_rules_from_query(
+    db: &Box<dyn StingyDatabase>,
+    tag: &str,
+    filters: QueryFilters,
+    snapshot: bool,
+) -> Result<Vec<AddTagRuleResult>> {
+    if snapshot {
+        let debits = filters.transaction_types.is_empty()
+            || filters
+                .transaction_types
+                .iter()
+                .any(|tt| *tt != model::TransactionType::Credit);
+        let credits = filters.transaction_types.is_empty()
+            || filters
+                .transaction_types
+                .contains(&model::TransactionType::Credit);
+        let mut transaction_ids = vec![];
+        if debits {
+            let rows = db
+                .query_debits(filters.clone(), &QueryOrder::default())?
+                .rows;
+            transaction_ids.extend(rows.iter().map(|r| r.transaction_id));
+        }
+        if credits {
+            let rows = db.query_credits(filters, &QueryOrder::default())?.rows;
+            transaction_ids.extend(rows.iter().map(|r| r.transaction_id));
+        }
+        transaction_ids.sort();
+        return transaction_ids
+            .into_iter()
+            .map(|transaction_id| {
+                let attributes = TagRuleAttributes {
+                    transaction_id: Some(transaction_id as usize),
+                    ..Default::default()
+                };
+                add_tag_rule(db, tag, &attributes)
+            })
+            .collect();
     }
 
-    #[test]
-    fn add_tag_rule_to() {
-        let db = open_stingy_testing_database();
-        db.insert_test_data();
-        let result = add_tag_rule(
-            &db,
-            "test",
-            None,
-            None,
-            None,
-            None,
-            None,
-            None,
-            Some(NaiveDate::from_ymd_opt(2021, 02, 26).unwrap()),
-        )
+    if !filters.tags.is_empty() || !filters.not_tags.is_empty() {
+        bail!("tag rules can't match on tags, use a snapshot to tag the transactions currently returned by the query.");
+    }
+    let mut attributes = TagRuleAttributes {
+        description_contains: filters.description_contains,
+        transaction_type: match filters.transaction_types.as_slice() {
+            [] => None,
+            // A tag rule for debits also matches direct debits.
+            [model::TransactionType::Debit, model::TransactionType::DirectDebit]
+            | [model::TransactionType::Debit] => Some(model::TransactionType::Debit),
+            [transaction_type] => Some(transaction_type.clone()),
+            _ => bail!("tag rules can only match a single transaction type."),
+        },
+        amount_min: filters.amount_min,
+        amount_max: filters.amount_max,
+        from_date: filters.date_from,
+        to_date: filters.date_to,
+        ..Default::default()
+    };
+    match filters.accounts.as_slice() {
+        [] => {}
+        [account] => attributes.account = Some(account.clone()),
+        accounts => attributes.condition_groups.push(ConditionGroup::AnyOf(
+            accounts
+                .iter()
+                .map(|account| Condition::Account(account.clone()))
+                .collect(),
+        )),
+    }
+    if attributes == TagRuleAttributes::default() {
+        bail!("a tag rule needs at least one filter.");
+    }
+    Ok(vec![add_tag_rule(db, tag, &attributes)?])
+}
+
+/// Get the transactions without tags that haven't been ignored, largest first.
+pub fn get_untagged_transactions(db: &Box<dyn StingyDatabase>) -> Result<Vec<model::Transaction>> {
+    let mut transactions: Vec<model::Transaction> = db.get_all()?;
+    let transactions_tags = db.get_transactions_tags()?;
+    let ignored: Vec<model::IgnoredTransaction> = db.get_all()?;
+    transactions.retain(|t| {
+        !transactions_tags
+            .iter()
+            .any(|tt| Some(tt.transaction_id) == t.id)
+            && !ignored.iter().any(|i| Some(i.transaction_id) == t.id)
+    });
+    let amount = |t: &model::Transaction| t.debit_amount.max(t.credit_amount);
+    transactions.sort_by(|a, b| amount(b).total_cmp(&amount(a)).then(a.id.cmp(&b.id)));
+    Ok(transactions)
+}
+
+/// Count the transactions whose description contains some text, as a tag rule would match them.
+pub fn count_description_matches(
+    db: &Box<dyn StingyDatabase>,
+    description_contains: &str,
+) -> Result<usize> {
+    let transactions: Vec<model::Transaction> = db.get_all()?;
+    let description_contains = description_contains.to_lowercase();
+    Ok(transactions
+        .iter()
+        .filter(|t| t.description.to_lowercase().contains(&description_contains))
+        .count())
+}
+
+/// Leave a transaction untagged, without offering to tag it again in `get_untagged_transactions`.
+pub fn ignore_transaction(db: &Box<dyn StingyDatabase>, transaction_id: i64) -> Result<()> {
+    let transactions: Vec<model::Transaction> = db.get_all()?;
+    if !transactions.iter().any(|t| t.id == Some(transaction_id)) {
+        bail!("transaction {transaction_id} not found.");
+    }
+    db.insert(model::IgnoredTransaction {
+        id: None,
+        transaction_id,
+    })?;
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RetagResult {
+    /// The number of tag rules whose tag changed.
+    pub tag_rules: usize,
+    /// The number of those tag rules that became equal to another one, and were deleted.
+    pub deleted_tag_rules: usize,
+    /// The number of transactions tagged by those tag rules.
+    pub transactions: usize,
+}
+
+/// Change the tag of every tag rule for which `retag` returns a new tag.
+fn retag_tag_rules(
+    db: &Box<dyn StingyDatabase>,
+    retag: impl Fn(&str) -> Option<String>,
+) -> Result<RetagResult> {
+    let mut tag_rules: Vec<model::TagRule> = db.get_all()?;
+    tag_rules.sort_by_key(|tr| tr.id);
+    let transactions_tags = db.get_transactions_tags()?;
+    let mut result = RetagResult {
+        tag_rules: 0,
+        deleted_tag_rules: 0,
+        transactions: 0,
+    };
+    let mut transaction_ids = vec![];
+    for tag_rule in tag_rules {
+        let Some(tag) = retag(&tag_rule.tag).filter(|tag| *tag != tag_rule.tag) else {
+            continue;
+        };
+        let tag_rule_id = tag_rule.id.unwrap();
+        result.tag_rules += 1;
+        transaction_ids.extend(
+            transactions_tags
+                .iter()
+                .filter(|tt| tt.tag_rule_id == tag_rule_id)
+                .map(|tt| tt.transaction_id),
+        );
+
+        let attributes = get_tag_rule_attributes(db, &tag_rule)?;
+        let (mut model, _) = build_tag_rule(db, &tag, &attributes)?;
+        if db.lookup_tag_rule(&model)?.is_some() {
+            // Another tag rule already tags the same transactions with the new tag.
+            delete_tag_rule(db, &format!("{tag_rule_id}"))?;
+            result.deleted_tag_rules += 1;
+        } else {
+            model.id = Some(tag_rule_id);
+            db.update(&model)?;
+        }
+    }
+    transaction_ids.sort();
+    transaction_ids.dedup();
+    result.transactions = transaction_ids.len();
+    Ok(result)
+}
+
+/// Whether `tag` is `parent`, or one of its sub-tags, like 'travel/paris' for 'travel'.
+fn is_tag_or_subtag(tag: &str, parent: &str) -> bool {
+    let parent = parent.trim_end_matches('/');
+    tag == parent || tag.starts_with(&format!("{parent}/"))
+}
+
+/// Rename a tag in all tag rules, along with its sub-tags: renaming 'travel' (or 'travel/') to
+/// 'trips' also renames 'travel/paris' to 'trips/paris'.
+pub fn rename_tag(db: &Box<dyn StingyDatabase>, old: &str, new: &str) -> Result<RetagResult> {
+    let (old, new) = (old.trim_end_matches('/'), new.trim_end_matches('/'));
+    if old.is_empty() || new.is_empty() {
+        bail!("tags can't be empty.");
+    }
+    retag_tag_rules(db, |tag| {
+        is_tag_or_subtag(tag, old).then(|| format!("{new}{}", &tag[old.len()..]))
+    })
+}
+
+/// Replace several tags, and their sub-tags, with a single tag in all tag rules.
+pub fn merge_tags(
+    db: &Box<dyn StingyDatabase>,
+    tags: &[String],
+    into: &str,
+) -> Result<RetagResult> {
+    if into.is_empty() || tags.iter().any(|t| t.trim_end_matches('/').is_empty()) {
+        bail!("tags can't be empty.");
+    }
+    retag_tag_rules(db, |tag| {
+        tags.iter()
+            .any(|t| is_tag_or_subtag(tag, t))
+            .then(|| into.to_string())
+    })
+}
+
+/// Two tag rules with tags in different hierarchies that tag the same transactions.
+#[derive(Debug, PartialEq)]
+pub struct TagRuleConflict {
+    pub tag_rule_ids: (i64, i64),
+    pub tags: (String, String),
+    pub transactions: usize,
+}
+
+/// A transaction ID tag rule that takes precedence over other tag rules matching its transaction.
+#[derive(Debug, PartialEq)]
+pub struct TagRuleShadowing {
+    pub tag_rule_id: i64,
+    pub transaction_id: i64,
+    pub shadowed_tag_rule_ids: Vec<i64>,
+}
+
+#[derive(Debug)]
+pub struct AuditTagRulesResult {
+    /// Tag rules that don't tag any transactions.
+    pub dead_tag_rules: Vec<model::TagRule>,
+    pub conflicts: Vec<TagRuleConflict>,
+    pub shadowing: Vec<TagRuleShadowing>,
+    /// The number of untagged debits, and of all debits.
+    pub untagged_debits: (usize, usize),
+    /// The amount of untagged debits, and of all debits.
+    pub untagged_debit_amount: (f64, f64),
+}
+
+/// Find tag rules that are likely mistakes or no longer needed, and measure how many debits are
+/// left untagged.
+pub fn audit_tag_rules(db: &Box<dyn StingyDatabase>) -> Result<AuditTagRulesResult> {
+    let mut tag_rules: Vec<model::TagRule> = db.get_all()?;
+    tag_rules.sort_by_key(|tr| tr.id);
+    let transactions: Vec<model::Transaction> = db.get_all()?;
+    let transactions_tags = db.get_transactions_tags()?;
+
+    let dead_tag_rules = tag_rules
+        .iter()
+        .filter(|tr| {
+            !transactions_tags
+                .iter()
+                .any(|tt| Some(tt.tag_rule_id) == tr.id)
+        })
+        .cloned()
+        .collect();
+
+    // The transactions tags are sorted by transaction, and then by tag rule.
+    let mut conflicts: Vec<TagRuleConflict> = vec![];
+    for (i, tt) in transactions_tags.iter().enumerate() {
+        for other in transactions_tags[i + 1..]
+            .iter()
+            .take_while(|other| other.transaction_id == tt.transaction_id)
+            .filter(|other| tags_conflict(&tt.tag, &other.tag))
+        {
+            let tag_rule_ids = (tt.tag_rule_id, other.tag_rule_id);
+            match conflicts
+                .iter_mut()
+                .find(|c| c.tag_rule_ids == tag_rule_ids)
+            {
+                Some(conflict) => conflict.transactions += 1,
+                None => conflicts.push(TagRuleConflict {
+                    tag_rule_ids,
+                    tags: (tt.tag.clone(), other.tag.clone()),
+                    transactions: 1,
+                }),
+            }
+        }
+    }
+    conflicts.sort_by_key(|c| (std::cmp::Reverse(c.transactions), c.tag_rule_ids));
+
+    let matches = db.get_tag_rules_matches()?;
+    let mut shadowing = vec![];
+    for tag_rule in tag_rules.iter().filter(|tr| tr.transaction_id.is_some()) {
+        let transaction_id = tag_rule.transaction_id.unwrap();
+        let shadowed_tag_rule_ids: Vec<i64> = matches
+            .iter()
+            .filter(|m| m.transaction_id == transaction_id)
+            .filter(|m| {
+                tag_rules
+                    .iter()
+                    .any(|tr| tr.id == Some(m.tag_rule_id) && tr.transaction_id.is_none())
+            })
+            .map(|m| m.tag_rule_id)
+            .collect();
+        if !shadowed_tag_rule_ids.is_empty() {
+            shadowing.push(TagRuleShadowing {
+                tag_rule_id: tag_rule.id.unwrap(),
+                transaction_id,
+                shadowed_tag_rule_ids,
+            });
+        }
+    }
+
+    let mut untagged_debits = (0, 0);
+    let mut untagged_debit_amount = (0.0, 0.0);
+    for transaction in &transactions {
+        if transaction.transaction_type == model::TransactionType::Credit {
+            continue;
+        }
+        untagged_debits.1 += 1;
+        untagged_debit_amount.1 += transaction.debit_amount;
+        if !transactions_tags
+            .iter()
+            .any(|tt| Some(tt.transaction_id) == transaction.id)
+        {
+            untagged_debits.0 += 1;
+            untagged_debit_amount.0 += transaction.debit_amount;
+        }
+    }
+
+    Ok(AuditTagRulesResult {
+        dead_tag_rules,
+        conflicts,
+        shadowing,
+        untagged_debits,
+        untagged_debit_amount,
+    })
+}
+
+/// A tag rule in a tag rules file. The attributes are the same as the arguments to
+/// `stingy tags add-rule`, and the id, if present, is used to match the rule to an existing one.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TagRuleEntry {
+    id: Option<i64>,
+    tag: String,
+    transaction_id: Option<usize>,
+    account: Option<String>,
+    description_contains: Option<String>,
+    description_regex: Option<String>,
+    description_not_contains: Option<String>,
+    description_not_regex: Option<String>,
+    transaction_type: Option<String>,
+    amount_range: Option<String>,
+    period: Option<String>,
+    day_of_month: Option<String>,
+    day_of_week: Option<String>,
+    every_n_months: Option<u32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    any_of: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    none_of: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TagRulesFile {
+    #[serde(default)]
+    rules: Vec<TagRuleEntry>,
+}
+
+/// Format a range the way it's passed in the command line, with ':' for unbounded ends.
+fn format_range<T: PartialEq>(
+    lo: Option<T>,
+    hi: Option<T>,
+    (min, max): (T, T),
+    format: impl Fn(&T) -> String,
+) -> Option<String> {
+    if lo.is_none() && hi.is_none() {
+        return None;
+    }
+    let format_part = |part: Option<T>, unbounded: &T| match part {
+        Some(part) if part != *unbounded => format(&part),
+        _ => ":".to_string(),
+    };
+    Some(format!(
+        "{}-{}",
+        format_part(lo, &min),
+        format_part(hi, &max)
+    ))
+}
+
+impl TagRuleEntry {
+    fn new(id: Option<i64>, tag: &str, attributes: &TagRuleAttributes) -> Self {
+        let join_conditions = |conditions: &Vec<Condition>| -> String {
+            conditions
+                .iter()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        let mut entry = TagRuleEntry {
+            id,
+            tag: tag.to_string(),
+            transaction_id: attributes.transaction_id,
+            account: attributes.account.clone(),
+            description_contains: attributes.description_contains.clone(),
+            description_regex: attributes.description_regex.clone(),
+            description_not_contains: attributes.description_not_contains.clone(),
+            description_not_regex: attributes.description_not_regex.clone(),
+            transaction_type: attributes.transaction_type.as_ref().map(|tt| {
+                match tt {
+                    model::TransactionType::Debit => "debit",
+                    model::TransactionType::Credit => "credit",
+                    model::TransactionType::DirectDebit => "direct-debit",
+                }
+                .to_string()
+            }),
+            amount_range: format_range(
+                attributes.amount_min,
+                attributes.amount_max,
+                (f64::MIN, f64::MAX),
+                |amount| format!("{amount}"),
+            ),
+            period: format_range(
+                attributes.from_date,
+                attributes.to_date,
+                (NaiveDate::MIN, NaiveDate::MAX),
+                |date| date.format("%Y/%m/%d").to_string(),
+            ),
+            day_of_month: match (attributes.day_of_month_min, attributes.day_of_month_max) {
+                (Some(min), Some(max)) => Some(format!("{min}-{max}")),
+                _ => None,
+            },
+            day_of_week: attributes
+                .day_of_week
+                .map(|d| weekday_name(d).to_lowercase()),
+            every_n_months: attributes.every_n_months,
+            ..Default::default()
+        };
+        for group in &attributes.condition_groups {
+            match group {
+                ConditionGroup::AnyOf(conditions) => entry.any_of.push(join_conditions(conditions)),
+                ConditionGroup::NoneOf(conditions) => {
+                    entry.none_of.push(join_conditions(conditions))
+                }
+            }
+        }
+        entry
+    }
+
+    fn to_attributes(&self) -> Result<TagRuleAttributes> {
+        let (amount_min, amount_max) = crate::parse_amount_range(self.amount_range.as_deref())
+            .map_err(|e| anyhow!("invalid amount_range: {e}"))?;
+        let (from_date, to_date) = crate::parse_period(self.period.as_deref())
+            .map_err(|e| anyhow!("invalid period: {e}"))?;
+        let (day_of_month_min, day_of_month_max) =
+            crate::parse_day_of_month_range(self.day_of_month.as_deref())
+                .map_err(|e| anyhow!("invalid day_of_month: {e}"))?;
+        let day_of_week = crate::parse_day_of_week(self.day_of_week.as_deref())
+            .map_err(|e| anyhow!("invalid day_of_week: {e}"))?;
+        let transaction_type = match self.transaction_type.as_deref() {
+            None => None,
+            Some("debit") => Some(model::TransactionType::Debit),
+            Some("credit") => Some(model::TransactionType::Credit),
+            Some("direct-debit") => Some(model::TransactionType::DirectDebit),
+            Some(other) => bail!(
+                "invalid transaction_type '{other}', use 'debit', 'credit' or 'direct-debit'."
+            ),
+        };
+        let mut condition_groups = vec![];
+        for conditions in &self.any_of {
+            condition_groups.push(ConditionGroup::AnyOf(parse_conditions(conditions)?));
+        }
+        for conditions in &self.none_of {
+            condition_groups.push(ConditionGroup::NoneOf(parse_conditions(conditions)?));
+        }
+        let attributes = TagRuleAttributes {
+            transaction_id: self.transaction_id,
+            account: self.account.clone(),
+            description_contains: self.description_contains.clone(),
+            description_regex: self.description_regex.clone(),
+            description_not_contains: self.description_not_contains.clone(),
+            description_not_regex: self.description_not_regex.clone(),
+            transaction_type,
+            amount_min,
+            amount_max,
+            from_date,
+            to_date,
+            day_of_month_min,
+            day_of_month_max,
+            day_of_week,
+            every_n_months: self.every_n_months,
+            condition_groups,
+        };
+        if attributes == TagRuleAttributes::default() {
+            bail!("a tag rule needs at least one attribute.");
+        }
+        Ok(attributes)
+    }
+}
+
+/// Export all tag rules, in the format accepted by `plan_tag_rules`.
+pub fn export_tag_rules(db: &Box<dyn StingyDatabase>) -> Result<String> {
+    let mut tag_rules: Vec<model::TagRule> = db.get_all()?;
+    tag_rules.sort_by_key(|tr| tr.id);
+    let mut rules = vec![];
+    for tag_rule in &tag_rules {
+        let attributes = get_tag_rule_attributes(db, tag_rule)?;
+        rules.push(TagRuleEntry::new(tag_rule.id, &tag_rule.tag, &attributes));
+    }
+    toml::to_string(&TagRulesFile { rules }).map_err(|e| anyhow!("failed to export: {e}"))
+}
+
+/// A change to the tag rules in the database, needed to make them match a tag rules file.
+#[derive(Debug)]
+pub struct TagRuleChange {
+    /// The tag rule in the database, or None if the tag rule is added.
+    pub current: Option<model::TagRule>,
+    /// The tag rule in the file, or None if the tag rule is removed.
+    pub new: Option<model::TagRule>,
+    groups: ResolvedConditionGroups,
+}
+
+#[derive(Debug)]
+pub struct TagRulesPlan {
+    pub changes: Vec<TagRuleChange>,
+    pub unchanged: usize,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ApplyTagRulesResult {
+    /// The number of transactions tagged by the tag rule in each change, before and after it.
+    pub tagged_by_change: Vec<(usize, usize)>,
+    /// The number of transactions with at least one tag, before and after all changes.
+    pub tagged: (usize, usize),
+}
+
+/// Compute the changes needed to make the tag rules in the database match the ones in a tag
+/// rules file.
+///
+/// Rules in the file that are equal to a rule in the database are unchanged, regardless of their
+/// id. Otherwise, a rule in the file with the id of an existing rule changes it, and other rules
+/// are added. The remaining rules in the database are removed.
+pub fn plan_tag_rules(db: &Box<dyn StingyDatabase>, contents: &str) -> Result<TagRulesPlan> {
+    let file: TagRulesFile =
+        toml::from_str(contents).map_err(|e| anyhow!("invalid tag rules file: {e}"))?;
+    let mut wanted: Vec<(Option<i64>, model::TagRule, ResolvedConditionGroups)> = vec![];
+    for (i, entry) in file.rules.iter().enumerate() {
+        let (model, groups) = entry
+            .to_attributes()
+            .and_then(|attributes| build_tag_rule(db, &entry.tag, &attributes))
+            .map_err(|e| anyhow!("rule {} in the file: {e}", i + 1))?;
+        if wanted.iter().any(|(_, m, g)| *m == model && *g == groups) {
+            bail!("rule {} in the file is a duplicate of another rule.", i + 1);
+        }
+        if entry.id.is_some() && wanted.iter().any(|(id, _, _)| *id == entry.id) {
+            bail!(
+                "rule {} in the file has the same id as another rule.",
+                i + 1
+            );
+        }
+        wanted.push((entry.id, model, groups));
+    }
+
+    let mut tag_rules: Vec<model::TagRule> = db.get_all()?;
+    tag_rules.sort_by_key(|tr| tr.id);
+    let mut existing = vec![];
+    for tag_rule in tag_rules {
+        // Build the tag rule again, so it can be compared with the ones in the file.
+        let attributes = get_tag_rule_attributes(db, &tag_rule)?;
+        let built = build_tag_rule(db, &tag_rule.tag, &attributes)?;
+        existing.push(Some((tag_rule, built)));
+    }
+
+    let mut unchanged = 0;
+    let mut remaining = vec![];
+    for (id, model, groups) in wanted {
+        let equal = existing.iter().position(|e| {
+            e.as_ref()
+                .is_some_and(|(_, (m, g))| *m == model && *g == groups)
+        });
+        match equal {
+            Some(i) => {
+                existing[i] = None;
+                unchanged += 1;
+            }
+            None => remaining.push((id, model, groups)),
+        }
+    }
+
+    let mut changes = vec![];
+    for (id, model, groups) in remaining {
+        let same_id = existing.iter().position(|e| {
+            e.as_ref()
+                .is_some_and(|(tag_rule, _)| id.is_some() && tag_rule.id == id)
+        });
+        let current = same_id.and_then(|i| existing[i].take()).map(|(tr, _)| tr);
+        changes.push(TagRuleChange {
+            current,
+            new: Some(model),
+            groups,
+        });
+    }
+    for (tag_rule, _) in existing.into_iter().flatten() {
+        changes.push(TagRuleChange {
+            current: Some(tag_rule),
+            new: None,
+            groups: vec![],
+        });
+    }
+    // Show removed and changed tag rules in order, followed by added ones.
+    changes.sort_by_key(|c| (c.current.is_none(), c.current.as_ref().map(|tr| tr.id)));
+    Ok(TagRulesPlan { changes, unchanged })
+}
+
+/// Apply the changes in a plan from `plan_tag_rules`.
+pub fn apply_tag_rules(
+    db: &Box<dyn StingyDatabase>,
+    plan: &TagRulesPlan,
+) -> Result<ApplyTagRulesResult> {
+    let tagged_before = db.count_tagged_transactions()?;
+    let mut tagged_by_change = vec![];
+    for change in &plan.changes {
+        let before = match &change.current {
+            Some(tag_rule) => {
+                db.count_matching_transactions(&format!("{}", tag_rule.id.unwrap()))?
+            }
+            None => 0,
+        };
+        tagged_by_change.push((before, 0));
+    }
+
+    // Remove tag rules first, so they don't conflict with the ones that are changed or added.
+    for change in plan.changes.iter().filter(|c| c.new.is_none()) {
+        let tag_rule_id = change.current.as_ref().unwrap().id.unwrap();
+        delete_tag_rule(db, &format!("{tag_rule_id}"))?;
+    }
+    for (i, change) in plan.changes.iter().enumerate() {
+        let Some(ref model) = change.new else {
+            continue;
+        };
+        if let Some(tag_rule_id) = db.lookup_tag_rule(model)? {
+            bail!(
+                "tag rule {tag_rule_id} conflicts with '{}'",
+                model.human_readable
+            );
+        }
+        let tag_rule_id = match &change.current {
+            Some(current) => {
+                let tag_rule_id = current.id.unwrap();
+                replace_tag_rule(db, tag_rule_id, model.clone(), change.groups.clone())?;
+                tag_rule_id
+            }
+            None => {
+                let NewOrExisting::New(model) = db.insert(model.clone())? else {
+                    bail!("Tag can't be looked up, but also can't be inserted?");
+                };
+                let tag_rule_id = model.id.unwrap();
+                insert_condition_groups(db, tag_rule_id, change.groups.clone())?;
+                tag_rule_id
+            }
+        };
+        tagged_by_change[i].1 = db.count_matching_transactions(&format!("{tag_rule_id}"))?;
+    }
+    Ok(ApplyTagRulesResult {
+        tagged_by_change,
+        tagged: (tagged_before, db.count_tagged_transactions()?),
+    })
+}
+
+#[cfg(test)]
+mod tags_tests {
+    use super::*;
+    use crate::database::open_stingy_testing_database;
+
+    #[test]
+    fn add_tag_rule_description_contains() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_contains: Some("GRoCeRiEs".to_string()), // Should be case-insensitive.
+                ..Default::default()
+            },
+        )
         .unwrap();
         let expected = AddTagRuleResult::Added {
             tag_rule_id: 1,
-            tagged_transactions: 6,
+            tagged_transactions: 2,
         };
         assert_eq!(result, expected);
     }
 
     #[test]
-    fn add_tag_rule_transaction_id() {
+    fn add_tag_rule_description_regex() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_regex: Some(r"^food order [12]$".to_string()), // Should be case-insensitive.
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 2,
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn add_tag_rule_description_regex_word_boundary() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        // Unlike a substring match, this doesn't match "SUBSCRIPTION".
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_regex: Some(r"\bSCRIPT".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 0,
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn add_tag_rule_invalid_description_regex() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        assert!(add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_regex: Some("(food".to_string()),
+                ..Default::default()
+            }
+        )
+        .is_err());
+        assert_eq!(list_tag_rules(&db, None).unwrap().rows.len(), 0);
+    }
+
+    #[test]
+    fn invalid_description_regex_in_database() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        // Bypass the validation in add_tag_rule, so that SQLite evaluates the invalid pattern.
+        let result = db.insert(model::TagRule {
+            tag: "test".to_string(),
+            description_regex: Some("(coffee".to_string()),
+            ..Default::default()
+        });
+        assert!(result.unwrap_err().to_string().contains("unclosed group"));
+        assert_eq!(list_tag_rules(&db, None).unwrap().rows.len(), 0);
+
+        // Other tag rules are still evaluated, both when adding them and when importing.
+        add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_regex: Some("^coffee$".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 2);
+        db.insert(model::Transaction {
+            id: None,
+            account_name: "000000 - 00000000".to_string(),
+            posted_date: NaiveDate::from_ymd_opt(2021, 03, 04).unwrap(),
+            description: "COFFEE".to_string(),
+            debit_amount: 3.0,
+            credit_amount: 0.0,
+            balance: 9849.76,
+            transaction_type: model::TransactionType::Debit,
+            currency: "EUR".to_string(),
+        })
+        .unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 3);
+    }
+
+    #[test]
+    fn add_tag_rule_description_contains_and_regex() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_contains: Some("food".to_string()),
+                description_regex: Some("1|3".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 2,
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn add_tag_rule_signed_amount_range() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        // Debits of more than 20, but not the credits.
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                amount_min: Some(-1000.0),
+                amount_max: Some(-20.0),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 3,
+        };
+        assert_eq!(result, expected);
+        assert_eq!(
+            list_tag_rules(&db, None).unwrap().rows[0][2],
+            "Apply tag 'test' to any transactions where the signed amount (negative for debits) is larger or equal to '-1000', and the signed amount (negative for debits) is smaller than '-20'."
+        );
+
+        // Debits up to 5, and credits under 5.
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                amount_min: Some(-5.0),
+                amount_max: Some(5.0),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 2,
+            tagged_transactions: 4,
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn add_tag_rule_direct_debit() {
         let db = open_stingy_testing_database();
         db.insert_test_data();
-        let result =
-            add_tag_rule(&db, "test", Some(8), None, None, None, None, None, None).unwrap();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                transaction_type: Some(model::TransactionType::DirectDebit),
+                ..Default::default()
+            },
+        )
+        .unwrap();
         let expected = AddTagRuleResult::Added {
             tag_rule_id: 1,
             tagged_transactions: 1,
         };
         assert_eq!(result, expected);
-        assert_eq!(db.count_matching_transactions("1").unwrap(), 1);
+        assert!(list_tag_rules(&db, None).unwrap().rows[0][2]
+            .starts_with("Apply tag 'test' to Direct Debit transactions"));
     }
 
     #[test]
-    fn add_tag_match_after() {
+    fn add_tag_rule_day_of_month() {
         let db = open_stingy_testing_database();
+        db.insert_test_data();
         let result = add_tag_rule(
             &db,
             "test",
-            None,
-            None,
-            None,
-            None,
-            None,
-            None,
-            Some(NaiveDate::from_ymd_opt(2021, 02, 25).unwrap()),
+            &TagRuleAttributes {
+                day_of_month_min: Some(25),
+                day_of_month_max: Some(26),
+                ..Default::default()
+            },
         )
         .unwrap();
         let expected = AddTagRuleResult::Added {
             tag_rule_id: 1,
-            tagged_transactions: 0, // The database is empty.
+            tagged_transactions: 5,
         };
         assert_eq!(result, expected);
 
-        db.insert_test_data();
-        assert_eq!(db.count_matching_transactions("1").unwrap(), 3); // Now we have matches.
+        // The range wraps around the end of the month, matching both 2021/02/01 and 2021/03/01.
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                day_of_month_min: Some(28),
+                day_of_month_max: Some(1),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 2,
+            tagged_transactions: 6,
+        };
+        assert_eq!(result, expected);
+
+        assert!(add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                day_of_month_min: Some(0),
+                day_of_month_max: Some(32),
+                ..Default::default()
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn add_tag_rule_day_of_week() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                day_of_week: Some(Weekday::Fri),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 3,
+        };
+        assert_eq!(result, expected);
+
+        let tag_rules: Vec<model::TagRule> = db.get_all().unwrap();
+        assert_eq!(tag_rules[0].day_of_week, Some(5));
+        assert_eq!(
+            tag_rules[0].human_readable,
+            "Apply tag 'test' to any transactions where the day of the week is Friday."
+        );
+        assert_eq!(
+            get_tag_rule_attributes(&db, &tag_rules[0])
+                .unwrap()
+                .day_of_week,
+            Some(Weekday::Fri)
+        );
+    }
+
+    #[test]
+    fn add_tag_rule_every_n_months() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        // Without a period, months are counted from January, so this matches March.
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_contains: Some("INTEREST".to_string()),
+                every_n_months: Some(2),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 1,
+        };
+        assert_eq!(result, expected);
+        let transactions_tags = db.get_transactions_tags().unwrap();
+        assert_eq!(transactions_tags[0].transaction_id, 13);
+
+        // With a period, they are counted from its first month.
+        let result = add_tag_rule(
+            &db,
+            "other",
+            &TagRuleAttributes {
+                description_contains: Some("INTEREST".to_string()),
+                from_date: Some(NaiveDate::from_ymd_opt(2021, 02, 01).unwrap()),
+                to_date: Some(NaiveDate::MAX),
+                every_n_months: Some(2),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 2,
+            tagged_transactions: 1,
+        };
+        assert_eq!(result, expected);
+        let tag_rules: Vec<model::TagRule> = db.get_all().unwrap();
+        assert!(tag_rules[1]
+            .human_readable
+            .ends_with("the month is every 2 month(s) counting from 2021/02."));
+
+        assert!(add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                every_n_months: Some(0),
+                ..Default::default()
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn add_tag_rule_description_not_contains() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_contains: Some("food order".to_string()),
+                description_not_contains: Some("2".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 2,
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn add_tag_rule_transaction_type_description_not_contains() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_not_contains: Some("coffee".to_string()),
+                transaction_type: Some(model::TransactionType::Debit),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 7, // All debits except the two coffees.
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn add_tag_rule_description_not_regex() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_contains: Some("food".to_string()),
+                description_not_regex: Some(r"^food order [12]$".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 1,
+        };
+        assert_eq!(result, expected);
+        assert!(add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_not_regex: Some("(food".to_string()),
+                ..Default::default()
+            }
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn add_tag_rule_transaction_type() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                transaction_type: Some(model::TransactionType::Debit),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 9, // Also matches direct debit.
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn add_tag_rule_amount_min() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                amount_min: Some(20.0),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 5, // Matches only the top transactions, of any type.
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn add_tag_rule_amount_max() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                amount_max: Some(10.0),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 5, // 3 Debits, 2 Credits in different accounts.
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn add_tag_rule_from() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                from_date: Some(NaiveDate::from_ymd_opt(2021, 02, 26).unwrap()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 10,
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn add_tag_rule_to() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                to_date: Some(NaiveDate::from_ymd_opt(2021, 02, 26).unwrap()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 6,
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn add_tag_rule_transaction_id() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                transaction_id: Some(8),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 1,
+        };
+        assert_eq!(result, expected);
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 1);
+    }
+
+    #[test]
+    fn add_tag_rule_account() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                account: Some("222222".to_string()),
+                description_contains: Some("INTEREST".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 2,
+        };
+        assert_eq!(result, expected);
+
+        // The same rule in another account doesn't match anything.
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                account: Some("111111".to_string()),
+                description_contains: Some("INTEREST".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 2,
+            tagged_transactions: 0,
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn add_tag_rule_account_alias() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        crate::commands::accounts::alias(&db, "111111", "joint").unwrap();
+        let result = add_tag_rule(
+            &db,
+            "insurance",
+            &TagRuleAttributes {
+                account: Some("joint".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 1,
+        };
+        assert_eq!(result, expected);
+
+        let tag_rules: Vec<model::TagRule> = db.get_all().unwrap();
+        assert_eq!(tag_rules[0].account, Some("111111 - 11111111".to_string()));
+        assert_eq!(
+            tag_rules[0].human_readable,
+            "Apply tag 'insurance' to any transactions where the account is 'joint'."
+        );
+    }
+
+    #[test]
+    fn add_tag_rule_account_not_unique_or_not_found() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        // Both a prefix of "111111 - 11111111" and "222222 - 22222222".
+        crate::commands::accounts::alias(&db, "111111", "revolut 1").unwrap();
+        crate::commands::accounts::alias(&db, "222222", "revolut 2").unwrap();
+        assert!(add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                account: Some("revolut".to_string()),
+                ..Default::default()
+            }
+        )
+        .is_err());
+        assert!(add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                account: Some("999999".to_string()),
+                ..Default::default()
+            }
+        )
+        .is_err());
+        assert_eq!(list_tag_rules(&db, None).unwrap().rows.len(), 0);
+    }
+
+    #[test]
+    fn add_tag_account_match_after() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                account: Some("000000".to_string()),
+                description_contains: Some("coffee".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 2);
+
+        let mut transaction = model::Transaction {
+            id: None,
+            account_name: "111111 - 11111111".to_string(),
+            posted_date: NaiveDate::from_ymd_opt(2021, 03, 04).unwrap(),
+            description: "COFFEE".to_string(),
+            debit_amount: 3.5,
+            credit_amount: 0.0,
+            balance: 96.5,
+            transaction_type: model::TransactionType::Debit,
+            currency: "EUR".to_string(),
+        };
+        db.insert(transaction.clone()).unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 2);
+
+        transaction.account_name = "000000 - 00000000".to_string();
+        db.insert(transaction).unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 3);
+    }
+
+    #[test]
+    fn add_tag_match_after() {
+        let db = open_stingy_testing_database();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                to_date: Some(NaiveDate::from_ymd_opt(2021, 02, 25).unwrap()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 0, // The database is empty.
+        };
+        assert_eq!(result, expected);
+
+        db.insert_test_data();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 3); // Now we have matches.
+    }
+
+    #[test]
+    fn add_tag_regex_match_after() {
+        let db = open_stingy_testing_database();
+        add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_regex: Some("^coffee$".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        db.insert_test_data();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 2);
+    }
+
+    #[test]
+    fn add_tag_rule_duplicate() {
+        let db = open_stingy_testing_database();
+        add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                transaction_type: Some(model::TransactionType::Debit),
+                to_date: Some(NaiveDate::from_ymd_opt(2021, 02, 26).unwrap()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                transaction_type: Some(model::TransactionType::Debit),
+                to_date: Some(NaiveDate::from_ymd_opt(2021, 02, 26).unwrap()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            result.unwrap(),
+            AddTagRuleResult::NotUnique { tag_rule_id: 1 }
+        );
+    }
+
+    #[test]
+    fn list_tag_rules_none() {
+        let db = open_stingy_testing_database();
+        let result = list_tag_rules(&db, None).unwrap();
+        assert_eq!(result.columns, vec!["ID", "Tag", "Description",]);
+        assert_eq!(result.rows.len(), 0);
+    }
+
+    #[test]
+    fn list_tag_rules_multiple() {
+        let db = open_stingy_testing_database();
+        add_tag_rule(
+            &db,
+            "test1",
+            &TagRuleAttributes {
+                to_date: Some(NaiveDate::from_ymd_opt(2021, 02, 24).unwrap()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        add_tag_rule(
+            &db,
+            "test2",
+            &TagRuleAttributes {
+                to_date: Some(NaiveDate::from_ymd_opt(2021, 02, 25).unwrap()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        add_tag_rule(
+            &db,
+            "test3",
+            &TagRuleAttributes {
+                to_date: Some(NaiveDate::from_ymd_opt(2021, 02, 26).unwrap()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let result = list_tag_rules(&db, None).unwrap();
+        assert_eq!(result.rows.len(), 3);
+        assert_eq!(result.rows[0][0], "1");
+        assert_eq!(result.rows[0][1], "test1");
+        assert_eq!(result.rows[1][0], "2");
+        assert_eq!(result.rows[1][1], "test2");
+        assert_eq!(result.rows[2][0], "3");
+        assert_eq!(result.rows[2][1], "test3");
+    }
+
+    #[test]
+    fn list_tag_rules_filter() {
+        let db = open_stingy_testing_database();
+        add_tag_rule(
+            &db,
+            "test1",
+            &TagRuleAttributes {
+                to_date: Some(NaiveDate::from_ymd_opt(2021, 02, 24).unwrap()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        add_tag_rule(
+            &db,
+            "test2",
+            &TagRuleAttributes {
+                to_date: Some(NaiveDate::from_ymd_opt(2021, 02, 25).unwrap()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        // Prefix match.
+        let result = list_tag_rules(&db, Some("test")).unwrap();
+        assert_eq!(result.rows.len(), 2);
+        assert_eq!(result.rows[0][1], "test1");
+        assert_eq!(result.rows[1][1], "test2");
+
+        let result = list_tag_rules(&db, Some("test1")).unwrap();
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0][1], "test1");
+
+        let result = list_tag_rules(&db, Some("test2")).unwrap();
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0][1], "test2");
+
+        let result = list_tag_rules(&db, Some("test3")).unwrap();
+        assert_eq!(result.rows.len(), 0);
+    }
+
+    #[test]
+    fn delete_one_tag_rule() {
+        let db = open_stingy_testing_database();
+        add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_contains: Some("transfer".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(delete_tag_rule(&db, &1.to_string()).unwrap(), 1);
+
+        // Check that we don't panic for invalid ids.
+        assert_eq!(delete_tag_rule(&db, &1.to_string()).unwrap(), 0);
+        assert_eq!(delete_tag_rule(&db, &11.to_string()).unwrap(), 0);
+
+        // Check that we return an error when we can't parse.
+        assert!(delete_tag_rule(&db, "eleven").is_err());
+    }
+
+    #[test]
+    fn untag_after_deleting() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_contains: Some("PUB".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_contains: Some("TRANSFER".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // One match for each rule.
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 1);
+        assert_eq!(db.count_matching_transactions("2").unwrap(), 1);
+
+        assert_eq!(delete_tag_rule(&db, "1").unwrap(), 1);
+
+        // Only rule 2 still matches.
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 0);
+        assert_eq!(db.count_matching_transactions("2").unwrap(), 1);
+    }
+
+    #[test]
+    fn transaction_id_tag_overrides_other_tags() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+
+        // Tag transaction 7 using a rule (that doesn't refer to its ID).
+        assert_eq!(
+            add_tag_rule(
+                &db,
+                "pub",
+                &TagRuleAttributes {
+                    description_contains: Some("PUB".to_string()),
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
+            AddTagRuleResult::Added {
+                tag_rule_id: 1,
+                tagged_transactions: 1
+            }
+        );
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 1);
+
+        // Now tag it by ID, the previous rule disappears.
+        assert_eq!(
+            add_tag_rule(
+                &db,
+                "not pub",
+                &TagRuleAttributes {
+                    transaction_id: Some(7),
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
+            AddTagRuleResult::Added {
+                tag_rule_id: 2,
+                tagged_transactions: 1
+            }
+        );
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 0);
+        assert_eq!(db.count_matching_transactions("2").unwrap(), 1);
+
+        // Add another tag using the transaction ID, both should stay.
+        assert_eq!(
+            add_tag_rule(
+                &db,
+                "not cafe",
+                &TagRuleAttributes {
+                    transaction_id: Some(7),
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
+            AddTagRuleResult::Added {
+                tag_rule_id: 3,
+                tagged_transactions: 1
+            }
+        );
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 0);
+        assert_eq!(db.count_matching_transactions("2").unwrap(), 1);
+        assert_eq!(db.count_matching_transactions("3").unwrap(), 1);
+
+        // Remove one of the rules we added, the other should stay.
+        delete_tag_rule(&db, "2").unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 0);
+        assert_eq!(db.count_matching_transactions("2").unwrap(), 0);
+        assert_eq!(db.count_matching_transactions("3").unwrap(), 1);
+
+        // Remove the other rule, now the original rule should return.
+        delete_tag_rule(&db, "3").unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 1);
+        assert_eq!(db.count_matching_transactions("2").unwrap(), 0);
+        assert_eq!(db.count_matching_transactions("3").unwrap(), 0);
+    }
+
+    #[test]
+    fn transaction_id_tag_not_unique() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+
+        assert_eq!(
+            add_tag_rule(
+                &db,
+                "not pub",
+                &TagRuleAttributes {
+                    description_contains: Some("PUB".to_string()),
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
+            AddTagRuleResult::Added {
+                tag_rule_id: 1,
+                tagged_transactions: 1
+            }
+        );
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 1);
+
+        // A rule with transaction ID overrides a rule without, even if they set
+        // the same tag.
+        assert_eq!(
+            add_tag_rule(
+                &db,
+                "not pub",
+                &TagRuleAttributes {
+                    transaction_id: Some(7),
+                    ..Default::default()
+                }
+            )
+            .unwrap(),
+            AddTagRuleResult::Added {
+                tag_rule_id: 2,
+                tagged_transactions: 1
+            }
+        );
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 0);
+        assert_eq!(db.count_matching_transactions("2").unwrap(), 1);
+    }
+
+    #[test]
+    fn regex_tag_returns_after_deleting_transaction_id_tag() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+
+        add_tag_rule(
+            &db,
+            "pub",
+            &TagRuleAttributes {
+                description_regex: Some("^pub$".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        add_tag_rule(
+            &db,
+            "not pub",
+            &TagRuleAttributes {
+                transaction_id: Some(7),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 0);
+
+        // Deleting the transaction ID rule re-evaluates the regex rule.
+        delete_tag_rule(&db, "2").unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 1);
+    }
+
+    #[test]
+    fn human_readable_description_regex() {
+        let db = open_stingy_testing_database();
+        add_tag_rule(
+            &db,
+            "fuel",
+            &TagRuleAttributes {
+                description_regex: Some(r"\bSHELL\b".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let result = list_tag_rules(&db, None).unwrap();
+        assert_eq!(
+            result.rows[0][2],
+            r"Apply tag 'fuel' to any transactions where the description matches the regex '\bSHELL\b'."
+        );
+    }
+
+    #[test]
+    fn human_readable_description_exclusions() {
+        let db = open_stingy_testing_database();
+        add_tag_rule(
+            &db,
+            "shopping",
+            &TagRuleAttributes {
+                description_contains: Some("AMAZON".to_string()),
+                description_not_contains: Some("PRIME".to_string()),
+                description_not_regex: Some(r"MKTP\s+IE".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let result = list_tag_rules(&db, None).unwrap();
+        assert_eq!(
+            result.rows[0][2],
+            "Apply tag 'shopping' to any transactions where the description contains 'AMAZON', \
+             and the description doesn't contain 'PRIME', and the description doesn't match the \
+             regex 'MKTP\\s+IE'."
+        );
+    }
+
+    #[test]
+    fn add_tag_rule_any_of() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "treats",
+            &TagRuleAttributes {
+                condition_groups: vec![ConditionGroup::AnyOf(vec![
+                    Condition::DescriptionContains("COFFEE".to_string()),
+                    Condition::DescriptionContains("PUB".to_string()),
+                ])],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 3,
+        };
+        assert_eq!(result, expected);
+        assert_eq!(
+            list_tag_rules(&db, None).unwrap().rows[0][2],
+            "Apply tag 'treats' to any transactions where either the description contains \
+             'COFFEE', or the description contains 'PUB'."
+        );
+    }
+
+    #[test]
+    fn add_tag_rule_none_of() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                transaction_type: Some(model::TransactionType::Debit),
+                condition_groups: vec![ConditionGroup::NoneOf(vec![
+                    Condition::DescriptionContains("COFFEE".to_string()),
+                    Condition::DescriptionContains("GROCERIES".to_string()),
+                ])],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 5, // All debits except coffees and groceries.
+        };
+        assert_eq!(result, expected);
+        assert_eq!(
+            list_tag_rules(&db, None).unwrap().rows[0][2],
+            "Apply tag 'test' to Debit transactions where the description doesn't contain \
+             'COFFEE', and the description doesn't contain 'GROCERIES'."
+        );
+    }
+
+    #[test]
+    fn add_tag_rule_multiple_condition_groups() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let result = add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                condition_groups: vec![
+                    ConditionGroup::AnyOf(vec![
+                        Condition::DescriptionContains("FOOD".to_string()),
+                        Condition::Account("111111".to_string()),
+                    ]),
+                    ConditionGroup::NoneOf(vec![Condition::DescriptionRegex("3$".to_string())]),
+                ],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = AddTagRuleResult::Added {
+            tag_rule_id: 1,
+            tagged_transactions: 3, // FOOD ORDER 1 and 2, and the INSURANCE REPAYMENT.
+        };
+        assert_eq!(result, expected);
+        assert_eq!(
+            list_tag_rules(&db, None).unwrap().rows[0][2],
+            "Apply tag 'test' to any transactions where either the description contains 'FOOD', \
+             or the account is '111111 - 11111111', and the description doesn't match the regex \
+             '3$'."
+        );
+    }
+
+    #[test]
+    fn transaction_id_tag_overrides_condition_groups() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_tag_rule(
+            &db,
+            "not pub",
+            &TagRuleAttributes {
+                transaction_id: Some(7),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        // Evaluating the condition groups doesn't tag transaction 7 (the PUB) again.
+        add_tag_rule(
+            &db,
+            "pub",
+            &TagRuleAttributes {
+                condition_groups: vec![ConditionGroup::AnyOf(vec![
+                    Condition::DescriptionContains("PUB".to_string()),
+                    Condition::DescriptionContains("COFFEE".to_string()),
+                ])],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 1);
+        assert_eq!(db.count_matching_transactions("2").unwrap(), 2);
+
+        // Nor does changing them.
+        edit_tag_rule(
+            &db,
+            "2",
+            None,
+            &TagRuleAttributes {
+                condition_groups: vec![ConditionGroup::AnyOf(vec![
+                    Condition::DescriptionContains("PUB".to_string()),
+                ])],
+                ..Default::default()
+            },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(db.count_matching_transactions("2").unwrap(), 0);
+
+        delete_tag_rule(&db, "1").unwrap();
+        assert_eq!(db.count_matching_transactions("2").unwrap(), 1);
+    }
+
+    #[test]
+    fn add_tag_rule_condition_groups_match_after() {
+        let db = open_stingy_testing_database();
+        add_tag_rule(
+            &db,
+            "treats",
+            &TagRuleAttributes {
+                condition_groups: vec![ConditionGroup::AnyOf(vec![
+                    Condition::DescriptionContains("COFFEE".to_string()),
+                    Condition::DescriptionContains("PUB".to_string()),
+                ])],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        db.insert_test_data();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 3);
+    }
+
+    #[test]
+    fn add_tag_rule_invalid_condition_groups() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        assert!(add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                condition_groups: vec![ConditionGroup::AnyOf(vec![])],
+                ..Default::default()
+            }
+        )
+        .is_err());
+        assert!(add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                condition_groups: vec![ConditionGroup::AnyOf(vec![Condition::DescriptionRegex(
+                    "(".to_string()
+                )])],
+                ..Default::default()
+            }
+        )
+        .is_err());
+        assert_eq!(list_tag_rules(&db, None).unwrap().rows.len(), 0);
+    }
+
+    #[test]
+    fn delete_and_undo_composite_tag_rule() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_tag_rule(
+            &db,
+            "treats",
+            &TagRuleAttributes {
+                condition_groups: vec![ConditionGroup::AnyOf(vec![
+                    Condition::DescriptionContains("COFFEE".to_string()),
+                    Condition::DescriptionContains("PUB".to_string()),
+                ])],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+
+        crate::commands::undo::begin_undo_step(&db, "delete").unwrap();
+        assert_eq!(delete_tag_rule(&db, "1").unwrap(), 1);
+        let groups: Vec<model::TagRuleConditionGroup> = db.get_all().unwrap();
+        assert!(groups.is_empty());
+
+        crate::commands::undo::command_undo(&db).unwrap();
+        let conditions: Vec<model::TagRuleCondition> = db.get_all().unwrap();
+        assert_eq!(conditions.len(), 2);
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 3);
+    }
+
+    #[test]
+    fn parse_conditions_with_commas() {
+        assert_eq!(
+            parse_conditions("description-contains=A, B,description-regex=\\d{1,3},account=joint")
+                .unwrap(),
+            vec![
+                Condition::DescriptionContains("A, B".to_string()),
+                Condition::DescriptionRegex("\\d{1,3}".to_string()),
+                Condition::Account("joint".to_string()),
+            ]
+        );
+        assert!(parse_conditions("description=A").is_err());
+        assert!(parse_conditions("").is_err());
+    }
+
+    #[test]
+    fn edit_tag_rule_keeps_id() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_tag_rule(
+            &db,
+            "coffee",
+            &TagRuleAttributes {
+                description_contains: Some("COFFEE".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let result = edit_tag_rule(
+            &db,
+            "1",
+            Some("pub"),
+            &TagRuleAttributes {
+                description_contains: Some("PUB".to_string()),
+                ..Default::default()
+            },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            EditTagRuleResult::Edited {
+                tagged_transactions: 1
+            }
+        );
+        let result = list_tag_rules(&db, None).unwrap();
+        assert_eq!(
+            result.rows,
+            vec![vec![
+                "1",
+                "pub",
+                "Apply tag 'pub' to any transactions where the description contains 'PUB'."
+            ]]
+        );
+    }
+
+    #[test]
+    fn edit_tag_rule_keep_and_clear_attributes() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_tag_rule(
+            &db,
+            "food",
+            &TagRuleAttributes {
+                description_contains: Some("FOOD".to_string()),
+                amount_min: Some(20.0),
+                amount_max: Some(f64::MAX),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 2);
+
+        // Keeps the description and the tag.
+        let result = edit_tag_rule(
+            &db,
+            "1",
+            None,
+            &TagRuleAttributes::default(),
+            &[TagRuleAttribute::AmountRange],
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            EditTagRuleResult::Edited {
+                tagged_transactions: 3
+            }
+        );
+        assert_eq!(
+            list_tag_rules(&db, None).unwrap().rows[0][2],
+            "Apply tag 'food' to any transactions where the description contains 'FOOD'."
+        );
+
+        // Clearing all attributes isn't allowed.
+        assert!(edit_tag_rule(
+            &db,
+            "1",
+            None,
+            &TagRuleAttributes::default(),
+            &[TagRuleAttribute::DescriptionContains],
+        )
+        .is_err());
+        assert!(edit_tag_rule(&db, "2", None, &TagRuleAttributes::default(), &[]).is_err());
+    }
+
+    #[test]
+    fn edit_tag_rule_recurring_dates() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_tag_rule(
+            &db,
+            "food",
+            &TagRuleAttributes {
+                description_contains: Some("FOOD".to_string()),
+                day_of_week: Some(Weekday::Fri),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 2);
+
+        let result = edit_tag_rule(
+            &db,
+            "1",
+            None,
+            &TagRuleAttributes {
+                day_of_month_min: Some(3),
+                day_of_month_max: Some(3),
+                ..Default::default()
+            },
+            &[TagRuleAttribute::DayOfWeek],
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            EditTagRuleResult::Edited {
+                tagged_transactions: 1
+            }
+        );
+        assert_eq!(
+            list_tag_rules(&db, None).unwrap().rows[0][2],
+            "Apply tag 'food' to any transactions where the description contains 'FOOD', and the day of the month is 3."
+        );
+    }
+
+    #[test]
+    fn edit_tag_rule_condition_groups() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_tag_rule(
+            &db,
+            "treats",
+            &TagRuleAttributes {
+                condition_groups: vec![ConditionGroup::AnyOf(vec![
+                    Condition::DescriptionContains("COFFEE".to_string()),
+                    Condition::DescriptionContains("PUB".to_string()),
+                ])],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let result = edit_tag_rule(
+            &db,
+            "1",
+            None,
+            &TagRuleAttributes {
+                transaction_type: Some(model::TransactionType::Debit),
+                amount_min: Some(3.0),
+                amount_max: Some(f64::MAX),
+                ..Default::default()
+            },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            EditTagRuleResult::Edited {
+                tagged_transactions: 2
+            }
+        );
+
+        let result = edit_tag_rule(
+            &db,
+            "1",
+            None,
+            &TagRuleAttributes::default(),
+            &[TagRuleAttribute::ConditionGroups],
+        )
+        .unwrap();
+        assert_eq!(
+            result,
+            EditTagRuleResult::Edited {
+                tagged_transactions: 8 // All debits but the 2.99 coffee.
+            }
+        );
+        let groups: Vec<model::TagRuleConditionGroup> = db.get_all().unwrap();
+        assert!(groups.is_empty());
+    }
+
+    #[test]
+    fn edit_tag_rule_not_unique() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        for description in ["COFFEE", "PUB"] {
+            add_tag_rule(
+                &db,
+                "test",
+                &TagRuleAttributes {
+                    description_contains: Some(description.to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        }
+        let result = edit_tag_rule(
+            &db,
+            "2",
+            None,
+            &TagRuleAttributes {
+                description_contains: Some("COFFEE".to_string()),
+                ..Default::default()
+            },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(result, EditTagRuleResult::NotUnique { tag_rule_id: 1 });
+    }
+
+    #[test]
+    fn edit_tag_rule_unchanged() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_tag_rule(
+            &db,
+            "test",
+            &TagRuleAttributes {
+                description_contains: Some("COFFEE".to_string()),
+                condition_groups: vec![ConditionGroup::NoneOf(vec![
+                    Condition::DescriptionContains("FOOD".to_string()),
+                ])],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let expected = EditTagRuleResult::Edited {
+            tagged_transactions: 2,
+        };
+
+        // Setting an attribute to the value it already has, or editing without changes.
+        let result = edit_tag_rule(
+            &db,
+            "1",
+            Some("test"),
+            &TagRuleAttributes {
+                description_contains: Some("COFFEE".to_string()),
+                ..Default::default()
+            },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(result, expected);
+        let result = edit_tag_rule(&db, "1", None, &TagRuleAttributes::default(), &[]).unwrap();
+        assert_eq!(result, expected);
+        assert_eq!(list_tag_rules(&db, None).unwrap().rows.len(), 1);
+    }
+
+    #[test]
+    fn edit_transaction_id_tag_rule() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_tag_rule(
+            &db,
+            "pub",
+            &TagRuleAttributes {
+                description_contains: Some("PUB".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        add_tag_rule(
+            &db,
+            "not pub",
+            &TagRuleAttributes {
+                transaction_id: Some(7),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 0);
+
+        // Moving the transaction ID rule to another transaction untags that one instead.
+        edit_tag_rule(
+            &db,
+            "2",
+            None,
+            &TagRuleAttributes {
+                transaction_id: Some(8),
+                ..Default::default()
+            },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 1);
+        assert_eq!(db.count_matching_transactions("2").unwrap(), 1);
+
+        // And a tag rule that gains a transaction ID takes precedence.
+        edit_tag_rule(
+            &db,
+            "2",
+            None,
+            &TagRuleAttributes {
+                transaction_id: Some(7),
+                ..Default::default()
+            },
+            &[],
+        )
+        .unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 0);
+        assert_eq!(db.count_matching_transactions("2").unwrap(), 1);
+    }
+
+    #[test]
+    fn undo_edit_tag_rule() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_tag_rule(
+            &db,
+            "treats",
+            &TagRuleAttributes {
+                condition_groups: vec![ConditionGroup::AnyOf(vec![
+                    Condition::DescriptionContains("COFFEE".to_string()),
+                    Condition::DescriptionContains("PUB".to_string()),
+                ])],
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let before = list_tag_rules(&db, None).unwrap().rows;
+
+        crate::commands::undo::begin_undo_step(&db, "edit").unwrap();
+        edit_tag_rule(
+            &db,
+            "1",
+            Some("coffee"),
+            &TagRuleAttributes {
+                description_contains: Some("COFFEE".to_string()),
+                ..Default::default()
+            },
+            &[TagRuleAttribute::ConditionGroups],
+        )
+        .unwrap();
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 2);
+
+        crate::commands::undo::command_undo(&db).unwrap();
+        assert_eq!(list_tag_rules(&db, None).unwrap().rows, before);
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 3);
+    }
+
+    #[test]
+    fn test_tag_rule_lists_matches() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        add_tag_rule(
+            &db,
+            "food",
+            &TagRuleAttributes {
+                description_contains: Some("FOOD ORDER".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let before = list_tag_rules(&db, None).unwrap().rows;
+
+        let result = test_tag_rule(
+            &db,
+            "treats",
+            &TagRuleAttributes {
+                description_regex: Some("^(COFFEE|FOOD ORDER 1)$".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(result.existing_tag_rule_id, None);
+        assert_eq!(result.matching, 3);
+        assert_eq!(result.conflicting, 1);
+        assert_eq!(format!("{:.02}", result.debit_total), "16.73");
+        assert_eq!(result.credit_total, 0.0);
+        assert_eq!(
+            result.rows[1],
+            vec![
+                "3",
+                "2021/02/26",
+                "000000 - 00000000",
+                "FOOD ORDER 1",
+                "Debit",
+                "10.00",
+                "⚠️ food"
+            ]
+        );
+        assert_eq!(result.rows[0][6], "");
+
+        // Nothing is written.
+        assert_eq!(list_tag_rules(&db, None).unwrap().rows, before);
+        assert_eq!(db.count_tagged_transactions().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_tag_rule_subtag_and_existing() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        let attributes = TagRuleAttributes {
+            description_contains: Some("FOOD ORDER".to_string()),
+            ..Default::default()
+        };
+        add_tag_rule(&db, "food", &attributes).unwrap();
+
+        // Tags in the same hierarchy don't conflict.
+        let result = test_tag_rule(
+            &db,
+            "food/delivery",
+            &TagRuleAttributes {
+                description_contains: Some("FOOD ORDER 2".to_string()),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(result.matching, 1);
+        assert_eq!(result.conflicting, 0);
+        assert_eq!(result.rows[0][6], "food");
+
+        // The tags from an equal tag rule are not listed.
+        let result = test_tag_rule(&db, "food", &attributes).unwrap();
+        assert_eq!(result.existing_tag_rule_id, Some(1));
+        assert_eq!(result.matching, 3);
+        assert!(result.rows.iter().all(|r| r[6].is_empty()));
+    }
+
+    #[test]
+    fn audit_tag_rules_findings() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        for (tag, attributes) in [
+            (
+                "food",
+                TagRuleAttributes {
+                    description_contains: Some("FOOD ORDER".to_string()),
+                    ..Default::default()
+                },
+            ),
+            (
+                "treats",
+                TagRuleAttributes {
+                    description_contains: Some("ORDER 1".to_string()),
+                    ..Default::default()
+                },
+            ),
+            (
+                "food/delivery",
+                TagRuleAttributes {
+                    description_contains: Some("ORDER 2".to_string()),
+                    ..Default::default()
+                },
+            ),
+            (
+                "cars",
+                TagRuleAttributes {
+                    description_contains: Some("PETROL".to_string()),
+                    ..Default::default()
+                },
+            ),
+            (
+                "groceries",
+                TagRuleAttributes {
+                    transaction_id: Some(10),
+                    ..Default::default()
+                },
+            ),
+        ] {
+            add_tag_rule(&db, tag, &attributes).unwrap();
+        }
+
+        let result = audit_tag_rules(&db).unwrap();
+        let dead: Vec<Option<i64>> = result.dead_tag_rules.iter().map(|tr| tr.id).collect();
+        assert_eq!(dead, vec![Some(4)]);
+        // 'food' and 'food/delivery' are in the same hierarchy.
+        assert_eq!(
+            result.conflicts,
+            vec![TagRuleConflict {
+                tag_rule_ids: (1, 2),
+                tags: ("food".to_string(), "treats".to_string()),
+                transactions: 1,
+            }]
+        );
+        assert_eq!(
+            result.shadowing,
+            vec![TagRuleShadowing {
+                tag_rule_id: 5,
+                transaction_id: 10,
+                shadowed_tag_rule_ids: vec![1],
+            }]
+        );
+        // The coffees, groceries, pub and subscription are untagged.
+        assert_eq!(result.untagged_debits, (6, 9));
+        assert_eq!(format!("{:.02}", result.untagged_debit_amount.0), "82.33");
+        assert_eq!(format!("{:.02}", result.untagged_debit_amount.1), "139.98");
+    }
+
+    #[test]
+    fn rename_tag_with_subtags() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        for (tag, description) in [
+            ("travel", "COFFEE"),
+            ("travel/paris", "FOOD ORDER 1"),
+            ("travel/lisbon", "FOOD ORDER 2"),
+            ("travelling", "PUB"),
+            ("trips/lisbon", "FOOD ORDER 2"),
+        ] {
+            add_tag_rule(
+                &db,
+                tag,
+                &TagRuleAttributes {
+                    description_contains: Some(description.to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        }
+
+        crate::commands::undo::begin_undo_step(&db, "rename").unwrap();
+        let before = list_tag_rules(&db, None).unwrap().rows;
+        let result = rename_tag(&db, "travel/", "trips").unwrap();
+        assert_eq!(
+            result,
+            RetagResult {
+                tag_rules: 3,
+                deleted_tag_rules: 1,
+                transactions: 4,
+            }
+        );
+        let rows = list_tag_rules(&db, None).unwrap().rows;
+        let tags: Vec<&str> = rows.iter().map(|r| r[1].as_str()).collect();
+        assert_eq!(
+            tags,
+            vec!["trips", "trips/paris", "travelling", "trips/lisbon"]
+        );
+        assert_eq!(
+            rows[0][2],
+            "Apply tag 'trips' to any transactions where the description contains 'COFFEE'."
+        );
+        assert_eq!(db.count_matching_transactions("1").unwrap(), 2);
+
+        crate::commands::undo::command_undo(&db).unwrap();
+        assert_eq!(list_tag_rules(&db, None).unwrap().rows, before);
+        assert!(rename_tag(&db, "travel", "").is_err());
     }
 
     #[test]
-    fn add_tag_rule_duplicate() {
+    fn merge_tags_into_one() {
         let db = open_stingy_testing_database();
-        add_tag_rule(
+        db.insert_test_data();
+        for (tag, description) in [
+            ("restaurants", "FOOD ORDER 1"),
+            ("takeaway/pizza", "FOOD ORDER 2"),
+            ("groceries", "GROCERIES"),
+        ] {
+            add_tag_rule(
+                &db,
+                tag,
+                &TagRuleAttributes {
+                    description_contains: Some(description.to_string()),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        }
+
+        let result = merge_tags(
             &db,
-            "test",
-            None,
-            None,
-            Some(model::TransactionType::Debit),
-            None,
-            None,
-            None,
-            Some(NaiveDate::from_ymd_opt(2021, 02, 26).unwrap()),
+            &["restaurants".to_string(), "takeaway".to_string()],
+            "dining",
         )
         .unwrap();
-        let result = add_tag_rule(
-            &db,
-            "test",
-            None,
-            None,
-            Some(model::TransactionType::Debit),
-            None,
-            None,
-            None,
-            Some(NaiveDate::from_ymd_opt(2021, 02, 26).unwrap()),
+        assert_eq!(
+            result,
+            RetagResult {
+                tag_rules: 2,
+                deleted_tag_rules: 0,
+                transactions: 2,
+            }
         );
+        let rows = list_tag_rules(&db, None).unwrap().rows;
+        let tags: Vec<&str> = rows.iter().map(|r| r[1].as_str()).collect();
+        assert_eq!(tags, vec!["dining", "dining", "groceries"]);
+    }
+
+    #[test]
+    fn suggest_tags_from_tagged_transactions() {
+        let db = open_stingy_testing_database();
+        db.insert_test_data();
+        for (tag, transaction_id) in [("food", 3), ("food", 4), ("coffee", 2), ("pubs", 7)] {
+            add_tag_rule(
+                &db,
+                tag,
+                &TagRuleAttributes {
+                    transaction_id: Some(transaction_id),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        }
+
+        let suggestions = suggest_tags(&db).unwrap();
+        let suggested: Vec<(&str, &str, usize)> = suggestions
+            .iter()
+            .map(|s| {
+                (
+                    s.description_contains.as_str(),
+                    s.tag.as_str(),
+                    s.transactions,
+                )
+            })
+            .collect();
+        // Groceries, and others, have nothing in common with tagged transactions.
         assert_eq!(
-            result.unwrap(),
-            AddTagRuleResult::NotUnique { tag_rule_id: 1 }
+            suggested,
+            vec![("FOOD ORDER", "food", 1), ("COFFEE", "coffee", 1)]
         );
+        assert!(suggestions[0].confidence > 0.5);
+        assert_eq!(format!("{:.02}", suggestions[0].amount), "25.15");
     }
 
     #[test]
-    fn list_tag_rules_none() {
+    fn add_tag_rule_from_query_filters() {
         let db = open_stingy_testing_database();
-        let result = list_tag_rules(&db, None).unwrap();
-        assert_eq!(result.columns, vec!["ID", "Tag", "Description",]);
-        assert_eq!(result.rows.len(), 0);
+        db.insert_test_data();
+        let filters = QueryFilters {
+            accounts: vec!["000000 - 00000000".to_string()],
+            description_contains: Some("FOOD".to_string()),
+            amount_min: Some(20.0),
+            amount_max: Some(f64::MAX),
+            transaction_types: vec![
+                model::TransactionType::Debit,
+                model::TransactionType::DirectDebit,
+            ],
+            ..Default::default()
+        };
+        let results = add_tag_rules_from_query(&db, "food", filters.clone(), false).unwrap();
+        assert_eq!(
+            results,
+            vec![AddTagRuleResult::Added {
+                tag_rule_id: 1,
+                tagged_transactions: 2,
+            }]
+        );
+        assert_eq!(
+            list_tag_rules(&db, None).unwrap().rows[0][2],
+            "Apply tag 'food' to Debit transactions where the account is '000000 - 00000000', \
+             and the description contains 'FOOD', and the amount is larger or equal to '20'."
+        );
+        assert_eq!(
+            db.query_debits(filters, &QueryOrder::default())
+                .unwrap()
+                .rows
+                .len(),
+            2
+        );
+
+        // Several accounts are matched with a condition group.
+        let filters = QueryFilters {
+            accounts: vec![
+                "111111 - 11111111".to_string(),
+                "222222 - 22222222".to_string(),
+            ],
+            ..Default::default()
+        };
+        let results = add_tag_rules_from_query(&db, "revolut", filters, false).unwrap();
+        assert_eq!(
+            results,
+            vec![AddTagRuleResult::Added {
+                tag_rule_id: 2,
+                tagged_transactions: 3,
+            }]
+        );
+
+        assert!(add_tag_rules_from_query(&db, "none", QueryFilters::default(), false).is_err());
+        let filters = QueryFilters {
+            tags: vec!["food".to_string()],
+            ..Default::default()
+        };
+        assert!(add_tag_rules_from_query(&db, "more food", filters, false).is_err());
     }
 
     #[test]
-    fn list_tag_rules_multiple() {
+    fn add_tag_rules_from_query_snapshot() {
         let db = open_stingy_testing_database();
+        db.insert_test_data();
         add_tag_rule(
             &db,
-            "test1",
-            None,
-            None,
-            None,
-            None,
-            None,
-            None,
-            Some(NaiveDate::from_ymd_opt(2021, 02, 24).unwrap()),
-        )
-        .unwrap();
-        add_tag_rule(
-            &db,
-            "test2",
-            None,
-            None,
-            None,
-            None,
-            None,
-            None,
-            Some(NaiveDate::from_ymd_opt(2021, 02, 25).unwrap()),
-        )
-        .unwrap();
-        add_tag_rule(
-            &db,
-            "test3",
-            None,
-            None,
-            None,
-            None,
-            None,
-            None,
-            Some(NaiveDate::from_ymd_opt(2021, 02, 26).unwrap()),
+            "food",
+            &TagRuleAttributes {
+                description_contains: Some("FOOD ORDER".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
-        let result = list_tag_rules(&db, None).unwrap();
-        assert_eq!(result.rows.len(), 3);
-        assert_eq!(result.rows[0][0], "1");
-        assert_eq!(result.rows[0][1], "test1");
-        assert_eq!(result.rows[1][0], "2");
-        assert_eq!(result.rows[1][1], "test2");
-        assert_eq!(result.rows[2][0], "3");
-        assert_eq!(result.rows[2][1], "test3");
+
+        let filters = QueryFilters {
+            tags: vec!["food".to_string()],
+            amount_min: Some(20.0),
+            ..Default::default()
+        };
+        let results = add_tag_rules_from_query(&db, "delivery", filters, true).unwrap();
+        assert_eq!(results.len(), 2);
+        let rows = list_tag_rules(&db, None).unwrap().rows;
+        assert_eq!(
+            rows[1][2],
+            "Apply tag 'delivery' to any transactions where the transaction id is '4'."
+        );
+        assert_eq!(
+            rows[2][2],
+            "Apply tag 'delivery' to any transactions where the transaction id is '10'."
+        );
+
+        // The snapshot doesn't follow later changes to the query results.
+        delete_tag_rule(&db, "1").unwrap();
+        assert_eq!(db.count_matching_transactions("2").unwrap(), 1);
     }
 
     #[test]
-    fn list_tag_rules_filter() {
+    fn untagged_transactions_for_triage() {
         let db = open_stingy_testing_database();
+        db.insert_test_data();
         add_tag_rule(
             &db,
-            "test1",
-            None,
-            None,
-            None,
-            None,
-            None,
-            None,
-            Some(NaiveDate::from_ymd_opt(2021, 02, 24).unwrap()),
-        )
-        .unwrap();
-        add_tag_rule(
-            &db,
-            "test2",
-            None,
-            None,
-            None,
-            None,
-            None,
-            None,
-            Some(NaiveDate::from_ymd_opt(2021, 02, 25).unwrap()),
+            "food",
+            &TagRuleAttributes {
+                description_contains: Some("FOOD ORDER".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
-        // Prefix match.
-        let result = list_tag_rules(&db, Some("test")).unwrap();
-        assert_eq!(result.rows.len(), 2);
-        assert_eq!(result.rows[0][1], "test1");
-        assert_eq!(result.rows[1][1], "test2");
 
-        let result = list_tag_rules(&db, Some("test1")).unwrap();
-        assert_eq!(result.rows.len(), 1);
-        assert_eq!(result.rows[0][1], "test1");
+        let ids = |db: &Box<dyn StingyDatabase>| -> Vec<i64> {
+            get_untagged_transactions(db)
+                .unwrap()
+                .iter()
+                .map(|t| t.id.unwrap())
+                .collect()
+        };
+        assert_eq!(ids(&db), vec![1, 11, 5, 7, 6, 9, 2, 8, 12, 13]);
+        assert_eq!(count_description_matches(&db, "coffee").unwrap(), 2);
 
-        let result = list_tag_rules(&db, Some("test2")).unwrap();
-        assert_eq!(result.rows.len(), 1);
-        assert_eq!(result.rows[0][1], "test2");
+        crate::commands::undo::begin_undo_step(&db, "ignore").unwrap();
+        ignore_transaction(&db, 1).unwrap();
+        assert_eq!(ids(&db), vec![11, 5, 7, 6, 9, 2, 8, 12, 13]);
+        // Ignoring twice is harmless.
+        ignore_transaction(&db, 1).unwrap();
+        assert!(ignore_transaction(&db, 1000).is_err());
 
-        let result = list_tag_rules(&db, Some("test3")).unwrap();
-        assert_eq!(result.rows.len(), 0);
+        crate::commands::undo::command_undo(&db).unwrap();
+        assert_eq!(ids(&db)[0], 1);
     }
 
     #[test]
-    fn delete_one_tag_rule() {
+    fn merchant_and_tokens() {
+        assert_eq!(merchant("VDP-TESCO STORES 3021 DUBLIN"), "VDP-TESCO STORES");
+        assert_eq!(merchant("Food order 22/01"), "FOOD ORDER");
+        assert_eq!(merchant("123 SHOP"), "");
+        assert_eq!(
+            tokens("VDP-TESCO STORES 3021"),
+            vec!["stores", "tesco", "vdp"]
+        );
+    }
+
+    fn add_test_tag_rules(db: &Box<dyn StingyDatabase>) {
+        for (tag, attributes) in [
+            (
+                "coffee",
+                TagRuleAttributes {
+                    description_contains: Some("COFFEE".to_string()),
+                    ..Default::default()
+                },
+            ),
+            (
+                "food",
+                TagRuleAttributes {
+                    description_regex: Some(r"^FOOD ORDER \d$".to_string()),
+                    transaction_type: Some(model::TransactionType::Debit),
+                    amount_min: Some(20.0),
+                    amount_max: Some(f64::MAX),
+                    ..Default::default()
+                },
+            ),
+            (
+                "groceries",
+                TagRuleAttributes {
+                    from_date: Some(NaiveDate::from_ymd_opt(2021, 03, 01).unwrap()),
+                    to_date: Some(NaiveDate::MAX),
+                    condition_groups: vec![ConditionGroup::AnyOf(vec![
+                        Condition::DescriptionContains("GROCERIES".to_string()),
+                        Condition::Account("000000 - 00000000".to_string()),
+                    ])],
+                    ..Default::default()
+                },
+            ),
+        ] {
+            add_tag_rule(db, tag, &attributes).unwrap();
+        }
+    }
+
+    #[test]
+    fn export_tag_rules_roundtrip() {
         let db = open_stingy_testing_database();
-        add_tag_rule(
-            &db,
-            "test",
-            None,
-            Some("transfer"),
-            None,
-            None,
-            None,
-            None,
-            None,
-        )
-        .unwrap();
-        assert_eq!(delete_tag_rule(&db, &1.to_string()).unwrap(), 1);
+        db.insert_test_data();
+        add_test_tag_rules(&db);
 
-        // Check that we don't panic for invalid ids.
-        assert_eq!(delete_tag_rule(&db, &1.to_string()).unwrap(), 0);
-        assert_eq!(delete_tag_rule(&db, &11.to_string()).unwrap(), 0);
+        let exported = export_tag_rules(&db).unwrap();
+        assert!(exported.contains("period = \"2021/03/01-:\""));
+        assert!(exported.contains("amount_range = \"20-:\""));
+        assert!(exported
+            .contains("any_of = [\"description-contains=GROCERIES,account=000000 - 00000000\"]"));
 
-        // Check that we return an error when we can't parse.
-        assert!(delete_tag_rule(&db, "eleven").is_err());
+        // Applying the exported rules changes nothing, even without ids.
+        let plan = plan_tag_rules(&db, &exported).unwrap();
+        assert_eq!(plan.changes.len(), 0);
+        assert_eq!(plan.unchanged, 3);
+        let without_ids: String = exported
+            .lines()
+            .filter(|l| !l.starts_with("id = "))
+            .map(|l| format!("{l}\n"))
+            .collect();
+        let plan = plan_tag_rules(&db, &without_ids).unwrap();
+        assert_eq!(plan.changes.len(), 0);
     }
 
     #[test]
-    fn untag_after_deleting() {
+    fn export_tag_rules_recurring_dates() {
         let db = open_stingy_testing_database();
         db.insert_test_data();
-        add_tag_rule(&db, "test", None, Some("PUB"), None, None, None, None, None).unwrap();
         add_tag_rule(
             &db,
-            "test",
-            None,
-            Some("TRANSFER"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            "rent",
+            &TagRuleAttributes {
+                day_of_month_min: Some(28),
+                day_of_month_max: Some(3),
+                day_of_week: Some(Weekday::Mon),
+                every_n_months: Some(3),
+                ..Default::default()
+            },
         )
         .unwrap();
 
-        // One match for each rule.
-        assert_eq!(db.count_matching_transactions("1").unwrap(), 1);
-        assert_eq!(db.count_matching_transactions("2").unwrap(), 1);
-
-        assert_eq!(delete_tag_rule(&db, "1").unwrap(), 1);
-
-        // Only rule 2 still matches.
-        assert_eq!(db.count_matching_transactions("1").unwrap(), 0);
-        assert_eq!(db.count_matching_transactions("2").unwrap(), 1);
+        let exported = export_tag_rules(&db).unwrap();
+        assert!(exported.contains("day_of_month = \"28-3\""));
+        assert!(exported.contains("day_of_week = \"monday\""));
+        assert!(exported.contains("every_n_months = 3"));
+        let plan = plan_tag_rules(&db, &exported).unwrap();
+        assert_eq!(plan.changes.len(), 0);
+        assert_eq!(plan.unchanged, 1);
     }
 
     #[test]
-    fn transaction_id_tag_overrides_other_tags() {
+    fn apply_tag_rules_file() {
         let db = open_stingy_testing_database();
         db.insert_test_data();
+        add_test_tag_rules(&db);
 
-        // Tag transaction 7 using a rule (that doesn't refer to its ID).
-        assert_eq!(
-            add_tag_rule(&db, "pub", None, Some("PUB"), None, None, None, None, None).unwrap(),
-            AddTagRuleResult::Added {
-                tag_rule_id: 1,
-                tagged_transactions: 1
-            }
-        );
-        assert_eq!(db.count_matching_transactions("1").unwrap(), 1);
+        let file = r#"
+            [[rules]]
+            id = 1
+            tag = "coffee"
+            description_contains = "COFFEE"
 
-        // Now tag it by ID, the previous rule disappears.
-        assert_eq!(
-            add_tag_rule(&db, "not pub", Some(7), None, None, None, None, None, None).unwrap(),
-            AddTagRuleResult::Added {
-                tag_rule_id: 2,
-                tagged_transactions: 1
-            }
-        );
-        assert_eq!(db.count_matching_transactions("1").unwrap(), 0);
-        assert_eq!(db.count_matching_transactions("2").unwrap(), 1);
+            [[rules]]
+            id = 2
+            tag = "food"
+            description_contains = "FOOD ORDER"
+            transaction_type = "debit"
 
-        // Add another tag using the transaction ID, both should stay.
+            [[rules]]
+            tag = "pub"
+            description_contains = "PUB"
+            period = "2021/03/01-2021/03/31"
+        "#;
+        let plan = plan_tag_rules(&db, file).unwrap();
+        assert_eq!(plan.unchanged, 1);
+        let changes: Vec<(Option<i64>, Option<&str>)> = plan
+            .changes
+            .iter()
+            .map(|c| {
+                (
+                    c.current.as_ref().and_then(|tr| tr.id),
+                    c.new.as_ref().map(|tr| tr.tag.as_str()),
+                )
+            })
+            .collect();
         assert_eq!(
-            add_tag_rule(&db, "not cafe", Some(7), None, None, None, None, None, None).unwrap(),
-            AddTagRuleResult::Added {
-                tag_rule_id: 3,
-                tagged_transactions: 1
-            }
+            changes,
+            vec![
+                (Some(2), Some("food")),
+                (Some(3), None),
+                (None, Some("pub"))
+            ]
         );
-        assert_eq!(db.count_matching_transactions("1").unwrap(), 0);
-        assert_eq!(db.count_matching_transactions("2").unwrap(), 1);
-        assert_eq!(db.count_matching_transactions("3").unwrap(), 1);
 
-        // Remove one of the rules we added, the other should stay.
-        delete_tag_rule(&db, "2").unwrap();
-        assert_eq!(db.count_matching_transactions("1").unwrap(), 0);
-        assert_eq!(db.count_matching_transactions("2").unwrap(), 0);
-        assert_eq!(db.count_matching_transactions("3").unwrap(), 1);
+        // A dry run doesn't change anything.
+        let before = list_tag_rules(&db, None).unwrap().rows;
+        let mut preview = None;
+        db.dry_run(&mut || {
+            preview = Some(apply_tag_rules(&db, &plan)?);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(list_tag_rules(&db, None).unwrap().rows, before);
 
-        // Remove the other rule, now the original rule should return.
-        delete_tag_rule(&db, "3").unwrap();
-        assert_eq!(db.count_matching_transactions("1").unwrap(), 1);
-        assert_eq!(db.count_matching_transactions("2").unwrap(), 0);
-        assert_eq!(db.count_matching_transactions("3").unwrap(), 0);
+        crate::commands::undo::begin_undo_step(&db, "apply").unwrap();
+        let result = apply_tag_rules(&db, &plan).unwrap();
+        assert_eq!(Some(&result), preview.as_ref());
+        assert_eq!(result.tagged_by_change, vec![(2, 3), (5, 0), (0, 1)]);
+        assert_eq!(result.tagged, (7, 6));
+
+        let rows = list_tag_rules(&db, None).unwrap().rows;
+        let tags: Vec<(&str, &str)> = rows
+            .iter()
+            .map(|r| (r[0].as_str(), r[1].as_str()))
+            .collect();
+        assert_eq!(tags, vec![("1", "coffee"), ("2", "food"), ("4", "pub")]);
+        assert_eq!(plan_tag_rules(&db, file).unwrap().changes.len(), 0);
+
+        // All changes are undone at once.
+        crate::commands::undo::command_undo(&db).unwrap();
+        assert_eq!(list_tag_rules(&db, None).unwrap().rows, before);
+        assert_eq!(db.count_tagged_transactions().unwrap(), 7);
     }
 
     #[test]
-    fn transaction_id_tag_not_unique() {
+    fn plan_tag_rules_invalid_file() {
         let db = open_stingy_testing_database();
         db.insert_test_data();
 
-        assert_eq!(
-            add_tag_rule(
-                &db,
-                "not pub",
-                None,
-                Some("PUB"),
-                None,
-                None,
-                None,
-                None,
-                None
-            )
-            .unwrap(),
-            AddTagRuleResult::Added {
-                tag_rule_id: 1,
-                tagged_transactions: 1
-            }
-        );
-        assert_eq!(db.count_matching_transactions("1").unwrap(), 1);
+        assert!(plan_tag_rules(&db, "rules = 1").is_err());
+        // Unknown attributes.
+        assert!(plan_tag_rules(&db, "[[rules]]\ntag = 'a'\ncolor = 'red'").is_err());
+        // No attributes.
+        assert!(plan_tag_rules(&db, "[[rules]]\ntag = 'a'").is_err());
+        assert!(plan_tag_rules(&db, "[[rules]]\ntag = 'a'\nperiod = 'soon'").is_err());
+        assert!(plan_tag_rules(
+            &db,
+            "[[rules]]\ntag = 'a'\ndescription_contains = 'A'\n\
+             [[rules]]\ntag = 'a'\ndescription_contains = 'A'"
+        )
+        .is_err());
+        assert!(plan_tag_rules(
+            &db,
+            "[[rules]]\nid = 1\ntag = 'a'\ndescription_contains = 'A'\n\
+             [[rules]]\nid = 1\ntag = 'a'\ndescription_contains = 'B'"
+        )
+        .is_err());
 
-        // A rule with transaction ID overrides a rule without, even if they set
-        // the same tag.
-        assert_eq!(
-            add_tag_rule(&db, "not pub", Some(7), None, None, None, None, None, None).unwrap(),
-            AddTagRuleResult::Added {
-                tag_rule_id: 2,
-                tagged_transactions: 1
-            }
-        );
-        assert_eq!(db.count_matching_transactions("1").unwrap(), 0);
-        assert_eq!(db.count_matching_transactions("2").unwrap(), 1);
+        // An empty file removes all tag rules.
+        add_test_tag_rules(&db);
+        assert_eq!(plan_tag_rules(&db, "").unwrap().changes.len(), 3);
     }
 
     // TODO Verify human_readable behavior
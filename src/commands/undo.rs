@@ -44,13 +44,10 @@ mod undo_tests {
         tags::add_tag_rule(
             &db,
             "test1",
-            None,
-            None,
-            None,
-            None,
-            None,
-            None,
-            Some(NaiveDate::from_ymd_opt(2021, 02, 24).unwrap()),
+            &tags::TagRuleAttributes {
+                to_date: Some(NaiveDate::from_ymd_opt(2021, 02, 24).unwrap()),
+                ..Default::default()
+            },
         )
         .unwrap();
         command_undo(&db).unwrap();
@@ -65,13 +62,10 @@ mod undo_tests {
         tags::add_tag_rule(
             &db,
             "test1",
-            None,
-            None,
-            None,
-            None,
-            None,
-            None,
-            Some(NaiveDate::from_ymd_opt(2021, 02, 24).unwrap()),
+            &tags::TagRuleAttributes {
+                to_date: Some(NaiveDate::from_ymd_opt(2021, 02, 24).unwrap()),
+                ..Default::default()
+            },
         )
         .unwrap();
         begin_undo_step(&db, "undo_tag_rule_deletion").unwrap();
@@ -89,13 +83,10 @@ mod undo_tests {
         tags::add_tag_rule(
             &db,
             "test1",
-            None,
-            Some("coffee"),
-            None,
-            None,
-            None,
-            None,
-            None,
+            &tags::TagRuleAttributes {
+                description_contains: Some("coffee".to_string()),
+                ..Default::default()
+            },
         )
         .unwrap();
 
@@ -1,4 +1,4 @@
-use crate::TimeAggregation;
+use crate::{SortBy, TimeAggregation};
 use anyhow::Result;
 use chrono::NaiveDate;
 use struct_field_names_as_array::FieldNamesAsArray;
@@ -47,7 +47,19 @@ pub struct QueryResult<RowType> {
     pub rows: Vec<RowType>,
 }
 
-#[derive(Default)]
+/// The minimum of a parsed amount range, or None when it's unbounded (':'). Unbounded minimums are
+/// stored and queried as None rather than as a negative bound.
+pub fn amount_range_min(amount_min: Option<f64>) -> Option<f64> {
+    amount_min.filter(|min| *min != f64::MIN)
+}
+
+/// Whether an amount range is signed, comparing debits as negative amounts and credits as positive
+/// ones. Ranges without a negative bound compare amounts regardless of their sign.
+pub fn is_signed_amount_range(amount_min: Option<f64>, amount_max: Option<f64>) -> bool {
+    amount_min.is_some_and(|min| min < 0.0) || amount_max.is_some_and(|max| max < 0.0)
+}
+
+#[derive(Default, Clone)]
 pub struct QueryFilters {
     pub accounts: Vec<String>,
     pub tags: Vec<String>,
@@ -60,6 +72,27 @@ pub struct QueryFilters {
     pub transaction_types: Vec<model::TransactionType>,
 }
 
+/// The order of the rows of the queries that list transactions, and the page of them to return.
+#[derive(Debug, Clone)]
+pub struct QueryOrder {
+    pub sort: SortBy,
+    pub ascending: bool,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+impl Default for QueryOrder {
+    /// All the rows, from the largest amount to the smallest.
+    fn default() -> Self {
+        QueryOrder {
+            sort: SortBy::amount,
+            ascending: false,
+            limit: None,
+            offset: None,
+        }
+    }
+}
+
 #[derive(Default, Debug, FieldNamesAsArray)]
 pub struct DebitsRow {
     pub account_name: String,
@@ -85,6 +118,23 @@ pub struct CreditsRow {
     pub credit_pct_cumulative: f64,
 }
 
+/// A debit or credit, as in a bank statement.
+#[derive(Default, Debug, FieldNamesAsArray)]
+#[field_names_as_array(visibility = "pub")]
+pub struct TransactionsRow {
+    pub account_name: String,
+    pub transaction_id: i64,
+    pub tags: Vec<String>,
+    pub posted_date: NaiveDate,
+    pub description: String,
+    /// Negative for debits.
+    pub amount: f64,
+    /// The balance after the transaction, as given by the bank.
+    pub balance: f64,
+    /// The running total of the amounts of the account's transactions in the query.
+    pub running_total: f64,
+}
+
 #[derive(Default, Debug, Clone, FieldNamesAsArray)]
 #[field_names_as_array(visibility = "pub")]
 pub struct ByTimeRow {
@@ -108,16 +158,188 @@ pub struct ByTagRow {
     pub tag_credit_pct: f64,
 }
 
+/// A node in the tag hierarchy, with the amounts of the transactions tagged with it, and with it
+/// or any of its sub-tags.
+#[derive(Default, Debug, Clone, FieldNamesAsArray)]
+#[field_names_as_array(visibility = "pub")]
+pub struct ByTagTreeRow {
+    pub tag: String,
+    pub tag_debit: f64,
+    pub tag_credit: f64,
+    pub tag_debit_cumulative: f64,
+    pub tag_credit_cumulative: f64,
+}
+
+/// The amount of the transactions with a tag in a time window.
+#[derive(Default, Debug, Clone, FieldNamesAsArray)]
+#[field_names_as_array(visibility = "pub")]
+pub struct ByTagTimeRow {
+    pub tag: String,
+    pub aggregation_window_end: NaiveDate,
+    pub amount: f64,
+}
+
+/// The debits and credits of a tag in a period, and in the period it's compared against.
+#[derive(Default, Debug, Clone)]
+pub struct CompareRow {
+    pub tag: String,
+    pub debit_amount: f64,
+    pub debit_amount_against: f64,
+    pub credit_amount: f64,
+    pub credit_amount_against: f64,
+}
+
+impl CompareRow {
+    pub fn debit_change(&self) -> f64 {
+        self.debit_amount - self.debit_amount_against
+    }
+
+    pub fn credit_change(&self) -> f64 {
+        self.credit_amount - self.credit_amount_against
+    }
+
+    /// The larger of the absolute changes in debits and credits.
+    pub fn largest_change(&self) -> f64 {
+        self.debit_change().abs().max(self.credit_change().abs())
+    }
+}
+
+/// A budget pro-rated to the period of a query, with the debits of its tag in that period.
+#[derive(Default, Debug, Clone)]
+pub struct BudgetRow {
+    pub tag: String,
+    pub period: model::BudgetPeriod,
+    pub budget_amount: f64,
+    pub debit_amount: f64,
+    /// The budget pro-rated to the days of the period so far.
+    pub expected_debit_amount: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cadence {
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AnomalyKind {
+    /// The spend of a tag, a month, compared to its monthly spend in the previous months.
+    Tag,
+    /// A debit, compared to the previous debits with the same normalized description.
+    Transaction,
+}
+
+/// The typical value of a series of amounts: their median, and how much they spread around it.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct AnomalyBaseline {
+    pub median: f64,
+    pub spread: f64,
+    /// How many amounts the baseline was computed from.
+    pub samples: usize,
+}
+
+/// A tag spend or a debit far above its baseline.
+#[derive(Debug, Clone)]
+pub struct AnomalyRow {
+    pub kind: AnomalyKind,
+    /// The tag, or the description of the debit.
+    pub subject: String,
+    /// The date of the debit, if this is a transaction anomaly.
+    pub date: Option<NaiveDate>,
+    pub amount: f64,
+    pub baseline: AnomalyBaseline,
+    /// How many spreads above the median the amount is.
+    pub score: f64,
+}
+
+/// A debit or credit expected on a day of a forecast.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForecastEvent {
+    pub description: String,
+    /// Negative for debits.
+    pub amount: f64,
+    pub large_outflow: bool,
+}
+
+/// The projected balance of an account at the end of a day.
+#[derive(Debug, Clone)]
+pub struct ForecastRow {
+    pub account_name: String,
+    pub date: NaiveDate,
+    pub events: Vec<ForecastEvent>,
+    pub balance: f64,
+}
+
+/// A series of debits that recur with a regular cadence, like a subscription.
+#[derive(Debug, Clone)]
+pub struct RecurringRow {
+    pub account_name: String,
+    /// The description of the latest debit in the series.
+    pub description: String,
+    pub cadence: Cadence,
+    pub occurrences: usize,
+    /// The median amount.
+    pub typical_amount: f64,
+    pub last_amount: f64,
+    pub last_date: NaiveDate,
+    pub next_date: NaiveDate,
+    /// The cost over a year, at the last amount.
+    pub annualized_amount: f64,
+    /// The last increase in the amount, as (previous amount, new amount, date).
+    pub price_increase: Option<(f64, f64, NaiveDate)>,
+}
+
+/// A tag applied to a transaction by a tag rule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionTag {
+    pub transaction_id: i64,
+    pub tag_rule_id: i64,
+    pub tag: String,
+}
+
 pub trait QueryOperations {
     // FIXME These should be query_by_transaction?
-    fn query_debits(&self, filters: QueryFilters) -> Result<QueryResult<DebitsRow>>;
-    fn query_credits(&self, filters: QueryFilters) -> Result<QueryResult<CreditsRow>>;
-    fn query_by_tag(&self, filters: QueryFilters) -> Result<QueryResult<ByTagRow>>;
+    fn query_debits(
+        &self,
+        filters: QueryFilters,
+        order: &QueryOrder,
+    ) -> Result<QueryResult<DebitsRow>>;
+    fn query_credits(
+        &self,
+        filters: QueryFilters,
+        order: &QueryOrder,
+    ) -> Result<QueryResult<CreditsRow>>;
+    /// Debits and credits, with a chronological running total whatever their order.
+    fn query_transactions(
+        &self,
+        filters: QueryFilters,
+        order: &QueryOrder,
+    ) -> Result<QueryResult<TransactionsRow>>;
+    /// Tags are rolled up to their first `depth` path segments, if given.
+    fn query_by_tag(
+        &self,
+        filters: QueryFilters,
+        depth: Option<usize>,
+    ) -> Result<QueryResult<ByTagRow>>;
+    fn query_by_tag_tree(
+        &self,
+        filters: QueryFilters,
+        depth: Option<usize>,
+    ) -> Result<QueryResult<ByTagTreeRow>>;
     fn query_by_time(
         &self,
         filters: QueryFilters,
         aggregation: &TimeAggregation,
     ) -> Result<QueryResult<ByTimeRow>>;
+    /// The amounts are debits plus credits, so `filters` should select a single type of
+    /// transaction.
+    fn query_by_tag_time(
+        &self,
+        filters: QueryFilters,
+        aggregation: &TimeAggregation,
+        depth: Option<usize>,
+    ) -> Result<QueryResult<ByTagTimeRow>>;
 }
 
 pub trait UndoOperations {
@@ -136,6 +358,14 @@ pub trait StingyDatabase:
     ModelOperations<model::Account>
     + ModelOperations<model::Transaction>
     + ModelOperations<model::TagRule>
+    + ModelOperations<model::TagRuleConditionGroup>
+    + ModelOperations<model::TagRuleCondition>
+    + ModelOperations<model::Refund>
+    + ModelOperations<model::IgnoredTransaction>
+    + ModelOperations<model::Budget>
+    + ModelOperations<model::Envelope>
+    + ModelOperations<model::EnvelopeAllocation>
+    + ModelOperations<model::EnvelopeMove>
     + QueryOperations
     + UndoOperations
     + private::Reset
@@ -144,6 +374,12 @@ pub trait StingyDatabase:
     fn count_transactions(&self) -> Result<usize>;
     fn lookup_tag_rule(&self, model: &model::TagRule) -> Result<Option<i64>>;
     fn count_matching_transactions(&self, tag_rule_id: &str) -> Result<usize>;
+    fn count_tagged_transactions(&self) -> Result<usize>;
+    fn get_transactions_tags(&self) -> Result<Vec<TransactionTag>>;
+    /// Like get_transactions_tags, but ignoring the precedence of transaction ID tag rules.
+    fn get_tag_rules_matches(&self) -> Result<Vec<TransactionTag>>;
+    /// Run `action`, and then roll back all changes it made to the database.
+    fn dry_run(&self, action: &mut dyn FnMut() -> Result<()>) -> Result<()>;
     #[cfg(test)]
     fn insert_test_data(&self);
 }
@@ -10,6 +10,13 @@ pub enum TransactionType {
     DirectDebit,
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum BudgetPeriod {
+    #[default]
+    Monthly,
+    Yearly,
+}
+
 #[derive(Debug, Clone, FieldNamesAsArray)]
 #[field_names_as_array(visibility = "pub")]
 pub struct Account {
@@ -34,17 +41,102 @@ pub struct Transaction {
     pub currency: String,
 }
 
-#[derive(Default, Debug, Clone, FieldNamesAsArray)]
+#[derive(Default, Debug, Clone, PartialEq, FieldNamesAsArray)]
 #[field_names_as_array(visibility = "pub")]
 pub struct TagRule {
     pub id: Option<i64>,
     pub tag: String,
     pub human_readable: String,
     pub transaction_id: Option<i64>,
+    pub account: Option<String>,
     pub description_contains: Option<String>,
+    pub description_regex: Option<String>,
+    pub description_not_contains: Option<String>,
+    pub description_not_regex: Option<String>,
     pub transaction_type: Option<TransactionType>,
     pub amount_min: Option<f64>,
     pub amount_max: Option<f64>,
     pub from_date: Option<NaiveDate>,
     pub to_date: Option<NaiveDate>,
+    pub day_of_month_min: Option<i64>,
+    pub day_of_month_max: Option<i64>,
+    /// 0 is Sunday, and 6 is Saturday.
+    pub day_of_week: Option<i64>,
+    pub every_n_months: Option<i64>,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, FieldNamesAsArray)]
+#[field_names_as_array(visibility = "pub")]
+pub struct TagRuleConditionGroup {
+    pub id: Option<i64>,
+    pub tag_rule_id: i64,
+    /// Either "any" or "none".
+    pub operator: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, FieldNamesAsArray)]
+#[field_names_as_array(visibility = "pub")]
+pub struct TagRuleCondition {
+    pub id: Option<i64>,
+    pub group_id: i64,
+    /// One of "description_contains", "description_regex" or "account".
+    pub attribute: String,
+    pub value: String,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, FieldNamesAsArray)]
+#[field_names_as_array(visibility = "pub")]
+pub struct Refund {
+    pub id: Option<i64>,
+    pub debit_transaction_id: i64,
+    pub credit_transaction_id: i64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, FieldNamesAsArray)]
+#[field_names_as_array(visibility = "pub")]
+pub struct IgnoredTransaction {
+    pub id: Option<i64>,
+    pub transaction_id: i64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, FieldNamesAsArray)]
+#[field_names_as_array(visibility = "pub")]
+pub struct Budget {
+    pub id: Option<i64>,
+    /// Matched against the tags of transactions like the --tags query filter, so that a prefix
+    /// such as "travel/" includes all its sub-tags.
+    pub tag: String,
+    pub amount: f64,
+    pub period: BudgetPeriod,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, FieldNamesAsArray)]
+#[field_names_as_array(visibility = "pub")]
+pub struct Envelope {
+    pub id: Option<i64>,
+    /// Matched against the tags of transactions like the --tags query filter.
+    pub tag: String,
+    pub monthly_amount: f64,
+    pub rollover: bool,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, FieldNamesAsArray)]
+#[field_names_as_array(visibility = "pub")]
+pub struct EnvelopeAllocation {
+    pub id: Option<i64>,
+    pub envelope_id: i64,
+    /// The first day of the month.
+    pub month: NaiveDate,
+    pub amount: f64,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, FieldNamesAsArray)]
+#[field_names_as_array(visibility = "pub")]
+pub struct EnvelopeMove {
+    pub id: Option<i64>,
+    pub from_envelope_id: i64,
+    pub to_envelope_id: i64,
+    /// The first day of the month.
+    pub month: NaiveDate,
+    pub amount: f64,
 }
@@ -0,0 +1,37 @@
+CREATE TABLE refunds (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    debit_transaction_id INTEGER NOT NULL,
+    credit_transaction_id INTEGER NOT NULL,
+    FOREIGN KEY(debit_transaction_id) REFERENCES transactions(id) ON DELETE CASCADE,
+    FOREIGN KEY(credit_transaction_id) REFERENCES transactions(id) ON DELETE CASCADE,
+    -- A purchase can be refunded in parts, but each refund only refunds one
+    -- purchase.
+    UNIQUE(credit_transaction_id)
+);
+
+-- The transactions, with each linked refund netted against the purchase it
+-- refunds: the purchase's debit is reduced by the refunded amount, and the
+-- refund itself no longer counts as a credit. Aggregate queries select from
+-- this view instead of the transactions table, so that refunds are attributed
+-- to the purchase's tags and time window.
+CREATE VIEW transactions_net_of_refunds AS
+SELECT
+    transactions.id,
+    transactions.account_name,
+    transactions.posted_date,
+    transactions.description,
+    transactions.debit_amount - (
+        SELECT TOTAL(refund.credit_amount)
+        FROM refunds
+        JOIN transactions AS refund ON refunds.credit_transaction_id = refund.id
+        WHERE refunds.debit_transaction_id = transactions.id
+    ) AS debit_amount,
+    IIF(
+        transactions.id IN (SELECT credit_transaction_id FROM refunds),
+        0.0,
+        transactions.credit_amount
+    ) AS credit_amount,
+    transactions.balance,
+    transactions.transaction_type,
+    transactions.currency
+FROM transactions;
@@ -0,0 +1,162 @@
+-- The regexp() function behind the REGEXP operator is registered by the
+-- application on every connection, see sqlite_impl.rs.
+ALTER TABLE tag_rules
+ADD COLUMN description_regex TEXT;
+
+DROP TRIGGER tag_rules_unique_insert;
+DROP TRIGGER tag_rules_unique_update;
+DROP TRIGGER evaluate_tag_rule_on_tag_rule_insert;
+DROP TRIGGER evaluate_tag_rules_on_transaction_insert;
+DROP TRIGGER evaluate_tag_rules_on_transaction_update;
+DROP TRIGGER delete_lower_priority_tags_when_transaction_id_tag_rule_is_deleted;
+
+-- We can't just use a UNIQUE constraint because SQLite treats NULL
+-- values as different from one another (https://www.sqlite.org/nulls.html).
+CREATE TRIGGER tag_rules_unique_insert
+BEFORE INSERT ON tag_rules
+BEGIN
+    SELECT RAISE(FAIL, "UNIQUE constraint failed.")
+    FROM tag_rules
+    WHERE (
+        NEW.tag,
+        NEW.human_readable,
+        NEW.transaction_id,
+        NEW.description_contains,
+        NEW.description_regex,
+        NEW.transaction_type,
+        NEW.amount_min,
+        NEW.amount_max,
+        NEW.from_date,
+        NEW.to_date
+    ) IS (
+        tag,
+        human_readable,
+        transaction_id,
+        description_contains,
+        description_regex,
+        transaction_type,
+        amount_min,
+        amount_max,
+        from_date,
+        to_date
+    );
+END;
+
+CREATE TRIGGER tag_rules_unique_update
+BEFORE UPDATE ON tag_rules
+BEGIN
+    SELECT RAISE(FAIL, "UNIQUE constraint failed.")
+    FROM tag_rules
+    WHERE (
+        NEW.tag,
+        NEW.human_readable,
+        NEW.transaction_id,
+        NEW.description_contains,
+        NEW.description_regex,
+        NEW.transaction_type,
+        NEW.amount_min,
+        NEW.amount_max,
+        NEW.from_date,
+        NEW.to_date
+    ) IS (
+        tag,
+        human_readable,
+        transaction_id,
+        description_contains,
+        description_regex,
+        transaction_type,
+        amount_min,
+        amount_max,
+        from_date,
+        to_date
+    );
+END;
+
+CREATE TRIGGER evaluate_tag_rule_on_tag_rule_insert
+AFTER INSERT ON tag_rules
+BEGIN
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transactions.id, NEW.id
+    FROM transactions
+    WHERE (
+        transactions.id = IFNULL(NEW.transaction_id, transactions.id) AND
+        INSTR(LOWER(transactions.transaction_type), LOWER(IFNULL(NEW.transaction_type, ""))) AND
+        INSTR(LOWER(transactions.description), LOWER(IFNULL(NEW.description_contains, ""))) AND
+        (NEW.description_regex IS NULL OR transactions.description REGEXP NEW.description_regex) AND
+        MAX(transactions.debit_amount, transactions.credit_amount) >= IFNULL(NEW.amount_min, 0.0) AND
+        MAX(transactions.debit_amount, transactions.credit_amount) < IFNULL(NEW.amount_max, 9e999) AND
+        transactions.posted_date >= IFNULL(NEW.from_date, "-Inf") AND
+        transactions.posted_date <= IFNULL(NEW.to_date, "Inf")
+    );
+END;
+
+CREATE TRIGGER evaluate_tag_rules_on_transaction_insert
+AFTER INSERT ON transactions
+BEGIN
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT NEW.id, tag_rules.id
+    FROM tag_rules
+    WHERE (
+        NEW.id = IFNULL(tag_rules.transaction_id, NEW.id) AND
+        INSTR(LOWER(NEW.transaction_type), LOWER(IFNULL(tag_rules.transaction_type, ""))) AND
+        INSTR(LOWER(NEW.description), LOWER(IFNULL(tag_rules.description_contains, ""))) AND
+        (tag_rules.description_regex IS NULL OR NEW.description REGEXP tag_rules.description_regex) AND
+        MAX(NEW.debit_amount, NEW.credit_amount) >= IFNULL(tag_rules.amount_min, 0.0) AND
+        MAX(NEW.debit_amount, NEW.credit_amount) < IFNULL(tag_rules.amount_max, 9e999) AND
+        NEW.posted_date >= IFNULL(tag_rules.from_date, "-Inf") AND
+        NEW.posted_date <= IFNULL(tag_rules.to_date, "Inf")
+    );
+END;
+
+CREATE TRIGGER evaluate_tag_rules_on_transaction_update
+AFTER UPDATE ON transactions
+BEGIN
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT NEW.id, tag_rules.id
+    FROM tag_rules
+    WHERE (
+        NEW.id = IFNULL(tag_rules.transaction_id, NEW.id) AND
+        INSTR(LOWER(NEW.transaction_type), LOWER(IFNULL(tag_rules.transaction_type, ""))) AND
+        INSTR(LOWER(NEW.description), LOWER(IFNULL(tag_rules.description_contains, ""))) AND
+        (tag_rules.description_regex IS NULL OR NEW.description REGEXP tag_rules.description_regex) AND
+        MAX(NEW.debit_amount, NEW.credit_amount) >= IFNULL(tag_rules.amount_min, 0.0) AND
+        MAX(NEW.debit_amount, NEW.credit_amount) < IFNULL(tag_rules.amount_max, 9e999) AND
+        NEW.posted_date >= IFNULL(tag_rules.from_date, "-Inf") AND
+        NEW.posted_date <= IFNULL(tag_rules.to_date, "Inf")
+    );
+END;
+
+CREATE TRIGGER delete_lower_priority_tags_when_transaction_id_tag_rule_is_deleted
+AFTER DELETE ON tag_rules
+BEGIN
+    -- Evaluate all tag rules on the transaction that used to be tagged.
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT OLD.transaction_id, tag_rules.id
+    FROM transactions JOIN tag_rules
+    WHERE (
+        transactions.id = OLD.transaction_id AND
+        transactions.id = IFNULL(tag_rules.transaction_id, transactions.id) AND
+        INSTR(LOWER(transactions.transaction_type), LOWER(IFNULL(tag_rules.transaction_type, ""))) AND
+        INSTR(LOWER(transactions.description), LOWER(IFNULL(tag_rules.description_contains, ""))) AND
+        (tag_rules.description_regex IS NULL OR transactions.description REGEXP tag_rules.description_regex) AND
+        MAX(transactions.debit_amount, transactions.credit_amount) >= IFNULL(tag_rules.amount_min, 0.0) AND
+        MAX(transactions.debit_amount, transactions.credit_amount) < IFNULL(tag_rules.amount_max, 9e999) AND
+        transactions.posted_date >= IFNULL(tag_rules.from_date, "-Inf") AND
+        transactions.posted_date <= IFNULL(tag_rules.to_date, "Inf")
+    );
+    -- If there is a tag by transaction ID, remove all tags not set by that
+    -- attribute.
+    DELETE FROM transactions_tags
+    WHERE (transaction_id, tag_rule_id) IN (
+        SELECT transactions_tags.transaction_id, transactions_tags.tag_rule_id
+        FROM transactions_tags
+        JOIN tag_rules ON tag_rule_id = tag_rules.id
+        WHERE tag_rules.transaction_id IS NULL AND
+        transactions_tags.transaction_id IN (
+            SELECT DISTINCT transactions_tags.transaction_id
+            FROM transactions_tags
+            JOIN tag_rules ON tag_rule_id = tag_rules.id
+            WHERE tag_rules.transaction_id IS NOT NULL
+        )
+    );
+END;
@@ -0,0 +1,145 @@
+ALTER TABLE tag_rules
+ADD COLUMN account TEXT REFERENCES accounts(name);
+
+DROP TRIGGER tag_rules_unique_insert;
+DROP TRIGGER tag_rules_unique_update;
+DROP TRIGGER evaluate_tag_rule_on_tag_rule_insert;
+DROP TRIGGER evaluate_tag_rules_on_transaction_insert;
+DROP TRIGGER evaluate_tag_rules_on_transaction_update;
+DROP TRIGGER delete_lower_priority_tags_when_transaction_id_tag_rule_is_deleted;
+
+-- We can't just use a UNIQUE constraint because SQLite treats NULL
+-- values as different from one another (https://www.sqlite.org/nulls.html).
+CREATE TRIGGER tag_rules_unique_insert
+BEFORE INSERT ON tag_rules
+BEGIN
+    SELECT RAISE(FAIL, "UNIQUE constraint failed.")
+    FROM tag_rules
+    WHERE (
+        NEW.tag,
+        NEW.human_readable,
+        NEW.transaction_id,
+        NEW.account,
+        NEW.description_contains,
+        NEW.description_regex,
+        NEW.transaction_type,
+        NEW.amount_min,
+        NEW.amount_max,
+        NEW.from_date,
+        NEW.to_date
+    ) IS (
+        tag,
+        human_readable,
+        transaction_id,
+        account,
+        description_contains,
+        description_regex,
+        transaction_type,
+        amount_min,
+        amount_max,
+        from_date,
+        to_date
+    );
+END;
+
+CREATE TRIGGER tag_rules_unique_update
+BEFORE UPDATE ON tag_rules
+BEGIN
+    SELECT RAISE(FAIL, "UNIQUE constraint failed.")
+    FROM tag_rules
+    WHERE (
+        NEW.tag,
+        NEW.human_readable,
+        NEW.transaction_id,
+        NEW.account,
+        NEW.description_contains,
+        NEW.description_regex,
+        NEW.transaction_type,
+        NEW.amount_min,
+        NEW.amount_max,
+        NEW.from_date,
+        NEW.to_date
+    ) IS (
+        tag,
+        human_readable,
+        transaction_id,
+        account,
+        description_contains,
+        description_regex,
+        transaction_type,
+        amount_min,
+        amount_max,
+        from_date,
+        to_date
+    );
+END;
+
+-- The (transaction, tag rule) pairs where the tag rule's conditions match the
+-- transaction. The triggers below select from this view to evaluate tag rules,
+-- so that the conditions are only spelled out here.
+CREATE VIEW tag_rules_matches AS
+SELECT transactions.id AS transaction_id, tag_rules.id AS tag_rule_id
+FROM transactions JOIN tag_rules
+WHERE (
+    transactions.id = IFNULL(tag_rules.transaction_id, transactions.id) AND
+    transactions.account_name = IFNULL(tag_rules.account, transactions.account_name) AND
+    INSTR(LOWER(transactions.transaction_type), LOWER(IFNULL(tag_rules.transaction_type, ""))) AND
+    INSTR(LOWER(transactions.description), LOWER(IFNULL(tag_rules.description_contains, ""))) AND
+    (tag_rules.description_regex IS NULL OR transactions.description REGEXP tag_rules.description_regex) AND
+    MAX(transactions.debit_amount, transactions.credit_amount) >= IFNULL(tag_rules.amount_min, 0.0) AND
+    MAX(transactions.debit_amount, transactions.credit_amount) < IFNULL(tag_rules.amount_max, 9e999) AND
+    transactions.posted_date >= IFNULL(tag_rules.from_date, "-Inf") AND
+    transactions.posted_date <= IFNULL(tag_rules.to_date, "Inf")
+);
+
+CREATE TRIGGER evaluate_tag_rule_on_tag_rule_insert
+AFTER INSERT ON tag_rules
+BEGIN
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE tag_rule_id = NEW.id;
+END;
+
+CREATE TRIGGER evaluate_tag_rules_on_transaction_insert
+AFTER INSERT ON transactions
+BEGIN
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE transaction_id = NEW.id;
+END;
+
+CREATE TRIGGER evaluate_tag_rules_on_transaction_update
+AFTER UPDATE ON transactions
+BEGIN
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE transaction_id = NEW.id;
+END;
+
+CREATE TRIGGER delete_lower_priority_tags_when_transaction_id_tag_rule_is_deleted
+AFTER DELETE ON tag_rules
+BEGIN
+    -- Evaluate all tag rules on the transaction that used to be tagged.
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE transaction_id = OLD.transaction_id;
+    -- If there is a tag by transaction ID, remove all tags not set by that
+    -- attribute.
+    DELETE FROM transactions_tags
+    WHERE (transaction_id, tag_rule_id) IN (
+        SELECT transactions_tags.transaction_id, transactions_tags.tag_rule_id
+        FROM transactions_tags
+        JOIN tag_rules ON tag_rule_id = tag_rules.id
+        WHERE tag_rules.transaction_id IS NULL AND
+        transactions_tags.transaction_id IN (
+            SELECT DISTINCT transactions_tags.transaction_id
+            FROM transactions_tags
+            JOIN tag_rules ON tag_rule_id = tag_rules.id
+            WHERE tag_rules.transaction_id IS NOT NULL
+        )
+    );
+END;
@@ -0,0 +1,103 @@
+ALTER TABLE tag_rules
+ADD COLUMN description_not_contains TEXT;
+
+ALTER TABLE tag_rules
+ADD COLUMN description_not_regex TEXT;
+
+DROP TRIGGER tag_rules_unique_insert;
+DROP TRIGGER tag_rules_unique_update;
+DROP VIEW tag_rules_matches;
+
+-- We can't just use a UNIQUE constraint because SQLite treats NULL
+-- values as different from one another (https://www.sqlite.org/nulls.html).
+CREATE TRIGGER tag_rules_unique_insert
+BEFORE INSERT ON tag_rules
+BEGIN
+    SELECT RAISE(FAIL, "UNIQUE constraint failed.")
+    FROM tag_rules
+    WHERE (
+        NEW.tag,
+        NEW.human_readable,
+        NEW.transaction_id,
+        NEW.account,
+        NEW.description_contains,
+        NEW.description_regex,
+        NEW.description_not_contains,
+        NEW.description_not_regex,
+        NEW.transaction_type,
+        NEW.amount_min,
+        NEW.amount_max,
+        NEW.from_date,
+        NEW.to_date
+    ) IS (
+        tag,
+        human_readable,
+        transaction_id,
+        account,
+        description_contains,
+        description_regex,
+        description_not_contains,
+        description_not_regex,
+        transaction_type,
+        amount_min,
+        amount_max,
+        from_date,
+        to_date
+    );
+END;
+
+CREATE TRIGGER tag_rules_unique_update
+BEFORE UPDATE ON tag_rules
+BEGIN
+    SELECT RAISE(FAIL, "UNIQUE constraint failed.")
+    FROM tag_rules
+    WHERE (
+        NEW.tag,
+        NEW.human_readable,
+        NEW.transaction_id,
+        NEW.account,
+        NEW.description_contains,
+        NEW.description_regex,
+        NEW.description_not_contains,
+        NEW.description_not_regex,
+        NEW.transaction_type,
+        NEW.amount_min,
+        NEW.amount_max,
+        NEW.from_date,
+        NEW.to_date
+    ) IS (
+        tag,
+        human_readable,
+        transaction_id,
+        account,
+        description_contains,
+        description_regex,
+        description_not_contains,
+        description_not_regex,
+        transaction_type,
+        amount_min,
+        amount_max,
+        from_date,
+        to_date
+    );
+END;
+
+-- The (transaction, tag rule) pairs where the tag rule's conditions match the
+-- transaction. The triggers below select from this view to evaluate tag rules,
+-- so that the conditions are only spelled out here.
+CREATE VIEW tag_rules_matches AS
+SELECT transactions.id AS transaction_id, tag_rules.id AS tag_rule_id
+FROM transactions JOIN tag_rules
+WHERE (
+    transactions.id = IFNULL(tag_rules.transaction_id, transactions.id) AND
+    transactions.account_name = IFNULL(tag_rules.account, transactions.account_name) AND
+    INSTR(LOWER(transactions.transaction_type), LOWER(IFNULL(tag_rules.transaction_type, ""))) AND
+    INSTR(LOWER(transactions.description), LOWER(IFNULL(tag_rules.description_contains, ""))) AND
+    (tag_rules.description_regex IS NULL OR transactions.description REGEXP tag_rules.description_regex) AND
+    (tag_rules.description_not_contains IS NULL OR NOT INSTR(LOWER(transactions.description), LOWER(tag_rules.description_not_contains))) AND
+    (tag_rules.description_not_regex IS NULL OR NOT transactions.description REGEXP tag_rules.description_not_regex) AND
+    MAX(transactions.debit_amount, transactions.credit_amount) >= IFNULL(tag_rules.amount_min, 0.0) AND
+    MAX(transactions.debit_amount, transactions.credit_amount) < IFNULL(tag_rules.amount_max, 9e999) AND
+    transactions.posted_date >= IFNULL(tag_rules.from_date, "-Inf") AND
+    transactions.posted_date <= IFNULL(tag_rules.to_date, "Inf")
+);
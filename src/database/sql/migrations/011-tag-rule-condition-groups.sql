@@ -0,0 +1,174 @@
+-- Composite tag rules: on top of its own attributes, a tag rule can have groups
+-- of conditions, all of which must match the transaction.
+CREATE TABLE tag_rule_condition_groups (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tag_rule_id INTEGER NOT NULL,
+    operator TEXT NOT NULL CHECK (operator IN ('any', 'none')),
+    FOREIGN KEY (tag_rule_id) REFERENCES tag_rules(id) ON DELETE CASCADE
+);
+
+CREATE TABLE tag_rule_conditions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    group_id INTEGER NOT NULL,
+    attribute TEXT NOT NULL CHECK (
+        attribute IN ('description_contains', 'description_regex', 'account')
+    ),
+    value TEXT NOT NULL,
+    FOREIGN KEY (group_id) REFERENCES tag_rule_condition_groups(id) ON DELETE CASCADE
+);
+
+DROP VIEW tag_rules_matches;
+
+-- The (transaction, tag rule) pairs where the tag rule's conditions match the
+-- transaction. The triggers below select from this view to evaluate tag rules,
+-- so that the conditions are only spelled out here.
+CREATE VIEW tag_rules_matches AS
+SELECT transactions.id AS transaction_id, tag_rules.id AS tag_rule_id
+FROM transactions JOIN tag_rules
+WHERE (
+    transactions.id = IFNULL(tag_rules.transaction_id, transactions.id) AND
+    transactions.account_name = IFNULL(tag_rules.account, transactions.account_name) AND
+    INSTR(LOWER(transactions.transaction_type), LOWER(IFNULL(tag_rules.transaction_type, ""))) AND
+    INSTR(LOWER(transactions.description), LOWER(IFNULL(tag_rules.description_contains, ""))) AND
+    (tag_rules.description_regex IS NULL OR transactions.description REGEXP tag_rules.description_regex) AND
+    (tag_rules.description_not_contains IS NULL OR NOT INSTR(LOWER(transactions.description), LOWER(tag_rules.description_not_contains))) AND
+    (tag_rules.description_not_regex IS NULL OR NOT transactions.description REGEXP tag_rules.description_not_regex) AND
+    MAX(transactions.debit_amount, transactions.credit_amount) >= IFNULL(tag_rules.amount_min, 0.0) AND
+    MAX(transactions.debit_amount, transactions.credit_amount) < IFNULL(tag_rules.amount_max, 9e999) AND
+    transactions.posted_date >= IFNULL(tag_rules.from_date, "-Inf") AND
+    transactions.posted_date <= IFNULL(tag_rules.to_date, "Inf") AND
+    -- Every condition group must match: an 'any' group when at least one of
+    -- its conditions matches, and a 'none' group when none of them do.
+    NOT EXISTS (
+        SELECT 1
+        FROM tag_rule_condition_groups AS condition_groups
+        WHERE condition_groups.tag_rule_id = tag_rules.id AND
+        (condition_groups.operator = 'none') = EXISTS (
+            SELECT 1
+            FROM tag_rule_conditions AS conditions
+            WHERE conditions.group_id = condition_groups.id AND
+            CASE conditions.attribute
+                WHEN 'description_contains' THEN
+                    INSTR(LOWER(transactions.description), LOWER(conditions.value))
+                WHEN 'description_regex' THEN
+                    transactions.description REGEXP conditions.value
+                WHEN 'account' THEN
+                    transactions.account_name = conditions.value
+            END
+        )
+    )
+);
+
+-- Re-evaluate a tag rule when its condition groups change. Its groups and
+-- conditions are inserted after the tag rule itself.
+CREATE TRIGGER evaluate_tag_rule_on_condition_group_insert
+AFTER INSERT ON tag_rule_condition_groups
+BEGIN
+    DELETE FROM transactions_tags
+    WHERE tag_rule_id = NEW.tag_rule_id;
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE tag_rule_id = NEW.tag_rule_id;
+    -- If there is a tag by transaction ID, remove all tags not set by that
+    -- attribute.
+    DELETE FROM transactions_tags
+    WHERE (transaction_id, tag_rule_id) IN (
+        SELECT transactions_tags.transaction_id, transactions_tags.tag_rule_id
+        FROM transactions_tags
+        JOIN tag_rules ON tag_rule_id = tag_rules.id
+        WHERE tag_rules.transaction_id IS NULL AND
+        transactions_tags.transaction_id IN (
+            SELECT DISTINCT transactions_tags.transaction_id
+            FROM transactions_tags
+            JOIN tag_rules ON tag_rule_id = tag_rules.id
+            WHERE tag_rules.transaction_id IS NOT NULL
+        )
+    );
+END;
+
+CREATE TRIGGER evaluate_tag_rule_on_condition_group_delete
+AFTER DELETE ON tag_rule_condition_groups
+BEGIN
+    DELETE FROM transactions_tags
+    WHERE tag_rule_id = OLD.tag_rule_id;
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE tag_rule_id = OLD.tag_rule_id;
+    -- If there is a tag by transaction ID, remove all tags not set by that
+    -- attribute.
+    DELETE FROM transactions_tags
+    WHERE (transaction_id, tag_rule_id) IN (
+        SELECT transactions_tags.transaction_id, transactions_tags.tag_rule_id
+        FROM transactions_tags
+        JOIN tag_rules ON tag_rule_id = tag_rules.id
+        WHERE tag_rules.transaction_id IS NULL AND
+        transactions_tags.transaction_id IN (
+            SELECT DISTINCT transactions_tags.transaction_id
+            FROM transactions_tags
+            JOIN tag_rules ON tag_rule_id = tag_rules.id
+            WHERE tag_rules.transaction_id IS NOT NULL
+        )
+    );
+END;
+
+CREATE TRIGGER evaluate_tag_rule_on_condition_insert
+AFTER INSERT ON tag_rule_conditions
+BEGIN
+    DELETE FROM transactions_tags
+    WHERE tag_rule_id = (
+        SELECT tag_rule_id FROM tag_rule_condition_groups WHERE id = NEW.group_id
+    );
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE tag_rule_id = (
+        SELECT tag_rule_id FROM tag_rule_condition_groups WHERE id = NEW.group_id
+    );
+    -- If there is a tag by transaction ID, remove all tags not set by that
+    -- attribute.
+    DELETE FROM transactions_tags
+    WHERE (transaction_id, tag_rule_id) IN (
+        SELECT transactions_tags.transaction_id, transactions_tags.tag_rule_id
+        FROM transactions_tags
+        JOIN tag_rules ON tag_rule_id = tag_rules.id
+        WHERE tag_rules.transaction_id IS NULL AND
+        transactions_tags.transaction_id IN (
+            SELECT DISTINCT transactions_tags.transaction_id
+            FROM transactions_tags
+            JOIN tag_rules ON tag_rule_id = tag_rules.id
+            WHERE tag_rules.transaction_id IS NOT NULL
+        )
+    );
+END;
+
+CREATE TRIGGER evaluate_tag_rule_on_condition_delete
+AFTER DELETE ON tag_rule_conditions
+BEGIN
+    DELETE FROM transactions_tags
+    WHERE tag_rule_id = (
+        SELECT tag_rule_id FROM tag_rule_condition_groups WHERE id = OLD.group_id
+    );
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE tag_rule_id = (
+        SELECT tag_rule_id FROM tag_rule_condition_groups WHERE id = OLD.group_id
+    );
+    -- If there is a tag by transaction ID, remove all tags not set by that
+    -- attribute.
+    DELETE FROM transactions_tags
+    WHERE (transaction_id, tag_rule_id) IN (
+        SELECT transactions_tags.transaction_id, transactions_tags.tag_rule_id
+        FROM transactions_tags
+        JOIN tag_rules ON tag_rule_id = tag_rules.id
+        WHERE tag_rules.transaction_id IS NULL AND
+        transactions_tags.transaction_id IN (
+            SELECT DISTINCT transactions_tags.transaction_id
+            FROM transactions_tags
+            JOIN tag_rules ON tag_rule_id = tag_rules.id
+            WHERE tag_rules.transaction_id IS NOT NULL
+        )
+    );
+END;
@@ -0,0 +1,31 @@
+CREATE TRIGGER evaluate_tag_rule_on_tag_rule_update
+AFTER UPDATE ON tag_rules
+BEGIN
+    DELETE FROM transactions_tags
+    WHERE tag_rule_id = NEW.id;
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE tag_rule_id = NEW.id;
+    -- If the rule used to have a transaction ID, the transaction may be
+    -- matched by other tag rules now.
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE transaction_id = OLD.transaction_id;
+    -- If there is a tag by transaction ID, remove all tags not set by that
+    -- attribute.
+    DELETE FROM transactions_tags
+    WHERE (transaction_id, tag_rule_id) IN (
+        SELECT transactions_tags.transaction_id, transactions_tags.tag_rule_id
+        FROM transactions_tags
+        JOIN tag_rules ON tag_rule_id = tag_rules.id
+        WHERE tag_rules.transaction_id IS NULL AND
+        transactions_tags.transaction_id IN (
+            SELECT DISTINCT transactions_tags.transaction_id
+            FROM transactions_tags
+            JOIN tag_rules ON tag_rule_id = tag_rules.id
+            WHERE tag_rules.transaction_id IS NOT NULL
+        )
+    );
+END;
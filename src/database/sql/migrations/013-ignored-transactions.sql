@@ -0,0 +1,8 @@
+-- Transactions that are deliberately left untagged, so that tags triage
+-- doesn't offer to tag them again.
+CREATE TABLE ignored_transactions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    transaction_id INTEGER NOT NULL,
+    FOREIGN KEY(transaction_id) REFERENCES transactions(id) ON DELETE CASCADE,
+    UNIQUE(transaction_id)
+);
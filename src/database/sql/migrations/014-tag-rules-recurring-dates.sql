@@ -0,0 +1,170 @@
+-- Recurring-date conditions: a range of days of the month, a day of the week
+-- (0 is Sunday, as in STRFTIME('%w')), and every N months.
+ALTER TABLE tag_rules
+ADD COLUMN day_of_month_min INTEGER;
+
+ALTER TABLE tag_rules
+ADD COLUMN day_of_month_max INTEGER;
+
+ALTER TABLE tag_rules
+ADD COLUMN day_of_week INTEGER;
+
+ALTER TABLE tag_rules
+ADD COLUMN every_n_months INTEGER;
+
+DROP TRIGGER tag_rules_unique_insert;
+DROP TRIGGER tag_rules_unique_update;
+DROP VIEW tag_rules_matches;
+
+-- We can't just use a UNIQUE constraint because SQLite treats NULL
+-- values as different from one another (https://www.sqlite.org/nulls.html).
+CREATE TRIGGER tag_rules_unique_insert
+BEFORE INSERT ON tag_rules
+BEGIN
+    SELECT RAISE(FAIL, "UNIQUE constraint failed.")
+    FROM tag_rules
+    WHERE (
+        NEW.tag,
+        NEW.human_readable,
+        NEW.transaction_id,
+        NEW.account,
+        NEW.description_contains,
+        NEW.description_regex,
+        NEW.description_not_contains,
+        NEW.description_not_regex,
+        NEW.transaction_type,
+        NEW.amount_min,
+        NEW.amount_max,
+        NEW.from_date,
+        NEW.to_date,
+        NEW.day_of_month_min,
+        NEW.day_of_month_max,
+        NEW.day_of_week,
+        NEW.every_n_months
+    ) IS (
+        tag,
+        human_readable,
+        transaction_id,
+        account,
+        description_contains,
+        description_regex,
+        description_not_contains,
+        description_not_regex,
+        transaction_type,
+        amount_min,
+        amount_max,
+        from_date,
+        to_date,
+        day_of_month_min,
+        day_of_month_max,
+        day_of_week,
+        every_n_months
+    );
+END;
+
+CREATE TRIGGER tag_rules_unique_update
+BEFORE UPDATE ON tag_rules
+BEGIN
+    SELECT RAISE(FAIL, "UNIQUE constraint failed.")
+    FROM tag_rules
+    WHERE (
+        NEW.tag,
+        NEW.human_readable,
+        NEW.transaction_id,
+        NEW.account,
+        NEW.description_contains,
+        NEW.description_regex,
+        NEW.description_not_contains,
+        NEW.description_not_regex,
+        NEW.transaction_type,
+        NEW.amount_min,
+        NEW.amount_max,
+        NEW.from_date,
+        NEW.to_date,
+        NEW.day_of_month_min,
+        NEW.day_of_month_max,
+        NEW.day_of_week,
+        NEW.every_n_months
+    ) IS (
+        tag,
+        human_readable,
+        transaction_id,
+        account,
+        description_contains,
+        description_regex,
+        description_not_contains,
+        description_not_regex,
+        transaction_type,
+        amount_min,
+        amount_max,
+        from_date,
+        to_date,
+        day_of_month_min,
+        day_of_month_max,
+        day_of_week,
+        every_n_months
+    );
+END;
+
+-- The (transaction, tag rule) pairs where the tag rule's conditions match the
+-- transaction. The triggers below select from this view to evaluate tag rules,
+-- so that the conditions are only spelled out here.
+CREATE VIEW tag_rules_matches AS
+SELECT transactions.id AS transaction_id, tag_rules.id AS tag_rule_id
+FROM transactions JOIN tag_rules
+WHERE (
+    transactions.id = IFNULL(tag_rules.transaction_id, transactions.id) AND
+    transactions.account_name = IFNULL(tag_rules.account, transactions.account_name) AND
+    INSTR(LOWER(transactions.transaction_type), LOWER(IFNULL(tag_rules.transaction_type, ""))) AND
+    INSTR(LOWER(transactions.description), LOWER(IFNULL(tag_rules.description_contains, ""))) AND
+    (tag_rules.description_regex IS NULL OR transactions.description REGEXP tag_rules.description_regex) AND
+    (tag_rules.description_not_contains IS NULL OR NOT INSTR(LOWER(transactions.description), LOWER(tag_rules.description_not_contains))) AND
+    (tag_rules.description_not_regex IS NULL OR NOT transactions.description REGEXP tag_rules.description_not_regex) AND
+    MAX(transactions.debit_amount, transactions.credit_amount) >= IFNULL(tag_rules.amount_min, 0.0) AND
+    MAX(transactions.debit_amount, transactions.credit_amount) < IFNULL(tag_rules.amount_max, 9e999) AND
+    transactions.posted_date >= IFNULL(tag_rules.from_date, "-Inf") AND
+    transactions.posted_date <= IFNULL(tag_rules.to_date, "Inf") AND
+    -- Day-of-month ranges wrap around the end of the month when the minimum is
+    -- larger than the maximum, as in 28-3.
+    (tag_rules.day_of_month_min IS NULL OR
+        CASE WHEN tag_rules.day_of_month_min <= tag_rules.day_of_month_max THEN
+            CAST(STRFTIME('%d', transactions.posted_date) AS INTEGER)
+                BETWEEN tag_rules.day_of_month_min AND tag_rules.day_of_month_max
+        ELSE
+            CAST(STRFTIME('%d', transactions.posted_date) AS INTEGER) >= tag_rules.day_of_month_min OR
+            CAST(STRFTIME('%d', transactions.posted_date) AS INTEGER) <= tag_rules.day_of_month_max
+        END) AND
+    (tag_rules.day_of_week IS NULL OR
+        CAST(STRFTIME('%w', transactions.posted_date) AS INTEGER) = tag_rules.day_of_week) AND
+    -- Months are counted from the start of the tag rule's period, or from
+    -- January when it has none ("-Inf" isn't a date, so STRFTIME is NULL).
+    (tag_rules.every_n_months IS NULL OR (
+        CAST(STRFTIME('%Y', transactions.posted_date) AS INTEGER) * 12 +
+        CAST(STRFTIME('%m', transactions.posted_date) AS INTEGER) -
+        IFNULL(
+            CAST(STRFTIME('%Y', tag_rules.from_date) AS INTEGER) * 12 +
+            CAST(STRFTIME('%m', tag_rules.from_date) AS INTEGER),
+            1
+        )
+    ) % tag_rules.every_n_months = 0) AND
+    -- Every condition group must match: an 'any' group when at least one of
+    -- its conditions matches, and a 'none' group when none of them do.
+    NOT EXISTS (
+        SELECT 1
+        FROM tag_rule_condition_groups AS condition_groups
+        WHERE condition_groups.tag_rule_id = tag_rules.id AND
+        (condition_groups.operator = 'none') = EXISTS (
+            SELECT 1
+            FROM tag_rule_conditions AS conditions
+            WHERE conditions.group_id = condition_groups.id AND
+            CASE conditions.attribute
+                WHEN 'description_contains' THEN
+                    INSTR(LOWER(transactions.description), LOWER(conditions.value))
+                WHEN 'description_regex' THEN
+                    transactions.description REGEXP conditions.value
+                WHEN 'account' THEN
+                    transactions.account_name = conditions.value
+            END
+        )
+    )
+);
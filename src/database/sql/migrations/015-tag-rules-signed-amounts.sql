@@ -0,0 +1,437 @@
+-- Tag rules can match direct debits only, and amount ranges with a negative
+-- bound are signed. Changing the CHECK constraint on the transaction type needs
+-- the table to be rebuilt, so everything that refers to it is dropped first,
+-- and recreated afterwards.
+DROP VIEW tag_rules_matches;
+DROP TRIGGER evaluate_tag_rules_on_transaction_insert;
+DROP TRIGGER evaluate_tag_rules_on_transaction_update;
+DROP TRIGGER evaluate_tag_rule_on_condition_group_insert;
+DROP TRIGGER evaluate_tag_rule_on_condition_group_delete;
+DROP TRIGGER evaluate_tag_rule_on_condition_insert;
+DROP TRIGGER evaluate_tag_rule_on_condition_delete;
+
+CREATE TABLE new_tag_rules (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tag TEXT NOT NULL,
+    human_readable TEXT NOT NULL,
+    transaction_id INTEGER,
+    description_contains TEXT,
+    transaction_type TEXT,
+    amount_min REAL,
+    amount_max REAL,
+    from_date TEXT,
+    to_date TEXT,
+    description_regex TEXT,
+    account TEXT REFERENCES accounts(name),
+    description_not_contains TEXT,
+    description_not_regex TEXT,
+    day_of_month_min INTEGER,
+    day_of_month_max INTEGER,
+    day_of_week INTEGER,
+    every_n_months INTEGER,
+    FOREIGN KEY(transaction_id) REFERENCES transactions(id) ON DELETE CASCADE
+    -- A "Debit" tag rule matches direct debits too.
+    CHECK(transaction_type IN ('Debit', 'Credit', 'Direct Debit'))
+);
+
+INSERT INTO new_tag_rules
+SELECT
+    id,
+    tag,
+    human_readable,
+    transaction_id,
+    description_contains,
+    transaction_type,
+    -- Unbounded minimums used to be stored as the smallest REAL, which would
+    -- now be a negative bound. Like other unbounded attributes, they are NULL.
+    NULLIF(amount_min, -1.7976931348623157e308),
+    amount_max,
+    from_date,
+    to_date,
+    description_regex,
+    account,
+    description_not_contains,
+    description_not_regex,
+    day_of_month_min,
+    day_of_month_max,
+    day_of_week,
+    every_n_months
+FROM tag_rules;
+DROP TABLE tag_rules;
+ALTER TABLE new_tag_rules RENAME TO tag_rules;
+
+-- The (transaction, tag rule) pairs where the tag rule's conditions match the
+-- transaction. The triggers below select from this view to evaluate tag rules,
+-- so that the conditions are only spelled out here.
+CREATE VIEW tag_rules_matches AS
+SELECT transaction_id, tag_rule_id
+FROM (
+    SELECT
+        transactions.id AS transaction_id,
+        tag_rules.id AS tag_rule_id,
+        tag_rules.amount_min,
+        tag_rules.amount_max,
+        -- Amount ranges with a negative bound are signed, with debits as
+        -- negative amounts. Other ranges match the amount regardless of its
+        -- sign. Unbounded minimums are NULL, not negative bounds.
+        IIF(
+            tag_rules.amount_min < 0 OR tag_rules.amount_max < 0,
+            transactions.credit_amount - transactions.debit_amount,
+            MAX(transactions.debit_amount, transactions.credit_amount)
+        ) AS amount
+    FROM transactions JOIN tag_rules
+    WHERE (
+        transactions.id = IFNULL(tag_rules.transaction_id, transactions.id) AND
+        transactions.account_name = IFNULL(tag_rules.account, transactions.account_name) AND
+        INSTR(LOWER(transactions.transaction_type), LOWER(IFNULL(tag_rules.transaction_type, ""))) AND
+        INSTR(LOWER(transactions.description), LOWER(IFNULL(tag_rules.description_contains, ""))) AND
+        (tag_rules.description_regex IS NULL OR transactions.description REGEXP tag_rules.description_regex) AND
+        (tag_rules.description_not_contains IS NULL OR NOT INSTR(LOWER(transactions.description), LOWER(tag_rules.description_not_contains))) AND
+        (tag_rules.description_not_regex IS NULL OR NOT transactions.description REGEXP tag_rules.description_not_regex) AND
+        transactions.posted_date >= IFNULL(tag_rules.from_date, "-Inf") AND
+        transactions.posted_date <= IFNULL(tag_rules.to_date, "Inf") AND
+        -- Day-of-month ranges wrap around the end of the month when the minimum is
+        -- larger than the maximum, as in 28-3.
+        (tag_rules.day_of_month_min IS NULL OR
+            CASE WHEN tag_rules.day_of_month_min <= tag_rules.day_of_month_max THEN
+                CAST(STRFTIME('%d', transactions.posted_date) AS INTEGER)
+                    BETWEEN tag_rules.day_of_month_min AND tag_rules.day_of_month_max
+            ELSE
+                CAST(STRFTIME('%d', transactions.posted_date) AS INTEGER) >= tag_rules.day_of_month_min OR
+                CAST(STRFTIME('%d', transactions.posted_date) AS INTEGER) <= tag_rules.day_of_month_max
+            END) AND
+        (tag_rules.day_of_week IS NULL OR
+            CAST(STRFTIME('%w', transactions.posted_date) AS INTEGER) = tag_rules.day_of_week) AND
+        -- Months are counted from the start of the tag rule's period, or from
+        -- January when it has none ("-Inf" isn't a date, so STRFTIME is NULL).
+        (tag_rules.every_n_months IS NULL OR (
+            CAST(STRFTIME('%Y', transactions.posted_date) AS INTEGER) * 12 +
+            CAST(STRFTIME('%m', transactions.posted_date) AS INTEGER) -
+            IFNULL(
+                CAST(STRFTIME('%Y', tag_rules.from_date) AS INTEGER) * 12 +
+                CAST(STRFTIME('%m', tag_rules.from_date) AS INTEGER),
+                1
+            )
+        ) % tag_rules.every_n_months = 0) AND
+        -- Every condition group must match: an 'any' group when at least one of
+        -- its conditions matches, and a 'none' group when none of them do.
+        NOT EXISTS (
+            SELECT 1
+            FROM tag_rule_condition_groups AS condition_groups
+            WHERE condition_groups.tag_rule_id = tag_rules.id AND
+            (condition_groups.operator = 'none') = EXISTS (
+                SELECT 1
+                FROM tag_rule_conditions AS conditions
+                WHERE conditions.group_id = condition_groups.id AND
+                CASE conditions.attribute
+                    WHEN 'description_contains' THEN
+                        INSTR(LOWER(transactions.description), LOWER(conditions.value))
+                    WHEN 'description_regex' THEN
+                        transactions.description REGEXP conditions.value
+                    WHEN 'account' THEN
+                        transactions.account_name = conditions.value
+                END
+            )
+        )
+    )
+)
+WHERE amount >= IFNULL(amount_min, -9e999) AND amount < IFNULL(amount_max, 9e999);
+
+CREATE TRIGGER delete_lower_priority_tags_when_transaction_id_tag_rule_is_added
+AFTER INSERT ON tag_rules
+BEGIN
+    DELETE FROM transactions_tags
+    WHERE (
+        NEW.transaction_id IS NOT NULL AND
+        transactions_tags.transaction_id = NEW.transaction_id AND
+        transactions_tags.tag_rule_id NOT IN (
+            SELECT id FROM tag_rules WHERE transaction_id IS NOT NULL
+        )
+    );
+END;
+
+CREATE TRIGGER evaluate_tag_rule_on_tag_rule_insert
+AFTER INSERT ON tag_rules
+BEGIN
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE tag_rule_id = NEW.id;
+END;
+
+CREATE TRIGGER evaluate_tag_rules_on_transaction_insert
+AFTER INSERT ON transactions
+BEGIN
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE transaction_id = NEW.id;
+END;
+
+CREATE TRIGGER evaluate_tag_rules_on_transaction_update
+AFTER UPDATE ON transactions
+BEGIN
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE transaction_id = NEW.id;
+END;
+
+CREATE TRIGGER delete_lower_priority_tags_when_transaction_id_tag_rule_is_deleted
+AFTER DELETE ON tag_rules
+BEGIN
+    -- Evaluate all tag rules on the transaction that used to be tagged.
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE transaction_id = OLD.transaction_id;
+    -- If there is a tag by transaction ID, remove all tags not set by that
+    -- attribute.
+    DELETE FROM transactions_tags
+    WHERE (transaction_id, tag_rule_id) IN (
+        SELECT transactions_tags.transaction_id, transactions_tags.tag_rule_id
+        FROM transactions_tags
+        JOIN tag_rules ON tag_rule_id = tag_rules.id
+        WHERE tag_rules.transaction_id IS NULL AND
+        transactions_tags.transaction_id IN (
+            SELECT DISTINCT transactions_tags.transaction_id
+            FROM transactions_tags
+            JOIN tag_rules ON tag_rule_id = tag_rules.id
+            WHERE tag_rules.transaction_id IS NOT NULL
+        )
+    );
+END;
+
+CREATE TRIGGER evaluate_tag_rule_on_condition_group_insert
+AFTER INSERT ON tag_rule_condition_groups
+BEGIN
+    DELETE FROM transactions_tags
+    WHERE tag_rule_id = NEW.tag_rule_id;
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE tag_rule_id = NEW.tag_rule_id;
+    -- If there is a tag by transaction ID, remove all tags not set by that
+    -- attribute.
+    DELETE FROM transactions_tags
+    WHERE (transaction_id, tag_rule_id) IN (
+        SELECT transactions_tags.transaction_id, transactions_tags.tag_rule_id
+        FROM transactions_tags
+        JOIN tag_rules ON tag_rule_id = tag_rules.id
+        WHERE tag_rules.transaction_id IS NULL AND
+        transactions_tags.transaction_id IN (
+            SELECT DISTINCT transactions_tags.transaction_id
+            FROM transactions_tags
+            JOIN tag_rules ON tag_rule_id = tag_rules.id
+            WHERE tag_rules.transaction_id IS NOT NULL
+        )
+    );
+END;
+
+CREATE TRIGGER evaluate_tag_rule_on_condition_group_delete
+AFTER DELETE ON tag_rule_condition_groups
+BEGIN
+    DELETE FROM transactions_tags
+    WHERE tag_rule_id = OLD.tag_rule_id;
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE tag_rule_id = OLD.tag_rule_id;
+    -- If there is a tag by transaction ID, remove all tags not set by that
+    -- attribute.
+    DELETE FROM transactions_tags
+    WHERE (transaction_id, tag_rule_id) IN (
+        SELECT transactions_tags.transaction_id, transactions_tags.tag_rule_id
+        FROM transactions_tags
+        JOIN tag_rules ON tag_rule_id = tag_rules.id
+        WHERE tag_rules.transaction_id IS NULL AND
+        transactions_tags.transaction_id IN (
+            SELECT DISTINCT transactions_tags.transaction_id
+            FROM transactions_tags
+            JOIN tag_rules ON tag_rule_id = tag_rules.id
+            WHERE tag_rules.transaction_id IS NOT NULL
+        )
+    );
+END;
+
+CREATE TRIGGER evaluate_tag_rule_on_condition_insert
+AFTER INSERT ON tag_rule_conditions
+BEGIN
+    DELETE FROM transactions_tags
+    WHERE tag_rule_id = (
+        SELECT tag_rule_id FROM tag_rule_condition_groups WHERE id = NEW.group_id
+    );
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE tag_rule_id = (
+        SELECT tag_rule_id FROM tag_rule_condition_groups WHERE id = NEW.group_id
+    );
+    -- If there is a tag by transaction ID, remove all tags not set by that
+    -- attribute.
+    DELETE FROM transactions_tags
+    WHERE (transaction_id, tag_rule_id) IN (
+        SELECT transactions_tags.transaction_id, transactions_tags.tag_rule_id
+        FROM transactions_tags
+        JOIN tag_rules ON tag_rule_id = tag_rules.id
+        WHERE tag_rules.transaction_id IS NULL AND
+        transactions_tags.transaction_id IN (
+            SELECT DISTINCT transactions_tags.transaction_id
+            FROM transactions_tags
+            JOIN tag_rules ON tag_rule_id = tag_rules.id
+            WHERE tag_rules.transaction_id IS NOT NULL
+        )
+    );
+END;
+
+CREATE TRIGGER evaluate_tag_rule_on_condition_delete
+AFTER DELETE ON tag_rule_conditions
+BEGIN
+    DELETE FROM transactions_tags
+    WHERE tag_rule_id = (
+        SELECT tag_rule_id FROM tag_rule_condition_groups WHERE id = OLD.group_id
+    );
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE tag_rule_id = (
+        SELECT tag_rule_id FROM tag_rule_condition_groups WHERE id = OLD.group_id
+    );
+    -- If there is a tag by transaction ID, remove all tags not set by that
+    -- attribute.
+    DELETE FROM transactions_tags
+    WHERE (transaction_id, tag_rule_id) IN (
+        SELECT transactions_tags.transaction_id, transactions_tags.tag_rule_id
+        FROM transactions_tags
+        JOIN tag_rules ON tag_rule_id = tag_rules.id
+        WHERE tag_rules.transaction_id IS NULL AND
+        transactions_tags.transaction_id IN (
+            SELECT DISTINCT transactions_tags.transaction_id
+            FROM transactions_tags
+            JOIN tag_rules ON tag_rule_id = tag_rules.id
+            WHERE tag_rules.transaction_id IS NOT NULL
+        )
+    );
+END;
+
+CREATE TRIGGER evaluate_tag_rule_on_tag_rule_update
+AFTER UPDATE ON tag_rules
+BEGIN
+    DELETE FROM transactions_tags
+    WHERE tag_rule_id = NEW.id;
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE tag_rule_id = NEW.id;
+    -- If the rule used to have a transaction ID, the transaction may be
+    -- matched by other tag rules now.
+    INSERT OR IGNORE INTO transactions_tags
+    SELECT transaction_id, tag_rule_id
+    FROM tag_rules_matches
+    WHERE transaction_id = OLD.transaction_id;
+    -- If there is a tag by transaction ID, remove all tags not set by that
+    -- attribute.
+    DELETE FROM transactions_tags
+    WHERE (transaction_id, tag_rule_id) IN (
+        SELECT transactions_tags.transaction_id, transactions_tags.tag_rule_id
+        FROM transactions_tags
+        JOIN tag_rules ON tag_rule_id = tag_rules.id
+        WHERE tag_rules.transaction_id IS NULL AND
+        transactions_tags.transaction_id IN (
+            SELECT DISTINCT transactions_tags.transaction_id
+            FROM transactions_tags
+            JOIN tag_rules ON tag_rule_id = tag_rules.id
+            WHERE tag_rules.transaction_id IS NOT NULL
+        )
+    );
+END;
+
+-- We can't just use a UNIQUE constraint because SQLite treats NULL
+-- values as different from one another (https://www.sqlite.org/nulls.html).
+CREATE TRIGGER tag_rules_unique_insert
+BEFORE INSERT ON tag_rules
+BEGIN
+    SELECT RAISE(FAIL, "UNIQUE constraint failed.")
+    FROM tag_rules
+    WHERE (
+        NEW.tag,
+        NEW.human_readable,
+        NEW.transaction_id,
+        NEW.account,
+        NEW.description_contains,
+        NEW.description_regex,
+        NEW.description_not_contains,
+        NEW.description_not_regex,
+        NEW.transaction_type,
+        NEW.amount_min,
+        NEW.amount_max,
+        NEW.from_date,
+        NEW.to_date,
+        NEW.day_of_month_min,
+        NEW.day_of_month_max,
+        NEW.day_of_week,
+        NEW.every_n_months
+    ) IS (
+        tag,
+        human_readable,
+        transaction_id,
+        account,
+        description_contains,
+        description_regex,
+        description_not_contains,
+        description_not_regex,
+        transaction_type,
+        amount_min,
+        amount_max,
+        from_date,
+        to_date,
+        day_of_month_min,
+        day_of_month_max,
+        day_of_week,
+        every_n_months
+    );
+END;
+
+CREATE TRIGGER tag_rules_unique_update
+BEFORE UPDATE ON tag_rules
+BEGIN
+    SELECT RAISE(FAIL, "UNIQUE constraint failed.")
+    FROM tag_rules
+    WHERE (
+        NEW.tag,
+        NEW.human_readable,
+        NEW.transaction_id,
+        NEW.account,
+        NEW.description_contains,
+        NEW.description_regex,
+        NEW.description_not_contains,
+        NEW.description_not_regex,
+        NEW.transaction_type,
+        NEW.amount_min,
+        NEW.amount_max,
+        NEW.from_date,
+        NEW.to_date,
+        NEW.day_of_month_min,
+        NEW.day_of_month_max,
+        NEW.day_of_week,
+        NEW.every_n_months
+    ) IS (
+        tag,
+        human_readable,
+        transaction_id,
+        account,
+        description_contains,
+        description_regex,
+        description_not_contains,
+        description_not_regex,
+        transaction_type,
+        amount_min,
+        amount_max,
+        from_date,
+        to_date,
+        day_of_month_min,
+        day_of_month_max,
+        day_of_week,
+        every_n_months
+    );
+END;
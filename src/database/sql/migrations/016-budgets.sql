@@ -0,0 +1,9 @@
+-- A spending limit for the transactions with a tag, or with any tag starting
+-- with it (e.g. "travel/" for all travel), in each month or year.
+CREATE TABLE budgets (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tag TEXT NOT NULL,
+    amount REAL NOT NULL CHECK(amount >= 0),
+    period TEXT NOT NULL CHECK(period IN ('Monthly', 'Yearly')),
+    UNIQUE(tag)
+);
@@ -0,0 +1,34 @@
+-- Envelope budgeting: each envelope holds funds for the transactions with a
+-- tag, or with any tag starting with it. Funds are allocated to it every
+-- month, and moved between envelopes to cover overspending.
+CREATE TABLE envelopes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    tag TEXT NOT NULL,
+    -- The amount allocated by default every month.
+    monthly_amount REAL NOT NULL CHECK(monthly_amount >= 0),
+    -- Whether the amount left unspent at the end of a month is carried over to
+    -- the next. Overspending is always carried over.
+    rollover BOOLEAN NOT NULL,
+    UNIQUE(tag)
+);
+
+-- Months are stored as their first day.
+CREATE TABLE envelope_allocations (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    envelope_id INTEGER NOT NULL,
+    month TEXT NOT NULL,
+    amount REAL NOT NULL CHECK(amount >= 0),
+    FOREIGN KEY(envelope_id) REFERENCES envelopes(id) ON DELETE CASCADE,
+    UNIQUE(envelope_id, month)
+);
+
+CREATE TABLE envelope_moves (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    from_envelope_id INTEGER NOT NULL,
+    to_envelope_id INTEGER NOT NULL,
+    month TEXT NOT NULL,
+    amount REAL NOT NULL CHECK(amount > 0),
+    FOREIGN KEY(from_envelope_id) REFERENCES envelopes(id) ON DELETE CASCADE,
+    FOREIGN KEY(to_envelope_id) REFERENCES envelopes(id) ON DELETE CASCADE,
+    CHECK(from_envelope_id != to_envelope_id)
+);
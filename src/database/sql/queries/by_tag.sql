@@ -1,16 +1,18 @@
 -- https://www.sqlite.org/lang_aggfunc.html explains the difference between
 -- TOTAL and SUM.
-WITH unique_debits_credits_per_tag AS (
+WITH RECURSIVE {tag_nodes}, unique_debits_credits_per_tag AS (
     -- A transaction may be tagged more than once with the same tag by different
-    -- rules. To avoid adding up the same transaction twice, we first select the
-    -- unique (transaction, debit, credit) tuples...
-    SELECT IIF(tag IS NULL, "", tag) AS tag,
+    -- rules, or with tags that roll up to the same one. To avoid adding up the
+    -- same transaction twice, we first select the unique (transaction, debit,
+    -- credit) tuples...
+    SELECT IIF(tag_nodes.node IS NULL, "", tag_nodes.node) AS tag,
            debit_amount,
            credit_amount
-    FROM transactions
+    FROM transactions_net_of_refunds AS transactions
     LEFT JOIN transactions_tags ON transactions_tags.transaction_id = transactions.id
     LEFT JOIN tag_rules ON transactions_tags.tag_rule_id = tag_rules.id
-    {filters} GROUP BY tag, transactions.id
+    LEFT JOIN tag_nodes ON tag_nodes.tag = tag_rules.tag AND tag_nodes.is_tag
+    {filters} GROUP BY tag_nodes.node, transactions.id
 ), per_tag_debit_credit AS (
     -- ... and onlt then we aggregate by tag.
     SELECT tag,
@@ -0,0 +1,16 @@
+WITH RECURSIVE {tag_nodes}, unique_amounts_per_tag AS (
+    -- As in by_tag.sql, select the unique (tag, transaction) pairs first, so
+    -- that a transaction isn't added up more than once in a tag.
+    SELECT IIF(tag_nodes.node IS NULL, '', tag_nodes.node) AS tag,
+           {aggregation_expr} AS aggregation,
+           debit_amount + credit_amount AS amount
+    FROM transactions_net_of_refunds AS transactions
+    LEFT JOIN transactions_tags ON transactions_tags.transaction_id = transactions.id
+    LEFT JOIN tag_rules ON transactions_tags.tag_rule_id = tag_rules.id
+    LEFT JOIN tag_nodes ON tag_nodes.tag = tag_rules.tag AND tag_nodes.is_tag
+    {filters} GROUP BY tag_nodes.node, transactions.id
+)
+SELECT tag, aggregation, TOTAL(amount)
+FROM unique_amounts_per_tag
+GROUP BY 1, 2
+ORDER BY 1, 2;
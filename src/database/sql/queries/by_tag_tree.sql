@@ -0,0 +1,44 @@
+-- Like by_tag.sql, but for every node in the tag hierarchy, with the amounts
+-- of the transactions tagged with the node itself, and with the node or any
+-- of its descendants.
+WITH RECURSIVE {tag_nodes}, unique_transactions_per_node AS (
+    -- A transaction belongs to a node at most once, even if more than one of
+    -- its tags is under it.
+    SELECT DISTINCT transactions.id,
+           IIF(tag_nodes.node IS NULL, "", tag_nodes.node) AS node,
+           IFNULL(tag_nodes.is_tag, TRUE) AS is_tag,
+           debit_amount,
+           credit_amount
+    FROM transactions_net_of_refunds AS transactions
+    LEFT JOIN transactions_tags ON transactions_tags.transaction_id = transactions.id
+    LEFT JOIN tag_rules ON transactions_tags.tag_rule_id = tag_rules.id
+    LEFT JOIN tag_nodes ON tag_nodes.tag = tag_rules.tag
+    {filters}
+), own_debit_credit AS (
+    SELECT node,
+           TOTAL(debit_amount) AS debit,
+           TOTAL(credit_amount) AS credit
+    FROM (
+        SELECT DISTINCT id, node, debit_amount, credit_amount
+        FROM unique_transactions_per_node
+        WHERE is_tag
+    )
+    GROUP BY node
+), cumulative_debit_credit AS (
+    SELECT node,
+           TOTAL(debit_amount) AS debit,
+           TOTAL(credit_amount) AS credit
+    FROM (
+        SELECT DISTINCT id, node, debit_amount, credit_amount
+        FROM unique_transactions_per_node
+    )
+    GROUP BY node
+)
+SELECT cumulative_debit_credit.node,
+       IFNULL(own_debit_credit.debit, 0.0),
+       IFNULL(own_debit_credit.credit, 0.0),
+       cumulative_debit_credit.debit,
+       cumulative_debit_credit.credit
+FROM cumulative_debit_credit
+LEFT JOIN own_debit_credit ON own_debit_credit.node = cumulative_debit_credit.node
+ORDER BY cumulative_debit_credit.node;
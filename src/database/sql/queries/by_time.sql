@@ -6,7 +6,7 @@ WITH filtered_unique_transactions AS (
         credit_amount,
         debit_amount,
         balance
-    FROM transactions
+    FROM transactions_net_of_refunds AS transactions
     LEFT JOIN transactions_tags ON transactions_tags.transaction_id = transactions.id
     LEFT JOIN tag_rules ON transactions_tags.tag_rule_id = tag_rules.id
     LEFT JOIN accounts ON transactions.account_name = accounts.name
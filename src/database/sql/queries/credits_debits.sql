@@ -4,18 +4,18 @@ SELECT  IFNULL(accounts.alias, account_name),
         {amount_column},
         description,
         posted_date,
+        -- The cumulative amounts follow the order of the rows, before any limit or offset.
         SUM({amount_column}) OVER (
-            ORDER BY {amount_column} DESC ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW
+            ORDER BY {order} ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW
         ),
         100 * SUM({amount_column}) OVER (
-            ORDER BY {amount_column} DESC ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW
-        ) / SUM({amount_column}) OVER (
-            ORDER BY {amount_column} DESC ROWS BETWEEN UNBOUNDED PRECEDING AND UNBOUNDED FOLLOWING
-        )
+            ORDER BY {order} ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW
+        ) / SUM({amount_column}) OVER ()
 FROM transactions
 LEFT JOIN transactions_tags ON transactions_tags.transaction_id = transactions.id
 LEFT JOIN tag_rules ON transactions_tags.tag_rule_id = tag_rules.id
 LEFT JOIN accounts ON transactions.account_name = accounts.name
 {filters}
 GROUP BY transactions.id
-ORDER BY transactions.{amount_column} DESC;
+ORDER BY {order}
+{limit};
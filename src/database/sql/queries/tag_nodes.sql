@@ -0,0 +1,21 @@
+-- The nodes of the tag hierarchy that each tag belongs to, with tags rolled up
+-- to their first {depth} path segments. For example, "travel/prague" belongs
+-- to "travel" and "travel/prague", or only to "travel" with a depth of 1.
+-- is_tag marks the node that the tag itself rolls up to.
+tag_prefixes(tag, prefix, rest, depth) AS (
+    SELECT DISTINCT tag, '', tag, 0 FROM tag_rules
+    UNION ALL
+    SELECT tag,
+           prefix || IIF(INSTR(rest, '/') > 0, SUBSTR(rest, 1, INSTR(rest, '/')), rest),
+           IIF(INSTR(rest, '/') > 0, SUBSTR(rest, INSTR(rest, '/') + 1), ''),
+           depth + 1
+    FROM tag_prefixes
+    WHERE rest != '' AND depth < {depth}
+), tag_nodes AS (
+    -- Prefixes of ancestors end in '/', which isn't part of their name.
+    SELECT tag,
+           IIF(rest = '', prefix, SUBSTR(prefix, 1, LENGTH(prefix) - 1)) AS node,
+           rest = '' OR depth = {depth} AS is_tag
+    FROM tag_prefixes
+    WHERE depth > 0
+)
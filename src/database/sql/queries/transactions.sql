@@ -0,0 +1,22 @@
+SELECT  IFNULL(accounts.alias, account_name),
+        transactions.id,
+        REPLACE(GROUP_CONCAT(DISTINCT IIF(tag IS NULL, "", tag)), ',', x'0a'),
+        posted_date,
+        description,
+        credit_amount - debit_amount,
+        balance,
+        -- The running total of each account, in chronological order like in a
+        -- statement, whatever the order of the rows.
+        SUM(credit_amount - debit_amount) OVER (
+            PARTITION BY transactions.account_name
+            ORDER BY posted_date, transactions.id
+            ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW
+        )
+FROM transactions
+LEFT JOIN transactions_tags ON transactions_tags.transaction_id = transactions.id
+LEFT JOIN tag_rules ON transactions_tags.tag_rule_id = tag_rules.id
+LEFT JOIN accounts ON transactions.account_name = accounts.name
+{filters}
+GROUP BY transactions.id
+ORDER BY {order}
+{limit};
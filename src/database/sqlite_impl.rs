@@ -1,11 +1,16 @@
 use anyhow::{anyhow, bail, Result};
 use chrono;
+use regex::{Regex, RegexBuilder};
 use sqlite;
+use sqlite3_sys as ffi;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::ffi::{c_char, c_int};
 use std::fs;
 use std::path::PathBuf;
 
 use crate::database::*;
+use crate::SortBy;
 
 fn sql<T: sqlite::Bindable>(
     conn: &sqlite::Connection,
@@ -91,6 +96,61 @@ const MIGRATIONS: &[Migration] = &[
         sql: include_str!("./sql/migrations/006-account-bank.sql"),
         disable_foreign_keys: false,
     },
+    Migration {
+        name: "007-refunds.sql",
+        sql: include_str!("./sql/migrations/007-refunds.sql"),
+        disable_foreign_keys: false,
+    },
+    Migration {
+        name: "008-tag-rules-description-regex.sql",
+        sql: include_str!("./sql/migrations/008-tag-rules-description-regex.sql"),
+        disable_foreign_keys: false,
+    },
+    Migration {
+        name: "009-tag-rules-account.sql",
+        sql: include_str!("./sql/migrations/009-tag-rules-account.sql"),
+        disable_foreign_keys: false,
+    },
+    Migration {
+        name: "010-tag-rules-description-exclusions.sql",
+        sql: include_str!("./sql/migrations/010-tag-rules-description-exclusions.sql"),
+        disable_foreign_keys: false,
+    },
+    Migration {
+        name: "011-tag-rule-condition-groups.sql",
+        sql: include_str!("./sql/migrations/011-tag-rule-condition-groups.sql"),
+        disable_foreign_keys: false,
+    },
+    Migration {
+        name: "012-evaluate-tag-rule-on-update.sql",
+        sql: include_str!("./sql/migrations/012-evaluate-tag-rule-on-update.sql"),
+        disable_foreign_keys: false,
+    },
+    Migration {
+        name: "013-ignored-transactions.sql",
+        sql: include_str!("./sql/migrations/013-ignored-transactions.sql"),
+        disable_foreign_keys: false,
+    },
+    Migration {
+        name: "014-tag-rules-recurring-dates.sql",
+        sql: include_str!("./sql/migrations/014-tag-rules-recurring-dates.sql"),
+        disable_foreign_keys: false,
+    },
+    Migration {
+        name: "015-tag-rules-signed-amounts.sql",
+        sql: include_str!("./sql/migrations/015-tag-rules-signed-amounts.sql"),
+        disable_foreign_keys: true,
+    },
+    Migration {
+        name: "016-budgets.sql",
+        sql: include_str!("./sql/migrations/016-budgets.sql"),
+        disable_foreign_keys: false,
+    },
+    Migration {
+        name: "017-envelopes.sql",
+        sql: include_str!("./sql/migrations/017-envelopes.sql"),
+        disable_foreign_keys: false,
+    },
 ];
 
 fn perform_migrations(conn: &sqlite::Connection, migrations: &[Migration]) -> Result<bool> {
@@ -131,11 +191,99 @@ fn perform_migrations(conn: &sqlite::Connection, migrations: &[Migration]) -> Re
     Ok((prev_version as usize) < migrations.len() - 1)
 }
 
+thread_local! {
+    // The same few tag rule patterns get matched against every transaction, so
+    // we only compile each of them once.
+    static REGEX_CACHE: RefCell<HashMap<String, Regex>> = RefCell::new(HashMap::new());
+}
+
+// The text of an argument of an application-defined function, or None if it's NULL or not UTF-8.
+// `value` must be an argument of the current call, and the result must not outlive it.
+unsafe fn sqlite_value_to_str<'a>(value: *mut ffi::sqlite3_value) -> Option<&'a str> {
+    if ffi::sqlite3_value_type(value) == ffi::SQLITE_NULL {
+        return None;
+    }
+    let text = ffi::sqlite3_value_text(value);
+    // sqlite3_value_text returns NULL when it runs out of memory.
+    if text.is_null() {
+        return None;
+    }
+    // The length of the text just converted, in bytes.
+    let len = ffi::sqlite3_value_bytes(value) as usize;
+    std::str::from_utf8(std::slice::from_raw_parts(text, len)).ok()
+}
+
+// The implementation of `X REGEXP Y`, which SQLite evaluates as `regexp(Y, X)`
+// but leaves up to the application (https://www.sqlite.org/lang_expr.html#regexp).
+// Like the other textual options, the match is case-insensitive and partial.
+extern "C" fn regexp(
+    context: *mut ffi::sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut ffi::sqlite3_value,
+) {
+    // SAFETY: SQLite calls this with `argc` valid arguments in `argv`, as registered in
+    // register_functions, and a context that is valid for the duration of the call. The argument
+    // texts are only borrowed until we set the result, and sqlite3_result_error copies the
+    // message.
+    unsafe {
+        let args = std::slice::from_raw_parts(argv, argc as usize);
+        let (Some(pattern), Some(text)) =
+            (sqlite_value_to_str(args[0]), sqlite_value_to_str(args[1]))
+        else {
+            ffi::sqlite3_result_null(context);
+            return;
+        };
+        let is_match = REGEX_CACHE.with(|cache| {
+            let mut cache = cache.borrow_mut();
+            if !cache.contains_key(pattern) {
+                let regex = RegexBuilder::new(pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| e.to_string())?;
+                cache.insert(pattern.to_string(), regex);
+            }
+            Ok::<bool, String>(cache[pattern].is_match(text))
+        });
+        match is_match {
+            Ok(is_match) => ffi::sqlite3_result_int(context, is_match as c_int),
+            Err(e) => {
+                ffi::sqlite3_result_error(context, e.as_ptr() as *const c_char, e.len() as c_int)
+            }
+        }
+    }
+}
+
+fn register_functions(conn: &sqlite::Connection) -> Result<()> {
+    // SAFETY: the connection handle is open for as long as `conn` is, the name is a
+    // NUL-terminated static string, and `regexp` has the signature SQLite expects. There is no
+    // user data, so there is nothing to destroy.
+    let result = unsafe {
+        ffi::sqlite3_create_function_v2(
+            conn.as_raw(),
+            c"regexp".as_ptr(),
+            2,
+            ffi::SQLITE_UTF8 | ffi::SQLITE_DETERMINISTIC,
+            std::ptr::null_mut(),
+            Some(regexp),
+            None,
+            None,
+            None,
+        )
+    };
+    if result != ffi::SQLITE_OK {
+        bail!("failed to register the regexp function (error {result})");
+    }
+    Ok(())
+}
+
 fn initialize_sqlite(conn: &sqlite::Connection) -> Result<bool> {
     // Foreign keys are disabled by default, and need to be enabled per connection.
     // https://www.sqlite.org/foreignkeys.html
     conn.execute("PRAGMA foreign_keys = ON;")
         .map_err(|e| anyhow!(e))?;
+    // Application-defined functions also need to be registered per connection,
+    // and before any tag rule triggers run.
+    register_functions(conn)?;
     perform_migrations(conn, MIGRATIONS).map_err(|e| anyhow!("failed to apply migration: {e}"))
 }
 
@@ -178,6 +326,30 @@ impl private::Reset for SQLiteStingyDatabase {
     }
 }
 
+/// Get the tags from a table or view of (transaction_id, tag_rule_id) pairs.
+fn get_transactions_tags_from(
+    conn: &sqlite::Connection,
+    table: &str,
+) -> Result<Vec<TransactionTag>> {
+    let rows = sqlv!(
+        conn,
+        &format!(
+            "SELECT {table}.transaction_id, tag_rule_id, tag FROM {table}
+             JOIN tag_rules ON tag_rules.id = {table}.tag_rule_id
+             ORDER BY {table}.transaction_id, tag_rule_id"
+        )
+    )?;
+    let mut transactions_tags = vec![];
+    for mut row in rows {
+        transactions_tags.push(TransactionTag {
+            transaction_id: (&row.remove(0)).try_into()?,
+            tag_rule_id: (&row.remove(0)).try_into()?,
+            tag: row.remove(0).try_into()?,
+        });
+    }
+    Ok(transactions_tags)
+}
+
 impl StingyDatabase for SQLiteStingyDatabase {
     fn get_uri(&self) -> String {
         let mut uri = "file://".to_string();
@@ -224,6 +396,37 @@ impl StingyDatabase for SQLiteStingyDatabase {
         Ok(count as usize)
     }
 
+    fn count_tagged_transactions(&self) -> Result<usize> {
+        let rows = sqlv!(
+            &self.conn,
+            "SELECT COUNT(DISTINCT transaction_id) FROM transactions_tags"
+        )?;
+        let count: i64 = (&rows[0][0]).try_into()?;
+        Ok(count as usize)
+    }
+
+    fn get_transactions_tags(&self) -> Result<Vec<TransactionTag>> {
+        get_transactions_tags_from(&self.conn, "transactions_tags")
+    }
+
+    fn get_tag_rules_matches(&self) -> Result<Vec<TransactionTag>> {
+        get_transactions_tags_from(&self.conn, "tag_rules_matches")
+    }
+
+    fn dry_run(&self, action: &mut dyn FnMut() -> Result<()>) -> Result<()> {
+        // A savepoint outside of a transaction starts one, which rolling back to the savepoint
+        // and releasing it then ends without committing anything.
+        // https://www.sqlite.org/lang_savepoint.html
+        self.conn
+            .execute("SAVEPOINT dry_run")
+            .map_err(|e| anyhow!("couldn't start dry run: {e}"))?;
+        let result = action();
+        self.conn
+            .execute("ROLLBACK TO dry_run; RELEASE dry_run")
+            .map_err(|e| anyhow!("couldn't roll back dry run: {e}"))?;
+        result
+    }
+
     #[cfg(test)]
     fn insert_test_data(&self) {
         self.conn
@@ -306,6 +509,18 @@ fn initialize_undo(conn: &sqlite::Connection, from_scratch: bool) -> Result<()>
     impl_undo_operations!(conn, model::Account, accounts);
     impl_undo_operations!(conn, model::Transaction, transactions);
     impl_undo_operations!(conn, model::TagRule, tag_rules);
+    impl_undo_operations!(
+        conn,
+        model::TagRuleConditionGroup,
+        tag_rule_condition_groups
+    );
+    impl_undo_operations!(conn, model::TagRuleCondition, tag_rule_conditions);
+    impl_undo_operations!(conn, model::Refund, refunds);
+    impl_undo_operations!(conn, model::IgnoredTransaction, ignored_transactions);
+    impl_undo_operations!(conn, model::Budget, budgets);
+    impl_undo_operations!(conn, model::Envelope, envelopes);
+    impl_undo_operations!(conn, model::EnvelopeAllocation, envelope_allocations);
+    impl_undo_operations!(conn, model::EnvelopeMove, envelope_moves);
     Ok(())
 }
 
@@ -471,6 +686,27 @@ impl From<&model::TransactionType> for sqlite::Value {
     }
 }
 
+impl TryFrom<sqlite::Value> for model::BudgetPeriod {
+    type Error = anyhow::Error;
+
+    fn try_from(value: sqlite::Value) -> Result<Self> {
+        Ok(match (&value).try_into()? {
+            "Monthly" => model::BudgetPeriod::Monthly,
+            "Yearly" => model::BudgetPeriod::Yearly,
+            &_ => bail!("unexpected budget period, this is a bug"),
+        })
+    }
+}
+
+impl From<&model::BudgetPeriod> for sqlite::Value {
+    fn from(model: &model::BudgetPeriod) -> Self {
+        match model {
+            model::BudgetPeriod::Monthly => "Monthly".into(),
+            model::BudgetPeriod::Yearly => "Yearly".into(),
+        }
+    }
+}
+
 fn from_naive_date_to_sqlite_value(naive_date: &chrono::NaiveDate) -> sqlite::Value {
     match naive_date {
         &chrono::NaiveDate::MAX => "Inf".into(),
@@ -481,8 +717,12 @@ fn from_naive_date_to_sqlite_value(naive_date: &chrono::NaiveDate) -> sqlite::Va
 
 fn try_from_sqlite_value_to_naive_date(value: sqlite::Value) -> Result<chrono::NaiveDate> {
     let date_from_sqlite: String = value.try_into()?;
-    chrono::NaiveDate::parse_from_str(&date_from_sqlite, "%Y-%m-%d")
-        .map_err(|_| anyhow!("couldn't parse transaction date"))
+    match date_from_sqlite.as_str() {
+        "Inf" => Ok(chrono::NaiveDate::MAX),
+        "-Inf" => Ok(chrono::NaiveDate::MIN),
+        _ => chrono::NaiveDate::parse_from_str(&date_from_sqlite, "%Y-%m-%d")
+            .map_err(|_| anyhow!("couldn't parse transaction date")),
+    }
 }
 
 fn try_from_sqlite_value_to_naive_date_opt(
@@ -622,13 +862,23 @@ impl TryFrom<Vec<sqlite::Value>> for model::TagRule {
             tag: values.remove(0).try_into()?,
             human_readable: values.remove(0).try_into()?,
             transaction_id: (&values.remove(0)).try_into()?,
+            account: Option::<&str>::try_from(&values.remove(0))?.map(|s| s.to_string()),
             description_contains: Option::<&str>::try_from(&values.remove(0))?
                 .map(|s| s.to_string()),
+            description_regex: Option::<&str>::try_from(&values.remove(0))?.map(|s| s.to_string()),
+            description_not_contains: Option::<&str>::try_from(&values.remove(0))?
+                .map(|s| s.to_string()),
+            description_not_regex: Option::<&str>::try_from(&values.remove(0))?
+                .map(|s| s.to_string()),
             transaction_type: null_opt!(values.remove(0)),
             amount_min: (&as_float!(values.remove(0))).try_into()?,
             amount_max: (&as_float!(values.remove(0))).try_into()?,
             from_date: try_from_sqlite_value_to_naive_date_opt(values.remove(0))?,
             to_date: try_from_sqlite_value_to_naive_date_opt(values.remove(0))?,
+            day_of_month_min: (&values.remove(0)).try_into()?,
+            day_of_month_max: (&values.remove(0)).try_into()?,
+            day_of_week: (&values.remove(0)).try_into()?,
+            every_n_months: (&values.remove(0)).try_into()?,
         })
     }
 }
@@ -644,21 +894,42 @@ impl From<&model::TagRule> for Vec<sqlite::Value> {
                 tag,
                 human_readable,
                 transaction_id,
+                account,
                 description_contains,
+                description_regex,
+                description_not_contains,
+                description_not_regex,
                 transaction_type,
                 amount_min,
                 amount_max,
                 from_date,
                 to_date,
+                day_of_month_min,
+                day_of_month_max,
+                day_of_week,
+                every_n_months,
             } => vec![
                 id.map(|v| v.into()).unwrap_or(Null),
                 tag.as_str().into(),
                 human_readable.as_str().into(),
                 transaction_id.map(|v| v.into()).unwrap_or(Null),
+                account.as_ref().map(|v| v.as_str().into()).unwrap_or(Null),
                 description_contains
                     .as_ref()
                     .map(|v| v.as_str().into())
                     .unwrap_or(Null),
+                description_regex
+                    .as_ref()
+                    .map(|v| v.as_str().into())
+                    .unwrap_or(Null),
+                description_not_contains
+                    .as_ref()
+                    .map(|v| v.as_str().into())
+                    .unwrap_or(Null),
+                description_not_regex
+                    .as_ref()
+                    .map(|v| v.as_str().into())
+                    .unwrap_or(Null),
                 transaction_type.as_ref().map(|v| v.into()).unwrap_or(Null),
                 amount_min.map(|v| v.into()).unwrap_or(Null),
                 amount_max.map(|v| v.into()).unwrap_or(Null),
@@ -670,6 +941,10 @@ impl From<&model::TagRule> for Vec<sqlite::Value> {
                     .as_ref()
                     .map(from_naive_date_to_sqlite_value)
                     .unwrap_or(Null),
+                day_of_month_min.map(|v| v.into()).unwrap_or(Null),
+                day_of_month_max.map(|v| v.into()).unwrap_or(Null),
+                day_of_week.map(|v| v.into()).unwrap_or(Null),
+                every_n_months.map(|v| v.into()).unwrap_or(Null),
             ],
         }
     }
@@ -677,6 +952,282 @@ impl From<&model::TagRule> for Vec<sqlite::Value> {
 
 impl_model_operations!(model::TagRule, tag_rules);
 
+impl TryFrom<Vec<sqlite::Value>> for model::TagRuleConditionGroup {
+    type Error = anyhow::Error;
+
+    fn try_from(mut values: Vec<sqlite::Value>) -> Result<Self> {
+        assert_eq!(values.len(), Self::FIELD_NAMES_AS_ARRAY.len());
+        Ok(Self {
+            id: (&values.remove(0)).try_into()?,
+            tag_rule_id: (&values.remove(0)).try_into()?,
+            operator: values.remove(0).try_into()?,
+        })
+    }
+}
+
+impl From<&model::TagRuleConditionGroup> for Vec<sqlite::Value> {
+    fn from(model: &model::TagRuleConditionGroup) -> Self {
+        // We use a match statement to force a build error if the struct
+        // fields change.
+        match model {
+            model::TagRuleConditionGroup {
+                id,
+                tag_rule_id,
+                operator,
+            } => vec![
+                id.map(|v| v.into()).unwrap_or(sqlite::Value::Null),
+                (*tag_rule_id).into(),
+                operator.as_str().into(),
+            ],
+        }
+    }
+}
+
+impl_model_operations!(model::TagRuleConditionGroup, tag_rule_condition_groups);
+
+impl TryFrom<Vec<sqlite::Value>> for model::TagRuleCondition {
+    type Error = anyhow::Error;
+
+    fn try_from(mut values: Vec<sqlite::Value>) -> Result<Self> {
+        assert_eq!(values.len(), Self::FIELD_NAMES_AS_ARRAY.len());
+        Ok(Self {
+            id: (&values.remove(0)).try_into()?,
+            group_id: (&values.remove(0)).try_into()?,
+            attribute: values.remove(0).try_into()?,
+            value: values.remove(0).try_into()?,
+        })
+    }
+}
+
+impl From<&model::TagRuleCondition> for Vec<sqlite::Value> {
+    fn from(model: &model::TagRuleCondition) -> Self {
+        // We use a match statement to force a build error if the struct
+        // fields change.
+        match model {
+            model::TagRuleCondition {
+                id,
+                group_id,
+                attribute,
+                value,
+            } => vec![
+                id.map(|v| v.into()).unwrap_or(sqlite::Value::Null),
+                (*group_id).into(),
+                attribute.as_str().into(),
+                value.as_str().into(),
+            ],
+        }
+    }
+}
+
+impl_model_operations!(model::TagRuleCondition, tag_rule_conditions);
+
+impl TryFrom<Vec<sqlite::Value>> for model::Refund {
+    type Error = anyhow::Error;
+
+    fn try_from(mut values: Vec<sqlite::Value>) -> Result<Self> {
+        assert_eq!(values.len(), Self::FIELD_NAMES_AS_ARRAY.len());
+        Ok(Self {
+            id: (&values.remove(0)).try_into()?,
+            debit_transaction_id: (&values.remove(0)).try_into()?,
+            credit_transaction_id: (&values.remove(0)).try_into()?,
+        })
+    }
+}
+
+impl From<&model::Refund> for Vec<sqlite::Value> {
+    fn from(model: &model::Refund) -> Self {
+        // We use a match statement to force a build error if the struct
+        // fields change.
+        match model {
+            model::Refund {
+                id,
+                debit_transaction_id,
+                credit_transaction_id,
+            } => vec![
+                id.map(|v| v.into()).unwrap_or(sqlite::Value::Null),
+                (*debit_transaction_id).into(),
+                (*credit_transaction_id).into(),
+            ],
+        }
+    }
+}
+
+impl_model_operations!(model::Refund, refunds);
+
+impl TryFrom<Vec<sqlite::Value>> for model::IgnoredTransaction {
+    type Error = anyhow::Error;
+
+    fn try_from(mut values: Vec<sqlite::Value>) -> Result<Self> {
+        assert_eq!(values.len(), Self::FIELD_NAMES_AS_ARRAY.len());
+        Ok(Self {
+            id: (&values.remove(0)).try_into()?,
+            transaction_id: (&values.remove(0)).try_into()?,
+        })
+    }
+}
+
+impl From<&model::IgnoredTransaction> for Vec<sqlite::Value> {
+    fn from(model: &model::IgnoredTransaction) -> Self {
+        // We use a match statement to force a build error if the struct
+        // fields change.
+        match model {
+            model::IgnoredTransaction { id, transaction_id } => vec![
+                id.map(|v| v.into()).unwrap_or(sqlite::Value::Null),
+                (*transaction_id).into(),
+            ],
+        }
+    }
+}
+
+impl_model_operations!(model::IgnoredTransaction, ignored_transactions);
+
+impl TryFrom<Vec<sqlite::Value>> for model::Budget {
+    type Error = anyhow::Error;
+
+    fn try_from(mut values: Vec<sqlite::Value>) -> Result<Self> {
+        assert_eq!(values.len(), Self::FIELD_NAMES_AS_ARRAY.len());
+        Ok(Self {
+            id: (&values.remove(0)).try_into()?,
+            tag: values.remove(0).try_into()?,
+            amount: (&as_float!(values.remove(0))).try_into()?,
+            period: values.remove(0).try_into()?,
+        })
+    }
+}
+
+impl From<&model::Budget> for Vec<sqlite::Value> {
+    fn from(model: &model::Budget) -> Self {
+        // We use a match statement to force a build error if the struct
+        // fields change.
+        match model {
+            model::Budget {
+                id,
+                tag,
+                amount,
+                period,
+            } => vec![
+                id.map(|v| v.into()).unwrap_or(sqlite::Value::Null),
+                tag.as_str().into(),
+                (*amount).into(),
+                period.into(),
+            ],
+        }
+    }
+}
+
+impl_model_operations!(model::Budget, budgets);
+
+impl TryFrom<Vec<sqlite::Value>> for model::Envelope {
+    type Error = anyhow::Error;
+
+    fn try_from(mut values: Vec<sqlite::Value>) -> Result<Self> {
+        assert_eq!(values.len(), Self::FIELD_NAMES_AS_ARRAY.len());
+        Ok(Self {
+            id: (&values.remove(0)).try_into()?,
+            tag: values.remove(0).try_into()?,
+            monthly_amount: (&as_float!(values.remove(0))).try_into()?,
+            rollover: (&values.remove(0)).try_into::<i64>()? > 0,
+        })
+    }
+}
+
+impl From<&model::Envelope> for Vec<sqlite::Value> {
+    fn from(model: &model::Envelope) -> Self {
+        // We use a match statement to force a build error if the struct
+        // fields change.
+        match model {
+            model::Envelope {
+                id,
+                tag,
+                monthly_amount,
+                rollover,
+            } => vec![
+                id.map(|v| v.into()).unwrap_or(sqlite::Value::Null),
+                tag.as_str().into(),
+                (*monthly_amount).into(),
+                (*rollover as i64).into(),
+            ],
+        }
+    }
+}
+
+impl_model_operations!(model::Envelope, envelopes);
+
+impl TryFrom<Vec<sqlite::Value>> for model::EnvelopeAllocation {
+    type Error = anyhow::Error;
+
+    fn try_from(mut values: Vec<sqlite::Value>) -> Result<Self> {
+        assert_eq!(values.len(), Self::FIELD_NAMES_AS_ARRAY.len());
+        Ok(Self {
+            id: (&values.remove(0)).try_into()?,
+            envelope_id: (&values.remove(0)).try_into()?,
+            month: try_from_sqlite_value_to_naive_date(values.remove(0))?,
+            amount: (&as_float!(values.remove(0))).try_into()?,
+        })
+    }
+}
+
+impl From<&model::EnvelopeAllocation> for Vec<sqlite::Value> {
+    fn from(model: &model::EnvelopeAllocation) -> Self {
+        // We use a match statement to force a build error if the struct
+        // fields change.
+        match model {
+            model::EnvelopeAllocation {
+                id,
+                envelope_id,
+                month,
+                amount,
+            } => vec![
+                id.map(|v| v.into()).unwrap_or(sqlite::Value::Null),
+                (*envelope_id).into(),
+                from_naive_date_to_sqlite_value(month),
+                (*amount).into(),
+            ],
+        }
+    }
+}
+
+impl_model_operations!(model::EnvelopeAllocation, envelope_allocations);
+
+impl TryFrom<Vec<sqlite::Value>> for model::EnvelopeMove {
+    type Error = anyhow::Error;
+
+    fn try_from(mut values: Vec<sqlite::Value>) -> Result<Self> {
+        assert_eq!(values.len(), Self::FIELD_NAMES_AS_ARRAY.len());
+        Ok(Self {
+            id: (&values.remove(0)).try_into()?,
+            from_envelope_id: (&values.remove(0)).try_into()?,
+            to_envelope_id: (&values.remove(0)).try_into()?,
+            month: try_from_sqlite_value_to_naive_date(values.remove(0))?,
+            amount: (&as_float!(values.remove(0))).try_into()?,
+        })
+    }
+}
+
+impl From<&model::EnvelopeMove> for Vec<sqlite::Value> {
+    fn from(model: &model::EnvelopeMove) -> Self {
+        // We use a match statement to force a build error if the struct
+        // fields change.
+        match model {
+            model::EnvelopeMove {
+                id,
+                from_envelope_id,
+                to_envelope_id,
+                month,
+                amount,
+            } => vec![
+                id.map(|v| v.into()).unwrap_or(sqlite::Value::Null),
+                (*from_envelope_id).into(),
+                (*to_envelope_id).into(),
+                from_naive_date_to_sqlite_value(month),
+                (*amount).into(),
+            ],
+        }
+    }
+}
+
+impl_model_operations!(model::EnvelopeMove, envelope_moves);
+
 fn query_filters_to_sql(filters: QueryFilters) -> (String, Vec<(String, sqlite::Value)>) {
     let mut sql = vec![];
     let mut args: HashMap<String, sqlite::Value> = HashMap::new();
@@ -739,8 +1290,11 @@ fn query_filters_to_sql(filters: QueryFilters) -> (String, Vec<(String, sqlite::
         );
     }
 
-    let amount_column =
-        r#"IIF(transactions.transaction_type = "Credit", credit_amount, debit_amount)"#;
+    let amount_column = if is_signed_amount_range(filters.amount_min, filters.amount_max) {
+        "(credit_amount - debit_amount)"
+    } else {
+        r#"IIF(transactions.transaction_type = "Credit", credit_amount, debit_amount)"#
+    };
 
     if let Some(amount_min) = filters.amount_min {
         sql.push(format!("{amount_column} >= :AMOUNT_MIN"));
@@ -799,8 +1353,61 @@ fn get_and_populate_query_template(
     (query_sql, args)
 }
 
+/// Populate the tag hierarchy used by the by-tag queries, rolling tags up to `depth` levels.
+fn populate_tag_nodes(query_sql: &str, depth: Option<usize>) -> String {
+    let depth = depth.map(|d| d as i64).unwrap_or(i64::MAX);
+    query_sql.replace(
+        "{tag_nodes}",
+        &include_str!("./sql/queries/tag_nodes.sql").replace("{depth}", &depth.to_string()),
+    )
+}
+
+/// Replace `{aggregation_expr}` with an expression for the last day of a transaction's time window.
+fn populate_aggregation_expr(query_sql: &str, aggregation: &TimeAggregation) -> String {
+    let aggregation_expr = if *aggregation == TimeAggregation::Month {
+        r#"DATE(posted_date, "start of month", "+1 month", "-1 day")"#
+    } else if *aggregation == TimeAggregation::Week {
+        r#"DATE(posted_date, "weekday 6")"#
+    } else {
+        r#"DATE(posted_date, "start of year", "+1 year", "-1 day")"#
+    };
+    query_sql.replace("{aggregation_expr}", aggregation_expr)
+}
+
+/// Replace `{order}` with the order of the rows of a detail query, breaking ties by transaction ID
+/// so that pages don't overlap, and `{limit}` with the page of rows to return. `{amount_column}`
+/// is left for the caller to replace.
+fn populate_order_and_limit(query_sql: &str, order: &QueryOrder) -> String {
+    let column = match order.sort {
+        SortBy::date => "posted_date",
+        SortBy::amount => "{amount_column}",
+        SortBy::description => "LOWER(description)",
+        SortBy::account => "LOWER(IFNULL(accounts.alias, transactions.account_name))",
+    };
+    let direction = if order.ascending { "ASC" } else { "DESC" };
+    let limit = match (order.limit, order.offset) {
+        (None, None) => "".to_string(),
+        // A negative limit means no limit.
+        (limit, offset) => format!(
+            "LIMIT {} OFFSET {}",
+            limit.map(|l| l as i64).unwrap_or(-1),
+            offset.unwrap_or(0)
+        ),
+    };
+    query_sql
+        .replace(
+            "{order}",
+            &format!("{column} {direction}, transactions.id {direction}"),
+        )
+        .replace("{limit}", &limit)
+}
+
 impl QueryOperations for SQLiteStingyDatabase {
-    fn query_debits(&self, mut filters: QueryFilters) -> Result<QueryResult<DebitsRow>> {
+    fn query_debits(
+        &self,
+        mut filters: QueryFilters,
+        order: &QueryOrder,
+    ) -> Result<QueryResult<DebitsRow>> {
         filters.transaction_types = vec![
             model::TransactionType::Debit,
             model::TransactionType::DirectDebit,
@@ -810,6 +1417,7 @@ impl QueryOperations for SQLiteStingyDatabase {
             filters,
         );
         let args: Vec<_> = args.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        query_sql = populate_order_and_limit(&query_sql, order);
         query_sql = query_sql.replace("{amount_column}", "debit_amount");
 
         let sqlite_rows = sql(&self.conn, &query_sql, args.as_slice())?;
@@ -820,13 +1428,18 @@ impl QueryOperations for SQLiteStingyDatabase {
         Ok(QueryResult { rows })
     }
 
-    fn query_credits(&self, mut filters: QueryFilters) -> Result<QueryResult<CreditsRow>> {
+    fn query_credits(
+        &self,
+        mut filters: QueryFilters,
+        order: &QueryOrder,
+    ) -> Result<QueryResult<CreditsRow>> {
         filters.transaction_types = vec![model::TransactionType::Credit];
         let (mut query_sql, args) = get_and_populate_query_template(
             include_str!("./sql/queries/credits_debits.sql"),
             filters,
         );
         let args: Vec<_> = args.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        query_sql = populate_order_and_limit(&query_sql, order);
         query_sql = query_sql.replace("{amount_column}", "credit_amount");
 
         let sqlite_rows = sql(&self.conn, &query_sql, args.as_slice())?;
@@ -837,10 +1450,54 @@ impl QueryOperations for SQLiteStingyDatabase {
         Ok(QueryResult { rows })
     }
 
-    fn query_by_tag(&self, filters: QueryFilters) -> Result<QueryResult<ByTagRow>> {
-        let (query_sql, args) =
+    fn query_transactions(
+        &self,
+        filters: QueryFilters,
+        order: &QueryOrder,
+    ) -> Result<QueryResult<TransactionsRow>> {
+        let (mut query_sql, args) = get_and_populate_query_template(
+            include_str!("./sql/queries/transactions.sql"),
+            filters,
+        );
+        let args: Vec<_> = args.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        query_sql = populate_order_and_limit(&query_sql, order);
+        query_sql = query_sql.replace("{amount_column}", "(credit_amount - debit_amount)");
+
+        let sqlite_rows = sql(&self.conn, &query_sql, args.as_slice())?;
+        let mut rows = Vec::new();
+        for row in sqlite_rows {
+            rows.push(row.try_into()?);
+        }
+        Ok(QueryResult { rows })
+    }
+
+    fn query_by_tag(
+        &self,
+        filters: QueryFilters,
+        depth: Option<usize>,
+    ) -> Result<QueryResult<ByTagRow>> {
+        let (mut query_sql, args) =
             get_and_populate_query_template(include_str!("./sql/queries/by_tag.sql"), filters);
         let args: Vec<_> = args.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        query_sql = populate_tag_nodes(&query_sql, depth);
+
+        let sqlite_rows = sql(&self.conn, &query_sql, args.as_slice())?;
+        let mut rows = Vec::new();
+        for row in sqlite_rows {
+            rows.push(row.try_into()?);
+        }
+        Ok(QueryResult { rows })
+    }
+
+    fn query_by_tag_tree(
+        &self,
+        filters: QueryFilters,
+        depth: Option<usize>,
+    ) -> Result<QueryResult<ByTagTreeRow>> {
+        let (mut query_sql, args) =
+            get_and_populate_query_template(include_str!("./sql/queries/by_tag_tree.sql"), filters);
+        let args: Vec<_> = args.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        query_sql = populate_tag_nodes(&query_sql, depth);
 
         let sqlite_rows = sql(&self.conn, &query_sql, args.as_slice())?;
         let mut rows = Vec::new();
@@ -859,14 +1516,27 @@ impl QueryOperations for SQLiteStingyDatabase {
             get_and_populate_query_template(include_str!("./sql/queries/by_time.sql"), filters);
         let args: Vec<_> = args.iter().map(|(k, v)| (k.as_str(), v)).collect();
 
-        let aggregation_expr = if *aggregation == TimeAggregation::Month {
-            r#"DATE(posted_date, "start of month", "+1 month", "-1 day")"#
-        } else if *aggregation == TimeAggregation::Week {
-            r#"DATE(posted_date, "weekday 6")"#
-        } else {
-            r#"DATE(posted_date, "start of year", "+1 year", "-1 day")"#
-        };
-        query_sql = query_sql.replace("{aggregation_expr}", aggregation_expr);
+        query_sql = populate_aggregation_expr(&query_sql, aggregation);
+
+        let sqlite_rows = sql(&self.conn, &query_sql, args.as_slice())?;
+        let mut rows = Vec::new();
+        for row in sqlite_rows {
+            rows.push(row.try_into()?);
+        }
+        Ok(QueryResult { rows })
+    }
+
+    fn query_by_tag_time(
+        &self,
+        filters: QueryFilters,
+        aggregation: &TimeAggregation,
+        depth: Option<usize>,
+    ) -> Result<QueryResult<ByTagTimeRow>> {
+        let (mut query_sql, args) =
+            get_and_populate_query_template(include_str!("./sql/queries/by_tag_time.sql"), filters);
+        let args: Vec<_> = args.iter().map(|(k, v)| (k.as_str(), v)).collect();
+        query_sql = populate_tag_nodes(&query_sql, depth);
+        query_sql = populate_aggregation_expr(&query_sql, aggregation);
 
         let sqlite_rows = sql(&self.conn, &query_sql, args.as_slice())?;
         let mut rows = Vec::new();
@@ -898,6 +1568,27 @@ impl TryFrom<Vec<sqlite::Value>> for DebitsRow {
     }
 }
 
+impl TryFrom<Vec<sqlite::Value>> for TransactionsRow {
+    type Error = anyhow::Error;
+
+    fn try_from(mut values: Vec<sqlite::Value>) -> Result<Self> {
+        assert_eq!(values.len(), Self::FIELD_NAMES_AS_ARRAY.len());
+        Ok(Self {
+            account_name: values.remove(0).try_into()?,
+            transaction_id: (&values.remove(0)).try_into()?,
+            tags: String::try_from(values.remove(0))?
+                .split("\n")
+                .map(|s| s.to_string())
+                .collect(),
+            posted_date: try_from_sqlite_value_to_naive_date(values.remove(0))?,
+            description: values.remove(0).try_into()?,
+            amount: (&as_float!(values.remove(0))).try_into()?,
+            balance: (&as_float!(values.remove(0))).try_into()?,
+            running_total: (&as_float!(values.remove(0))).try_into()?,
+        })
+    }
+}
+
 impl TryFrom<Vec<sqlite::Value>> for CreditsRow {
     type Error = anyhow::Error;
 
@@ -952,6 +1643,34 @@ impl TryFrom<Vec<sqlite::Value>> for ByTagRow {
     }
 }
 
+impl TryFrom<Vec<sqlite::Value>> for ByTagTreeRow {
+    type Error = anyhow::Error;
+
+    fn try_from(mut values: Vec<sqlite::Value>) -> Result<Self> {
+        assert_eq!(values.len(), Self::FIELD_NAMES_AS_ARRAY.len());
+        Ok(Self {
+            tag: values.remove(0).try_into()?,
+            tag_debit: (&as_float!(values.remove(0))).try_into()?,
+            tag_credit: (&as_float!(values.remove(0))).try_into()?,
+            tag_debit_cumulative: (&as_float!(values.remove(0))).try_into()?,
+            tag_credit_cumulative: (&as_float!(values.remove(0))).try_into()?,
+        })
+    }
+}
+
+impl TryFrom<Vec<sqlite::Value>> for ByTagTimeRow {
+    type Error = anyhow::Error;
+
+    fn try_from(mut values: Vec<sqlite::Value>) -> Result<Self> {
+        assert_eq!(values.len(), Self::FIELD_NAMES_AS_ARRAY.len());
+        Ok(Self {
+            tag: values.remove(0).try_into()?,
+            aggregation_window_end: try_from_sqlite_value_to_naive_date(values.remove(0))?,
+            amount: (&as_float!(values.remove(0))).try_into()?,
+        })
+    }
+}
+
 #[cfg(test)]
 mod sqlite_database_tests {
     use super::*;
@@ -5,15 +5,13 @@ use chrono;
 use chrono::Datelike;
 use chrono::NaiveDate;
 use clap::{error::ErrorKind, CommandFactory, ValueEnum};
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
 use regex::Regex;
 use std::env;
 use std::ffi::OsStr;
 use std::fmt::{Display, Error, Formatter};
 use std::fs;
 use std::io;
-#[cfg(not(test))]
-use std::io::Read;
 use std::io::Write;
 use std::path::Path;
 use std::process::ExitCode;
@@ -52,43 +50,40 @@ enum Commands {
         tags: TagOperation,
     },
 
+    /// Link refunds to the purchases they refund.
+    Refunds {
+        #[command(subcommand)]
+        refunds: RefundOperation,
+    },
+
+    /// Set spending limits for tags, per month or year.
+    Budget {
+        #[command(subcommand)]
+        budget: BudgetOperation,
+    },
+
+    /// Manage envelopes: funds allocated to tags every month, and moved between them.
+    Envelopes {
+        #[command(subcommand)]
+        envelopes: EnvelopeOperation,
+    },
+
     /// View transaction data, aggregated and filtered in different ways.
     Query {
         /// The name of the query to run.
         #[command(subcommand)]
         query: PreparedQuery,
 
-        /// Only consider transactions in this time period. Examples: 'january', '2021/01-2022-01', ':-march'.
-        #[arg(short, long, global = true)]
-        period: Option<String>,
-
-        /// Only consider transactions with these tags.
-        #[arg(short, long, use_value_delimiter = true, global = true)]
-        tags: Vec<String>,
-
-        /// Exclude transactions with these tags.
-        #[arg(short, long, use_value_delimiter = true, global = true)]
-        not_tags: Vec<String>,
-
-        /// Only include untagged transactions (conflicts with --tags, --not-tags).
-        #[arg(long, global = true)]
-        untagged: bool,
-
-        /// Only consider transactions whose description (partially) matches this value.
-        #[arg(short, long, global = true)]
-        description_contains: Option<String>,
-
-        /// Only consider transactions whose amount is in this range. Examples: '10-1000', '50-:'.
-        #[arg(long, global = true)]
-        amount_range: Option<String>,
-
-        /// Only consider transactions for this account.
-        #[arg(short, long, global = true)]
-        account: Option<String>,
+        #[command(flatten)]
+        filters: QueryFilterArgs,
     },
 
     /// Import transactions from a bank.
     Import {
+        /// After importing, look for anomalies in the current month of the imported accounts.
+        #[arg(long)]
+        anomalies: bool,
+
         #[command(subcommand)]
         import: ImportOperations,
     },
@@ -103,6 +98,40 @@ enum Commands {
     Reset {},
 }
 
+/// The filters for the transactions in a query, shared between the commands that use queries.
+#[derive(Debug, Args)]
+struct QueryFilterArgs {
+    /// Only consider transactions in this time period. Examples: 'january', '2021/01-2022-01', ':-march'.
+    #[arg(short, long, global = true)]
+    period: Option<String>,
+
+    /// Only consider transactions with these tags.
+    #[arg(short, long, use_value_delimiter = true, global = true)]
+    tags: Vec<String>,
+
+    /// Exclude transactions with these tags.
+    #[arg(short, long, use_value_delimiter = true, global = true)]
+    not_tags: Vec<String>,
+
+    /// Only include untagged transactions (conflicts with --tags, --not-tags).
+    #[arg(long, global = true)]
+    untagged: bool,
+
+    /// Only consider transactions whose description (partially) matches this value.
+    #[arg(short, long, global = true)]
+    description_contains: Option<String>,
+
+    /// Only consider transactions whose amount is in this range. Examples: '10-1000', '50-:'. Ranges
+    /// with a negative bound are signed, with debits as negative amounts: '-50--10' is debits of
+    /// 10 to 50, and ':--100' is debits of more than 100.
+    #[arg(long, global = true, allow_hyphen_values = true)]
+    amount_range: Option<String>,
+
+    /// Only consider transactions for this account.
+    #[arg(short, long, global = true)]
+    account: Option<String>,
+}
+
 #[derive(Debug, Subcommand)]
 enum AccountOperation {
     /// List imported accounts.
@@ -141,37 +170,340 @@ enum TagOperation {
         #[arg(short, long)]
         tag: String,
 
-        /// Match only the transaction with this ID. This is usually not needed.
-        #[arg(long)]
-        transaction_id: Option<usize>,
-
-        /// Match only transactions whose description contains this text
+        #[command(flatten)]
+        attributes: TagRuleArgs,
+    },
+    /// Preview the transactions a tag rule would tag, without adding it.
+    TestRule {
+        /// The tag associated with this rule.
         #[arg(short, long)]
-        description_contains: Option<String>,
+        tag: String,
 
-        /// Match only transactions of this type.
+        #[command(flatten)]
+        attributes: TagRuleArgs,
+    },
+    /// Add a tag rule matching the same transactions as a query, with the same filters as
+    /// 'query'. Tags can only be used as filters with --snapshot.
+    AddFromQuery {
+        /// The tag associated with the rule.
+        #[arg(long)]
+        tag: String,
+
+        /// Only match transactions of this type.
         #[arg(long)]
         transaction_type: Option<TransactionType>,
 
-        /// Match only transactions whose amount is in this range. Examples: '10-1000', '50-:'.
+        /// Instead of a rule for the filters, add a rule for each transaction currently returned
+        /// by the query, by transaction ID.
         #[arg(long)]
-        amount_range: Option<String>,
+        snapshot: bool,
+
+        #[command(flatten)]
+        filters: QueryFilterArgs,
+    },
+    /// Edit a tag rule, keeping its ID. Attributes that aren't passed are kept.
+    EditRule {
+        id: String,
 
-        /// Match only transactions in this period. Examples: 'january', '2021/01-2022/01',
-        /// ':-march'.
+        /// The tag associated with this rule.
         #[arg(short, long)]
-        period: Option<String>,
+        tag: Option<String>,
+
+        #[command(flatten)]
+        attributes: TagRuleArgs,
+
+        /// Remove the transaction ID from the rule.
+        #[arg(long, conflicts_with = "transaction_id")]
+        clear_transaction_id: bool,
+
+        /// Remove the account from the rule.
+        #[arg(long, conflicts_with = "account")]
+        clear_account: bool,
+
+        /// Remove the description text from the rule.
+        #[arg(long, conflicts_with = "description_contains")]
+        clear_description_contains: bool,
+
+        /// Remove the description regular expression from the rule.
+        #[arg(long, conflicts_with = "description_regex")]
+        clear_description_regex: bool,
+
+        /// Remove the excluded description text from the rule.
+        #[arg(long, conflicts_with = "description_not_contains")]
+        clear_description_not_contains: bool,
+
+        /// Remove the excluded description regular expression from the rule.
+        #[arg(long, conflicts_with = "description_not_regex")]
+        clear_description_not_regex: bool,
+
+        /// Remove the transaction type from the rule.
+        #[arg(long, conflicts_with = "transaction_type")]
+        clear_transaction_type: bool,
+
+        /// Remove the amount range from the rule.
+        #[arg(long, conflicts_with = "amount_range")]
+        clear_amount_range: bool,
+
+        /// Remove the period from the rule.
+        #[arg(long, conflicts_with = "period")]
+        clear_period: bool,
+
+        /// Remove the days of the month from the rule.
+        #[arg(long, conflicts_with = "day_of_month")]
+        clear_day_of_month: bool,
+
+        /// Remove the day of the week from the rule.
+        #[arg(long, conflicts_with = "day_of_week")]
+        clear_day_of_week: bool,
+
+        /// Remove the every N months condition from the rule.
+        #[arg(long, conflicts_with = "every_n_months")]
+        clear_every_n_months: bool,
+
+        /// Remove all --any-of and --none-of condition groups from the rule.
+        #[arg(long, conflicts_with_all = ["any_of", "none_of"])]
+        clear_condition_groups: bool,
     },
     /// Delete a tag rule, removing its tag from all transactions.
     #[command(alias = "remove-rule")]
     DeleteRule { id: String },
+    /// Suggest tags for untagged transactions, based on the descriptions of tagged ones, and
+    /// confirm adding tag rules for them.
+    Suggest,
+    /// Walk through untagged transactions, largest first, choosing how to tag each of them.
+    Triage,
+    /// Rename a tag in all tag rules. Sub-tags are renamed too, so renaming 'travel' to 'trips'
+    /// also renames 'travel/paris' to 'trips/paris'.
+    Rename {
+        /// The tag to rename.
+        old: String,
+        /// The new name for the tag.
+        new: String,
+    },
+    /// Replace several tags, and their sub-tags, with a single tag in all tag rules.
+    Merge {
+        /// The tags to replace.
+        #[arg(required = true)]
+        tags: Vec<String>,
+
+        /// The tag to replace them with.
+        #[arg(long)]
+        into: String,
+    },
+    /// Report tag rules that match no transactions or conflict with others, and untagged debits.
+    Audit,
+    /// Export all tag rules to a TOML file, which can be edited and applied with 'tags apply'.
+    Export {
+        /// The file to write to. The rules are printed if no file is passed.
+        file: Option<String>,
+    },
+    /// Make the tag rules match the ones in a TOML file, adding, changing and removing tag rules.
+    Apply {
+        /// A file in the format written by 'tags export'.
+        file: String,
+    },
+}
+
+/// The attributes of a tag rule, shared between the commands that add and edit tag rules.
+#[derive(Debug, Args)]
+struct TagRuleArgs {
+    /// Match only the transaction with this ID. This is usually not needed.
+    #[arg(long)]
+    transaction_id: Option<usize>,
+
+    /// Match only transactions in this account (name or alias). Unlike in queries, the selected
+    /// account is not used by default.
+    #[arg(short, long)]
+    account: Option<String>,
+
+    /// Match only transactions whose description contains this text
+    #[arg(short, long)]
+    description_contains: Option<String>,
+
+    /// Match only transactions whose description (partially, case-insensitively) matches this
+    /// regular expression. Example: '\bSHELL\b'.
+    #[arg(long)]
+    description_regex: Option<String>,
+
+    /// Match only transactions whose description doesn't contain this text.
+    #[arg(long)]
+    description_not_contains: Option<String>,
+
+    /// Match only transactions whose description (partially, case-insensitively) doesn't match
+    /// this regular expression.
+    #[arg(long)]
+    description_not_regex: Option<String>,
+
+    /// Match only transactions of this type.
+    #[arg(long)]
+    transaction_type: Option<TransactionType>,
+
+    /// Match only transactions whose amount is in this range. Examples: '10-1000', '50-:'. Ranges
+    /// with a negative bound are signed, with debits as negative amounts: '-50--10' is debits of
+    /// 10 to 50, and ':--100' is debits of more than 100.
+    #[arg(long, allow_hyphen_values = true)]
+    amount_range: Option<String>,
+
+    /// Match only transactions in this period. Examples: 'january', '2021/01-2022/01',
+    /// ':-march'.
+    #[arg(short, long)]
+    period: Option<String>,
+
+    /// Match only transactions on these days of the month. The range wraps around the end of
+    /// the month if the first day is larger. Examples: '1', '25-28', '28-3'.
+    #[arg(long)]
+    day_of_month: Option<String>,
+
+    /// Match only transactions on this day of the week. Examples: 'friday', 'sat'.
+    #[arg(long)]
+    day_of_week: Option<String>,
+
+    /// Match only transactions every this many months, counting from the start of --period, or
+    /// from January if there's no period. Example: '3' for quarterly transactions.
+    #[arg(long, value_name = "N")]
+    every_n_months: Option<u32>,
+
+    /// Match only transactions matching at least one of these comma-separated conditions. Can be
+    /// repeated, and each group of conditions must match. Example:
+    /// 'description-contains=TESCO,description-contains=LIDL'. Conditions can also be
+    /// 'description-regex=<regex>' or 'account=<account>'.
+    #[arg(long, value_name = "CONDITIONS")]
+    any_of: Vec<String>,
+
+    /// Match only transactions matching none of these comma-separated conditions. Can be
+    /// repeated, and accepts the same conditions as --any-of.
+    #[arg(long, value_name = "CONDITIONS")]
+    none_of: Vec<String>,
+}
+
+#[derive(Debug, Subcommand)]
+enum RefundOperation {
+    /// List the linked refunds.
+    List,
+    /// Find credits that look like refunds of earlier purchases, and confirm linking them.
+    Match {
+        /// Only consider refunds made within this many days of the purchase.
+        #[arg(long, default_value_t = 30)]
+        days: i64,
+    },
+    /// Link a refund (a credit) to the purchase (a debit) it refunds.
+    Link {
+        /// The transaction ID of the purchase.
+        #[arg(long)]
+        purchase_id: i64,
+
+        /// The transaction ID of the refund.
+        #[arg(long)]
+        refund_id: i64,
+    },
+    /// Delete a refund link, counting the refund as a credit again.
+    #[command(alias = "remove-link")]
+    Unlink { id: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum BudgetOperation {
+    /// List the budgets.
+    List,
+    /// Set the budget for a tag, replacing its existing budget, if any.
+    Set {
+        /// The tag to budget for. Like in queries, it's matched partially, so "travel/" includes
+        /// all travel sub-tags.
+        tag: String,
+
+        /// The amount that can be spent in each period.
+        amount: f64,
+
+        /// The period that the budget applies to.
+        #[arg(long, default_value_t = BudgetPeriod::monthly)]
+        period: BudgetPeriod,
+    },
+    /// Delete the budget for a tag.
+    #[command(alias = "remove")]
+    Delete { tag: String },
+}
+
+#[derive(Debug, Subcommand)]
+enum EnvelopeOperation {
+    /// List the envelopes, with their balances at the end of a month.
+    List {
+        /// The month, e.g. 'may' or '2023/05'. Defaults to the current month.
+        #[arg(long)]
+        month: Option<String>,
+    },
+    /// Create an envelope for a tag.
+    Create {
+        /// The tag of the envelope. Like in queries, it's matched partially, so "travel/" includes
+        /// all travel sub-tags.
+        tag: String,
+
+        /// The amount allocated to the envelope every month, unless allocated otherwise.
+        monthly_amount: f64,
+
+        /// Don't carry the funds left unspent at the end of a month over to the next.
+        #[arg(long)]
+        no_rollover: bool,
+    },
+    /// Allocate funds for a month, to one envelope or to all of them. An allocation replaces any
+    /// previous allocation to the same envelope in that month.
+    Allocate {
+        /// The tag of the envelope. If not given, all envelopes get their monthly amount.
+        tag: Option<String>,
+
+        /// The amount to allocate, instead of the envelope's monthly amount.
+        #[arg(long, requires = "tag")]
+        amount: Option<f64>,
+
+        /// The month, e.g. 'may' or '2023/05'. Defaults to the current month.
+        #[arg(long)]
+        month: Option<String>,
+    },
+    /// Move funds between envelopes in a month, for example to cover overspending.
+    Move {
+        /// The tag of the envelope to move funds from.
+        from: String,
+
+        /// The tag of the envelope to move funds to.
+        to: String,
+
+        /// The amount to move.
+        amount: f64,
+
+        /// The month, e.g. 'may' or '2023/05'. Defaults to the current month.
+        #[arg(long)]
+        month: Option<String>,
+    },
+    /// List the allocations and moves, of all envelopes or of one.
+    History {
+        /// The tag of the envelope.
+        tag: Option<String>,
+    },
+    /// Delete an envelope, with its allocations and moves.
+    #[command(alias = "remove")]
+    Delete { tag: String },
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum BudgetPeriod {
+    monthly,
+    yearly,
+}
+
+impl Display for BudgetPeriod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "{:?}", self)
+    }
 }
 
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum TransactionType {
+    /// Debits, including direct debits.
     debit,
     credit,
+    #[value(name = "direct-debit")]
+    direct_debit,
 }
 
 impl Display for TransactionType {
@@ -180,6 +512,41 @@ impl Display for TransactionType {
     }
 }
 
+#[allow(non_camel_case_types)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum SortBy {
+    date,
+    amount,
+    description,
+    account,
+}
+
+impl Display for SortBy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// How to sort and page through the rows of the queries that list transactions.
+#[derive(Debug, Default, Clone, Args)]
+pub struct DetailQueryArgs {
+    /// Sort by this column, in descending order unless --asc is given.
+    #[arg(long)]
+    sort: Option<SortBy>,
+
+    /// Sort in ascending order.
+    #[arg(long)]
+    asc: bool,
+
+    /// Only show this many transactions.
+    #[arg(long, value_name = "N")]
+    limit: Option<u64>,
+
+    /// Skip this many transactions before the ones shown.
+    #[arg(long, value_name = "N")]
+    offset: Option<u64>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum TimeAggregation {
     Week,
@@ -208,12 +575,28 @@ pub enum PreparedQuery {
         /// Also display the transaction IDs in the results.
         #[arg(long, global = true)]
         show_transaction_id: bool,
+
+        #[command(flatten)]
+        detail: DetailQueryArgs,
     },
     /// A detailed view of credit transactions.
     Credits {
         /// Also display the transaction IDs in the results.
         #[arg(long, global = true)]
         show_transaction_id: bool,
+
+        #[command(flatten)]
+        detail: DetailQueryArgs,
+    },
+    /// A detailed view of debit and credit transactions in chronological order, like a bank
+    /// statement.
+    Transactions {
+        /// Also display the transaction IDs in the results.
+        #[arg(long, global = true)]
+        show_transaction_id: bool,
+
+        #[command(flatten)]
+        detail: DetailQueryArgs,
     },
     /// A summary of expenses, grouped by time.
     ByTime {
@@ -231,6 +614,92 @@ pub enum PreparedQuery {
         #[arg(long)]
         transaction_type: Option<TransactionType>,
 
+        /// Roll tags up to their first N levels, so that with a depth of 1, "travel/prague" is
+        /// counted as "travel".
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        depth: Option<u32>,
+
+        /// Show the results as a table of the tag hierarchy, with the amounts of each tag, and of
+        /// the tag and all its sub-tags (cumulative).
+        #[arg(long)]
+        tree: bool,
+
+        /// Show the results as a table instead of the default chart.
+        #[arg(long, global = true)]
+        table: bool,
+    },
+    /// Debits that recur with a regular cadence (weekly, monthly or yearly) and a stable amount,
+    /// like subscriptions.
+    Recurring {
+        /// Consider amounts that differ by at most this percentage to be the same.
+        #[arg(long, value_name = "PERCENT", default_value_t = commands::recurring::DEFAULT_TOLERANCE)]
+        tolerance: f64,
+    },
+    /// The balance of each account projected day by day, from its recurring debits and credits,
+    /// and the average amounts per tag of the rest.
+    Forecast {
+        /// Project until the end of this month. Examples: '2027/03', 'december'.
+        #[arg(long, value_name = "MONTH")]
+        until: String,
+
+        /// Warn when a projected balance drops below this amount.
+        #[arg(long, value_name = "AMOUNT", default_value_t = 0.0)]
+        floor: f64,
+
+        /// Mark recurring debits of at least this amount as large outflows.
+        #[arg(long, value_name = "AMOUNT", default_value_t = 500.0)]
+        large_outflow: f64,
+
+        /// Show the results as a table instead of the default chart.
+        #[arg(long, global = true)]
+        table: bool,
+    },
+    /// Tags that were spent on much more than usual, and debits much larger than usual for their
+    /// description, in the period given by --period, or in the current month by default.
+    Anomalies {
+        /// Compare against this many months before the period.
+        #[arg(long, value_name = "MONTHS", default_value_t = commands::anomalies::DEFAULT_HISTORY)]
+        history: u32,
+
+        /// Report amounts more than this many spreads (a robust standard deviation) above the median.
+        #[arg(long, default_value_t = commands::anomalies::DEFAULT_THRESHOLD)]
+        threshold: f64,
+    },
+    /// The budgets, with how much of them was spent in the period given by --period, or in the
+    /// current month by default.
+    Budget {},
+    /// A comparison of the expenses in the period given by --period against another period, per
+    /// tag and overall.
+    Compare {
+        /// The period to compare against, in the same format as --period, or 'last-year' for the
+        /// same period a year earlier.
+        #[arg(long)]
+        against: String,
+
+        /// Roll tags up to their first N levels, so that with a depth of 1, "travel/prague" is
+        /// counted as "travel".
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        depth: Option<u32>,
+
+        /// Show the results as a table instead of the default chart.
+        #[arg(long, global = true)]
+        table: bool,
+    },
+    /// A summary of expenses, grouped by tag and by time.
+    ByTagTime {
+        /// Aggregate by this window.
+        #[arg(long, default_value_t = TimeAggregation::Month)]
+        aggregate: TimeAggregation,
+
+        /// Only consider transactions of this type (debits by default).
+        #[arg(long)]
+        transaction_type: Option<TransactionType>,
+
+        /// Roll tags up to their first N levels, so that with a depth of 1, "travel/prague" is
+        /// counted as "travel".
+        #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+        depth: Option<u32>,
+
         /// Show the results as a table instead of the default chart.
         #[arg(long, global = true)]
         table: bool,
@@ -351,7 +820,7 @@ fn stingy_main() -> Result<()> {
             }
             Ok(())
         }
-        Some(Commands::Import { import }) => {
+        Some(Commands::Import { anomalies, import }) => {
             let (format, paths) = match &import {
                 ImportOperations::AIB { csv } => (commands::import::ImportFormat::AIB, csv),
                 ImportOperations::Revolut {
@@ -390,6 +859,29 @@ fn stingy_main() -> Result<()> {
                             "{TIP} No account is currently selected as the default.  Use '{binary_name} help accounts' view account options."
                         )?;
                     }
+                    if *anomalies && accounts.len() > 0 {
+                        println!("Anomalies in the current month:")?;
+                        if commands::query::command_query(
+                            &db,
+                            &mut io::stdout(),
+                            &PreparedQuery::Anomalies {
+                                history: commands::anomalies::DEFAULT_HISTORY,
+                                threshold: commands::anomalies::DEFAULT_THRESHOLD,
+                            },
+                            &vec![],
+                            &vec![],
+                            None,
+                            None,
+                            None,
+                            None,
+                            None,
+                            accounts.iter().map(|a| a.as_str()).collect(),
+                        )?
+                        .is_none()
+                        {
+                            println!("None found.")?;
+                        }
+                    }
                     if let (Some(before), Some(after)) = (before, after) {
                         let gap_days = (after - before).num_days().abs();
                         if gap_days > 3 {
@@ -461,84 +953,177 @@ fn stingy_main() -> Result<()> {
             let mut to = output::table::TableOutput::new(io::stdout(), None);
             to.render_table(&result.columns, &result.rows).map(|_| ())
         }
+        Some(Commands::Tags {
+            tags: TagOperation::AddRule { tag, attributes },
+        }) => {
+            let attributes = parse_tag_rule_args(&mut cmd, attributes)?;
+            if attributes == commands::tags::TagRuleAttributes::default() {
+                bail!(cmd.error(
+                    ErrorKind::MissingRequiredArgument,
+                    "At least one parameter must be passed.\n\n{TIP} Use {binary_name} help tags add-rule to view available options."
+                ));
+            }
+            match commands::tags::add_tag_rule(&db, tag, &attributes)? {
+                commands::tags::AddTagRuleResult::Added {
+                    tag_rule_id,
+                    tagged_transactions,
+                } => {
+                    println!("{OK} Added tag rule {tag_rule_id}, tagging {tagged_transactions} transaction(s) with tag '{tag}'.")
+                }
+                commands::tags::AddTagRuleResult::NotUnique { tag_rule_id } => {
+                    println!("Tag rule {tag_rule_id} already matches these parameters, ignoring.")?;
+                    println!("{TIP} Use {binary_name} tags list to view tag rules.")
+                }
+            }?;
+            if attributes.transaction_id.is_some() {
+                println!("{TIP} Tag rules using transaction IDs override all other tag rules.")
+            } else {
+                Ok(())
+            }
+        }
+        Some(Commands::Tags {
+            tags: TagOperation::TestRule { tag, attributes },
+        }) => {
+            let attributes = parse_tag_rule_args(&mut cmd, attributes)?;
+            if attributes == commands::tags::TagRuleAttributes::default() {
+                bail!(cmd.error(
+                    ErrorKind::MissingRequiredArgument,
+                    "At least one parameter must be passed.\n\n{TIP} Use {binary_name} help tags test-rule to view available options."
+                ));
+            }
+            let result = commands::tags::test_tag_rule(&db, tag, &attributes)?;
+            if let Some(tag_rule_id) = result.existing_tag_rule_id {
+                println!("{TIP} Tag rule {tag_rule_id} already matches these parameters.")?;
+            }
+            if result.matching == 0 {
+                return println!("The tag rule wouldn't tag any transactions.");
+            }
+            let mut to = output::table::TableOutput::new(io::stdout(), None);
+            to.render_table(&result.columns, &result.rows)?;
+            println!(
+                "The tag rule would tag {} transaction(s) with '{tag}' ({:.02} in debits, {:.02} in credits).",
+                result.matching, result.debit_total, result.credit_total
+            )?;
+            if result.conflicting > 0 {
+                println!(
+                    "{WARN}{} of them already have a tag from a different hierarchy, and are marked above.",
+                    result.conflicting
+                )?;
+            }
+            println!("{TIP} Use {binary_name} tags add-rule with the same arguments to add the tag rule.")
+        }
         Some(Commands::Tags {
             tags:
-                TagOperation::AddRule {
+                TagOperation::AddFromQuery {
                     tag,
-                    transaction_id,
-                    description_contains,
                     transaction_type,
-                    amount_range,
-                    period,
+                    snapshot,
+                    filters,
                 },
         }) => {
-            let parameters = (
-                transaction_id.clone(),
-                description_contains.as_deref(),
-                amount_range.as_deref(),
-                period.as_deref(),
-                parse_amount_range(amount_range.as_deref()),
-                parse_period(period.as_deref()),
-            );
-            let transaction_type = transaction_type.as_ref().map(|tt| {
-                if *tt == crate::TransactionType::credit {
-                    model::TransactionType::Credit
-                } else {
-                    model::TransactionType::Debit
-                }
-            });
-            match parameters {
-                (None, None, None, None, _, _) => {
-                    bail!(cmd.error(
-                        ErrorKind::MissingRequiredArgument,
-                        "At least one parameter must be passed.\n\n{TIP} Use {binary_name} help tags add-rule to view available options."
-                    ));
-                }
-                (_, _, Some(_), _, Err(e), _) => {
-                    bail!(cmd.error(
-                        ErrorKind::InvalidValue,
-                        format!("Invalid format for --amount-range: {}", e)
-                    ));
-                }
-                (_, _, _, Some(_), _, Err(e)) => {
-                    bail!(cmd.error(
-                        ErrorKind::InvalidValue,
-                        format!("Invalid format for --period: {}", e)
-                    ));
-                }
-                (_, _, _, _, Ok((amount_min, amount_max)), Ok((from, to))) => {
-                    let result = commands::tags::add_tag_rule(
-                        &db,
-                        tag,
-                        *transaction_id,
-                        description_contains.as_deref(),
-                        transaction_type.clone(),
-                        amount_min,
-                        amount_max,
-                        from,
-                        to,
-                    )?;
-                    match result {
-                        commands::tags::AddTagRuleResult::Added {
-                            tag_rule_id,
-                            tagged_transactions,
-                        } => {
-                            println!("{OK} Added tag rule {tag_rule_id}, tagging {tagged_transactions} transaction(s) with tag '{tag}'.")
-                        }
-                        commands::tags::AddTagRuleResult::NotUnique { tag_rule_id } => {
-                            println!("Tag rule {tag_rule_id} already matches these parameters, ignoring.")?;
-                            println!("{TIP} Use {binary_name} tags list to view tag rules.")
-                        }
+            let mut filters = parse_query_filter_args(&mut cmd, &db, filters)?;
+            filters.transaction_types = match transaction_type {
+                Some(TransactionType::debit) => vec![
+                    model::TransactionType::Debit,
+                    model::TransactionType::DirectDebit,
+                ],
+                Some(TransactionType::credit) => vec![model::TransactionType::Credit],
+                Some(TransactionType::direct_debit) => vec![model::TransactionType::DirectDebit],
+                None => vec![],
+            };
+            let results = commands::tags::add_tag_rules_from_query(&db, tag, filters, *snapshot)?;
+            let added: Vec<i64> = results
+                .iter()
+                .filter_map(|r| match r {
+                    commands::tags::AddTagRuleResult::Added { tag_rule_id, .. } => {
+                        Some(*tag_rule_id)
                     }
+                    commands::tags::AddTagRuleResult::NotUnique { .. } => None,
+                })
+                .collect();
+            let tagged_transactions: usize = results
+                .iter()
+                .map(|r| match r {
+                    commands::tags::AddTagRuleResult::Added {
+                        tagged_transactions,
+                        ..
+                    } => *tagged_transactions,
+                    commands::tags::AddTagRuleResult::NotUnique { .. } => 0,
+                })
+                .sum();
+            if results.is_empty() {
+                println!("The query returned no results.")
+            } else if added.is_empty() {
+                println!("Existing tag rules already match these parameters, ignoring.")?;
+                println!("{TIP} Use {binary_name} tags list to view tag rules.")
+            } else {
+                println!(
+                    "{OK} Added {} tag rule(s), tagging {tagged_transactions} transaction(s) with tag '{tag}'.",
+                    added.len()
+                )
+            }
+        }
+        Some(Commands::Tags {
+            tags:
+                TagOperation::EditRule {
+                    id,
+                    tag,
+                    attributes,
+                    clear_transaction_id,
+                    clear_account,
+                    clear_description_contains,
+                    clear_description_regex,
+                    clear_description_not_contains,
+                    clear_description_not_regex,
+                    clear_transaction_type,
+                    clear_amount_range,
+                    clear_period,
+                    clear_day_of_month,
+                    clear_day_of_week,
+                    clear_every_n_months,
+                    clear_condition_groups,
+                },
+        }) => {
+            use commands::tags::TagRuleAttribute;
+            let attributes = parse_tag_rule_args(&mut cmd, attributes)?;
+            let clear: Vec<TagRuleAttribute> = [
+                (clear_transaction_id, TagRuleAttribute::TransactionId),
+                (clear_account, TagRuleAttribute::Account),
+                (
+                    clear_description_contains,
+                    TagRuleAttribute::DescriptionContains,
+                ),
+                (clear_description_regex, TagRuleAttribute::DescriptionRegex),
+                (
+                    clear_description_not_contains,
+                    TagRuleAttribute::DescriptionNotContains,
+                ),
+                (
+                    clear_description_not_regex,
+                    TagRuleAttribute::DescriptionNotRegex,
+                ),
+                (clear_transaction_type, TagRuleAttribute::TransactionType),
+                (clear_amount_range, TagRuleAttribute::AmountRange),
+                (clear_period, TagRuleAttribute::Period),
+                (clear_day_of_month, TagRuleAttribute::DayOfMonth),
+                (clear_day_of_week, TagRuleAttribute::DayOfWeek),
+                (clear_every_n_months, TagRuleAttribute::EveryNMonths),
+                (clear_condition_groups, TagRuleAttribute::ConditionGroups),
+            ]
+            .into_iter()
+            .filter(|(clear, _)| **clear)
+            .map(|(_, attribute)| attribute)
+            .collect();
+            match commands::tags::edit_tag_rule(&db, id, tag.as_deref(), &attributes, &clear)? {
+                commands::tags::EditTagRuleResult::Edited {
+                    tagged_transactions,
+                } => {
+                    println!("{OK} Edited tag rule {id}, which now tags {tagged_transactions} transaction(s).")
                 }
-                (_, _, _, _, _, _) => {
-                    unreachable!("This shouldn't happen.");
+                commands::tags::EditTagRuleResult::NotUnique { tag_rule_id } => {
+                    println!("Tag rule {tag_rule_id} already matches these parameters, ignoring.")?;
+                    println!("{TIP} Use {binary_name} tags list to view tag rules.")
                 }
-            }?;
-            if transaction_id.is_some() {
-                println!("{TIP} Tag rules using transaction IDs override all other tag rules.")
-            } else {
-                Ok(())
             }
         }
         Some(Commands::Tags {
@@ -568,54 +1153,486 @@ fn stingy_main() -> Result<()> {
                 }
             }
         }
-        Some(Commands::Query {
-            query,
-            period,
-            tags,
-            not_tags,
-            untagged,
-            description_contains,
-            amount_range,
-            account,
+        Some(Commands::Tags {
+            tags: TagOperation::Suggest,
         }) => {
-            let (from, to) = parse_period(period.as_deref())
-                .map_err(|e| cmd.error(ErrorKind::InvalidValue, e))?;
-            let (amount_min, amount_max) = {
-                parse_amount_range(amount_range.as_deref()).or_else(|e| {
-                    bail!(cmd.error(
-                        ErrorKind::InvalidValue,
-                        format!("Invalid format for --amount-range: {}", e)
-                    ));
-                })?
-            };
-            if *untagged {
-                if tags.len() > 0 || not_tags.len() > 0 {
-                    bail!(cmd.error(
-                        ErrorKind::ArgumentConflict,
-                        format!("--untagged cannot be used with --tags and --not-tags.")
+            let suggestions = commands::tags::suggest_tags(&db)?;
+            if suggestions.is_empty() {
+                println!("No suggestions for untagged transactions.")?;
+                return println!("{TIP} Suggestions are based on tagged transactions, so try tagging a few first.");
+            }
+            for suggestion in suggestions {
+                let prompt = format!(
+                    "Tag {} untagged transaction(s) from '{}' ({:.02}) with '{}' ({:.0}% confidence)?",
+                    suggestion.transactions,
+                    suggestion.description_contains,
+                    suggestion.amount,
+                    suggestion.tag,
+                    100.0 * suggestion.confidence
+                );
+                with_confirmation(&prompt, || {
+                    let attributes = commands::tags::TagRuleAttributes {
+                        description_contains: Some(suggestion.description_contains.clone()),
+                        ..Default::default()
+                    };
+                    match commands::tags::add_tag_rule(&db, &suggestion.tag, &attributes)? {
+                        commands::tags::AddTagRuleResult::Added {
+                            tag_rule_id,
+                            tagged_transactions,
+                        } => println!("{OK} Added tag rule {tag_rule_id}, tagging {tagged_transactions} transaction(s)."),
+                        commands::tags::AddTagRuleResult::NotUnique { .. } => Ok(()),
+                    }
+                })?;
+            }
+            Ok(())
+        }
+        Some(Commands::Tags {
+            tags: TagOperation::Triage,
+        }) => {
+            let transactions = commands::tags::get_untagged_transactions(&db)?;
+            if transactions.is_empty() {
+                return println!("{OK} There are no untagged transactions.");
+            }
+            println!("{TIP} Each decision can be undone separately with '{binary_name} undo'.")?;
+            'transactions: for (i, transaction) in transactions.iter().enumerate() {
+                let transaction_id = transaction.id.unwrap();
+                // A tag rule for an earlier transaction may have tagged this one.
+                if db
+                    .get_transactions_tags()?
+                    .iter()
+                    .any(|tt| tt.transaction_id == transaction_id)
+                {
+                    continue;
+                }
+                let similar = commands::tags::merchant(&transaction.description);
+                let matching = if similar.is_empty() {
+                    0
+                } else {
+                    commands::tags::count_description_matches(&db, &similar)?
+                };
+                println!(
+                    "\n[{}/{}] {} {} '{}' {:.02} ({:?}, transaction {transaction_id})",
+                    i + 1,
+                    transactions.len(),
+                    transaction.posted_date.format("%Y/%m/%d"),
+                    transaction.account_name,
+                    transaction.description,
+                    transaction.debit_amount.max(transaction.credit_amount),
+                    transaction.transaction_type,
+                )?;
+                let mut options = vec!["[t]ag this transaction".to_string()];
+                if !similar.is_empty() {
+                    options.push(format!(
+                        "tag all [d]escriptions containing '{similar}' ({matching} transaction(s))"
                     ));
                 }
-                // Since we match by substring and "" is a substring of all tags, if we put ""
-                // in not_tags we'll be excluding all transactions with any tags.
-                not_tags.push("".to_string());
+                options.extend([
+                    "[s]kip".to_string(),
+                    "[i]gnore".to_string(),
+                    "[q]uit".to_string(),
+                ]);
+                let attributes = loop {
+                    match ask(&format!("{}?", options.join(", ")))?.as_str() {
+                        "t" => {
+                            break commands::tags::TagRuleAttributes {
+                                transaction_id: Some(transaction_id as usize),
+                                ..Default::default()
+                            }
+                        }
+                        "d" if !similar.is_empty() => {
+                            break commands::tags::TagRuleAttributes {
+                                description_contains: Some(similar.clone()),
+                                ..Default::default()
+                            }
+                        }
+                        "i" => {
+                            commands::undo::begin_undo_step(
+                                &db,
+                                &format!(
+                                    "{binary_name} tags triage (ignore transaction {transaction_id})"
+                                ),
+                            )?;
+                            commands::tags::ignore_transaction(&db, transaction_id)?;
+                            println!("{OK} Transaction {transaction_id} won't be offered for tagging again.")?;
+                            continue 'transactions;
+                        }
+                        "s" | "" => continue 'transactions,
+                        "q" => return Ok(()),
+                        _ => {}
+                    }
+                };
+                let tag = ask("Tag?")?;
+                if tag.is_empty() {
+                    continue;
+                }
+                commands::undo::begin_undo_step(
+                    &db,
+                    &format!("{binary_name} tags triage (add tag rule for '{tag}')"),
+                )?;
+                match commands::tags::add_tag_rule(&db, &tag, &attributes)? {
+                    commands::tags::AddTagRuleResult::Added {
+                        tag_rule_id,
+                        tagged_transactions,
+                    } => println!("{OK} Added tag rule {tag_rule_id}, tagging {tagged_transactions} transaction(s) with tag '{tag}'."),
+                    commands::tags::AddTagRuleResult::NotUnique { tag_rule_id } => {
+                        println!("Tag rule {tag_rule_id} already matches these parameters, ignoring.")
+                    }
+                }?;
             }
-            let accounts = commands::accounts::get_account_or_selected(&db, account.as_deref())?;
-            let account_names: Vec<&str> = accounts
+            println!("{OK} All untagged transactions were reviewed.")
+        }
+        Some(Commands::Tags {
+            tags: TagOperation::Rename { old, new },
+        }) => {
+            let result = commands::tags::rename_tag(&db, old, new)?;
+            print_retag_result(
+                &result,
+                &format!("Renamed '{old}' to '{new}'"),
+                &binary_name,
+            )
+        }
+        Some(Commands::Tags {
+            tags: TagOperation::Merge { tags, into },
+        }) => {
+            let result = commands::tags::merge_tags(&db, tags, into)?;
+            print_retag_result(
+                &result,
+                &format!("Merged '{}' into '{into}'", tags.join("', '")),
+                &binary_name,
+            )
+        }
+        Some(Commands::Tags {
+            tags: TagOperation::Audit,
+        }) => {
+            let result = commands::tags::audit_tag_rules(&db)?;
+            let mut to = output::table::TableOutput::new(io::stdout(), None);
+            if result.dead_tag_rules.is_empty() {
+                println!("{OK} All tag rules tag at least one transaction.")?;
+            } else {
+                println!("{WARN} These tag rules don't tag any transactions:")?;
+                let rows: Vec<Vec<String>> = result
+                    .dead_tag_rules
+                    .iter()
+                    .map(|tr| {
+                        vec![
+                            format!("{}", tr.id.unwrap()),
+                            tr.tag.clone(),
+                            tr.human_readable.clone(),
+                        ]
+                    })
+                    .collect();
+                to.render_table(&["ID", "Tag", "Description"], &rows)?;
+            }
+            if result.conflicts.is_empty() {
+                println!("{OK} No transactions have tags from different hierarchies.")?;
+            } else {
+                println!("{WARN} These tag rules tag the same transactions with tags from different hierarchies:")?;
+                let rows: Vec<Vec<String>> = result
+                    .conflicts
+                    .iter()
+                    .map(|c| {
+                        vec![
+                            format!("{}, {}", c.tag_rule_ids.0, c.tag_rule_ids.1),
+                            format!("{}, {}", c.tags.0, c.tags.1),
+                            format!("{}", c.transactions),
+                        ]
+                    })
+                    .collect();
+                to.render_table(&["IDs", "Tags", "Transactions"], &rows)?;
+            }
+            if !result.shadowing.is_empty() {
+                println!("{TIP} These transaction ID tag rules override other tag rules that match the same transaction:")?;
+                let rows: Vec<Vec<String>> = result
+                    .shadowing
+                    .iter()
+                    .map(|s| {
+                        vec![
+                            format!("{}", s.tag_rule_id),
+                            format!("{}", s.transaction_id),
+                            s.shadowed_tag_rule_ids
+                                .iter()
+                                .map(|id| format!("{id}"))
+                                .collect::<Vec<_>>()
+                                .join(", "),
+                        ]
+                    })
+                    .collect();
+                to.render_table(&["ID", "Transaction ID", "Overridden IDs"], &rows)?;
+            }
+            let (untagged, debits) = result.untagged_debits;
+            let (untagged_amount, debit_amount) = result.untagged_debit_amount;
+            println!(
+                "{} of {} debits ({:.01}%) are untagged, amounting to {:.02} of {:.02} ({:.01}%).",
+                untagged,
+                debits,
+                100.0 * untagged as f64 / debits.max(1) as f64,
+                untagged_amount,
+                debit_amount,
+                100.0 * untagged_amount / debit_amount.max(f64::EPSILON),
+            )
+        }
+        Some(Commands::Tags {
+            tags: TagOperation::Export { file },
+        }) => {
+            let contents = commands::tags::export_tag_rules(&db)?;
+            match file.as_deref() {
+                Some(path) => {
+                    fs::write(path, contents)?;
+                    println!("{OK} Exported tag rules to '{path}'.")
+                }
+                None => print!("{contents}"),
+            }
+        }
+        Some(Commands::Tags {
+            tags: TagOperation::Apply { file },
+        }) => {
+            let contents = fs::read_to_string(file.as_str())
+                .map_err(|e| anyhow!("failed to read '{file}': {e}"))?;
+            let plan = commands::tags::plan_tag_rules(&db, &contents)?;
+            if plan.changes.is_empty() {
+                return println!("The tag rules already match '{file}', nothing to do.");
+            }
+            // Apply the changes without writing them, to show how they change the tagging.
+            let mut preview = None;
+            db.dry_run(&mut || {
+                preview = Some(commands::tags::apply_tag_rules(&db, &plan)?);
+                Ok(())
+            })?;
+            let preview = preview.unwrap();
+            let columns = vec![
+                "Change".to_string(),
+                "ID".to_string(),
+                "Tag".to_string(),
+                "Description".to_string(),
+                "Tagged".to_string(),
+            ];
+            let rows: Vec<Vec<String>> = plan
+                .changes
                 .iter()
-                .map(|account| account.name.as_str())
+                .zip(&preview.tagged_by_change)
+                .map(|(change, (before, after))| {
+                    let (kind, tag_rule) = match (&change.current, &change.new) {
+                        (None, Some(new)) => ("add", new),
+                        (Some(current), None) => ("remove", current),
+                        (_, new) => ("change", new.as_ref().unwrap()),
+                    };
+                    vec![
+                        kind.to_string(),
+                        change
+                            .current
+                            .as_ref()
+                            .map(|tr| format!("{}", tr.id.unwrap()))
+                            .unwrap_or_default(),
+                        tag_rule.tag.clone(),
+                        tag_rule.human_readable.clone(),
+                        format!("{before} → {after}"),
+                    ]
+                })
                 .collect();
+            let mut to = output::table::TableOutput::new(io::stdout(), None);
+            to.render_table(&columns, &rows)?;
+            let prompt = format!(
+                "{WARN} Apply {} change(s) to tag rules, leaving {} unchanged? Tagged transactions will go from {} to {}.",
+                plan.changes.len(),
+                plan.unchanged,
+                preview.tagged.0,
+                preview.tagged.1
+            );
+            with_confirmation(&prompt, || {
+                commands::tags::apply_tag_rules(&db, &plan)?;
+                println!("{OK} Tag rules now match '{file}'.")
+            })
+        }
+        Some(Commands::Refunds {
+            refunds: RefundOperation::List,
+        }) => {
+            let result = commands::refunds::list_refunds(&db)?;
+            let mut to = output::table::TableOutput::new(io::stdout(), None);
+            to.render_table(&result.columns, &result.rows).map(|_| ())
+        }
+        Some(Commands::Refunds {
+            refunds: RefundOperation::Match { days },
+        }) => {
+            let candidates = commands::refunds::find_refund_candidates(&db, *days)?;
+            if candidates.is_empty() {
+                return println!("No new refunds found.");
+            }
+            for candidate in candidates {
+                let (purchase, refund) = (&candidate.purchase, &candidate.refund);
+                let prompt = format!(
+                    "Link refund {} of {:.02} on {} to purchase {} of {:.02} on {} ('{}')?",
+                    refund.id.unwrap(),
+                    refund.credit_amount,
+                    refund.posted_date.format("%Y/%m/%d"),
+                    purchase.id.unwrap(),
+                    purchase.debit_amount,
+                    purchase.posted_date.format("%Y/%m/%d"),
+                    purchase.description,
+                );
+                with_confirmation(&prompt, || {
+                    commands::refunds::link_refund(&db, purchase.id.unwrap(), refund.id.unwrap())
+                        .map(|_| ())
+                })?;
+            }
+            println!("{TIP} Use '{binary_name} refunds list' to view linked refunds.")
+        }
+        Some(Commands::Refunds {
+            refunds:
+                RefundOperation::Link {
+                    purchase_id,
+                    refund_id,
+                },
+        }) => match commands::refunds::link_refund(&db, *purchase_id, *refund_id) {
+            Ok(refund) => println!(
+                "{OK} Linked refund {refund_id} to purchase {purchase_id} (link {}).",
+                refund.id.unwrap()
+            ),
+            Err(err) => {
+                println!("{ERR} Failed to link refund: {err}")?;
+                println!("{TIP} Use '{binary_name} query credits --show-transaction-id' to find transaction IDs.")
+            }
+        },
+        Some(Commands::Refunds {
+            refunds: RefundOperation::Unlink { id },
+        }) => {
+            if commands::refunds::unlink_refund(&db, id)? != 1 {
+                bail!(cmd.error(
+                    ErrorKind::InvalidValue,
+                    format!("Refund link {id} not found.\n\n{TIP} Use {binary_name} refunds list to see existing links.")
+                ));
+            }
+            println!("{OK} Refund link {id} deleted.")
+        }
+        Some(Commands::Budget {
+            budget: BudgetOperation::List,
+        }) => {
+            let result = commands::budget::list_budgets(&db)?;
+            let mut to = output::table::TableOutput::new(io::stdout(), None);
+            to.render_table(&result.columns, &result.rows).map(|_| ())
+        }
+        Some(Commands::Budget {
+            budget:
+                BudgetOperation::Set {
+                    tag,
+                    amount,
+                    period,
+                },
+        }) => {
+            let period = match period {
+                BudgetPeriod::monthly => model::BudgetPeriod::Monthly,
+                BudgetPeriod::yearly => model::BudgetPeriod::Yearly,
+            };
+            let is_new = commands::budget::set_budget(&db, tag, *amount, period)
+                .map_err(|e| cmd.error(ErrorKind::InvalidValue, e))?;
+            println!(
+                "{OK} {} {} budget of {amount:.02} for '{tag}'.",
+                if is_new { "Set" } else { "Replaced with" },
+                commands::budget::budget_period_name(period)
+            )?;
+            println!("{TIP} Use '{binary_name} query budget' to view how much of it was spent.")
+        }
+        Some(Commands::Budget {
+            budget: BudgetOperation::Delete { tag },
+        }) => {
+            if commands::budget::delete_budget(&db, tag)? != 1 {
+                bail!(cmd.error(
+                    ErrorKind::InvalidValue,
+                    format!("No budget for '{tag}'.\n\n{TIP} Use {binary_name} budget list to see existing budgets.")
+                ));
+            }
+            println!("{OK} Budget for '{tag}' deleted.")
+        }
+        Some(Commands::Envelopes {
+            envelopes: EnvelopeOperation::List { month },
+        }) => {
+            let month = parse_envelope_month(month.as_deref())
+                .map_err(|e| cmd.error(ErrorKind::InvalidValue, e))?;
+            let result = commands::envelopes::list_envelopes(&db, month)?;
+            if result.rows.is_empty() {
+                return println!(
+                    "{TIP} Use '{binary_name} envelopes create' to create an envelope."
+                );
+            }
+            let mut to = output::table::TableOutput::new(io::stdout(), None);
+            to.render_table(&result.columns, &result.rows).map(|_| ())
+        }
+        Some(Commands::Envelopes {
+            envelopes:
+                EnvelopeOperation::Create {
+                    tag,
+                    monthly_amount,
+                    no_rollover,
+                },
+        }) => {
+            commands::envelopes::create_envelope(&db, tag, *monthly_amount, !*no_rollover)
+                .map_err(|e| cmd.error(ErrorKind::InvalidValue, e))?;
+            println!("{OK} Created envelope for '{tag}', with {monthly_amount:.02} every month.")?;
+            println!(
+                "{TIP} Use '{binary_name} envelopes allocate' to allocate funds for this month."
+            )
+        }
+        Some(Commands::Envelopes {
+            envelopes: EnvelopeOperation::Allocate { tag, amount, month },
+        }) => {
+            let month = parse_envelope_month(month.as_deref())
+                .map_err(|e| cmd.error(ErrorKind::InvalidValue, e))?;
+            let allocations = commands::envelopes::allocate(&db, tag.as_deref(), *amount, month)
+                .map_err(|e| cmd.error(ErrorKind::InvalidValue, e))?;
+            for (tag, amount) in allocations {
+                println!(
+                    "{OK} Allocated {amount:.02} to '{tag}' for {}.",
+                    month.format("%Y/%m")
+                )?;
+            }
+            Ok(())
+        }
+        Some(Commands::Envelopes {
+            envelopes:
+                EnvelopeOperation::Move {
+                    from,
+                    to,
+                    amount,
+                    month,
+                },
+        }) => {
+            let month = parse_envelope_month(month.as_deref())
+                .map_err(|e| cmd.error(ErrorKind::InvalidValue, e))?;
+            commands::envelopes::move_funds(&db, from, to, *amount, month)
+                .map_err(|e| cmd.error(ErrorKind::InvalidValue, e))?;
+            println!(
+                "{OK} Moved {amount:.02} from '{from}' to '{to}' for {}.",
+                month.format("%Y/%m")
+            )
+        }
+        Some(Commands::Envelopes {
+            envelopes: EnvelopeOperation::History { tag },
+        }) => {
+            let result = commands::envelopes::envelope_history(&db, tag.as_deref())
+                .map_err(|e| cmd.error(ErrorKind::InvalidValue, e))?;
+            let mut to = output::table::TableOutput::new(io::stdout(), None);
+            to.render_table(&result.columns, &result.rows).map(|_| ())
+        }
+        Some(Commands::Envelopes {
+            envelopes: EnvelopeOperation::Delete { tag },
+        }) => {
+            commands::envelopes::delete_envelope(&db, tag)
+                .map_err(|e| cmd.error(ErrorKind::InvalidValue, e))?;
+            println!("{OK} Envelope for '{tag}' deleted.")
+        }
+        Some(Commands::Query { query, filters }) => {
+            let filters = parse_query_filter_args(&mut cmd, &db, filters)?;
             if commands::query::command_query(
                 &db,
                 &mut io::stdout(),
                 query,
-                tags,
-                not_tags,
-                description_contains.as_deref(),
-                amount_min,
-                amount_max,
-                from,
-                to,
-                account_names,
+                &filters.tags,
+                &filters.not_tags,
+                filters.description_contains.as_deref(),
+                filters.amount_min,
+                filters.amount_max,
+                filters.date_from,
+                filters.date_to,
+                filters.accounts.iter().map(|a| a.as_str()).collect(),
             )?
             .is_none()
             {
@@ -623,7 +1640,14 @@ fn stingy_main() -> Result<()> {
             }
             match query {
                 PreparedQuery::ByTime { table: false, .. }
-                | PreparedQuery::ByTag { table: false, .. } => {
+                | PreparedQuery::ByTagTime { table: false, .. }
+                | PreparedQuery::Forecast { table: false, .. }
+                | PreparedQuery::Compare { table: false, .. }
+                | PreparedQuery::ByTag {
+                    table: false,
+                    tree: false,
+                    ..
+                } => {
                     println!("{TIP} Add --table to this command to view details in table format.")
                 }
                 _ => Ok(()),
@@ -643,6 +1667,143 @@ fn stingy_main() -> Result<()> {
     }
 }
 
+fn print_retag_result(
+    result: &commands::tags::RetagResult,
+    done: &str,
+    binary_name: &str,
+) -> Result<()> {
+    if result.tag_rules == 0 {
+        println!("No tag rules have these tags.")?;
+        return println!("{TIP} Use {binary_name} tags list to view tag rules.");
+    }
+    println!(
+        "{OK} {done} in {} tag rule(s), tagging {} transaction(s).",
+        result.tag_rules, result.transactions
+    )?;
+    if result.deleted_tag_rules > 0 {
+        println!(
+            "{} of them became equal to other tag rules, and were deleted.",
+            result.deleted_tag_rules
+        )
+    } else {
+        Ok(())
+    }
+}
+
+fn parse_query_filter_args(
+    cmd: &mut clap::Command,
+    db: &Box<dyn database::StingyDatabase>,
+    args: &QueryFilterArgs,
+) -> Result<database::QueryFilters> {
+    let (date_from, date_to) =
+        parse_period(args.period.as_deref()).map_err(|e| cmd.error(ErrorKind::InvalidValue, e))?;
+    let (amount_min, amount_max) = {
+        parse_amount_range(args.amount_range.as_deref()).or_else(|e| {
+            bail!(cmd.error(
+                ErrorKind::InvalidValue,
+                format!("Invalid format for --amount-range: {}", e)
+            ));
+        })?
+    };
+    let mut not_tags = args.not_tags.clone();
+    if args.untagged {
+        if args.tags.len() > 0 || args.not_tags.len() > 0 {
+            bail!(cmd.error(
+                ErrorKind::ArgumentConflict,
+                format!("--untagged cannot be used with --tags and --not-tags.")
+            ));
+        }
+        // Since we match by substring and "" is a substring of all tags, if we put ""
+        // in not_tags we'll be excluding all transactions with any tags.
+        not_tags.push("".to_string());
+    }
+    let accounts = commands::accounts::get_account_or_selected(db, args.account.as_deref())?;
+    Ok(database::QueryFilters {
+        accounts: accounts.into_iter().map(|account| account.name).collect(),
+        tags: args.tags.clone(),
+        not_tags,
+        description_contains: args.description_contains.clone(),
+        amount_min: database::amount_range_min(amount_min),
+        amount_max,
+        date_from,
+        date_to,
+        transaction_types: vec![],
+    })
+}
+
+fn parse_tag_rule_args(
+    cmd: &mut clap::Command,
+    args: &TagRuleArgs,
+) -> Result<commands::tags::TagRuleAttributes> {
+    let (amount_min, amount_max) =
+        parse_amount_range(args.amount_range.as_deref()).map_err(|e| {
+            cmd.error(
+                ErrorKind::InvalidValue,
+                format!("Invalid format for --amount-range: {}", e),
+            )
+        })?;
+    let (from_date, to_date) = parse_period(args.period.as_deref()).map_err(|e| {
+        cmd.error(
+            ErrorKind::InvalidValue,
+            format!("Invalid format for --period: {}", e),
+        )
+    })?;
+    let (day_of_month_min, day_of_month_max) =
+        parse_day_of_month_range(args.day_of_month.as_deref()).map_err(|e| {
+            cmd.error(
+                ErrorKind::InvalidValue,
+                format!("Invalid format for --day-of-month: {}", e),
+            )
+        })?;
+    let day_of_week = parse_day_of_week(args.day_of_week.as_deref()).map_err(|e| {
+        cmd.error(
+            ErrorKind::InvalidValue,
+            format!("Invalid format for --day-of-week: {}", e),
+        )
+    })?;
+    let mut condition_groups = vec![];
+    for (conditions, any) in args
+        .any_of
+        .iter()
+        .map(|c| (c, true))
+        .chain(args.none_of.iter().map(|c| (c, false)))
+    {
+        let conditions = commands::tags::parse_conditions(conditions).map_err(|e| {
+            cmd.error(
+                ErrorKind::InvalidValue,
+                format!("Invalid condition for --any-of or --none-of: {}", e),
+            )
+        })?;
+        condition_groups.push(if any {
+            commands::tags::ConditionGroup::AnyOf(conditions)
+        } else {
+            commands::tags::ConditionGroup::NoneOf(conditions)
+        });
+    }
+    Ok(commands::tags::TagRuleAttributes {
+        transaction_id: args.transaction_id,
+        account: args.account.clone(),
+        description_contains: args.description_contains.clone(),
+        description_regex: args.description_regex.clone(),
+        description_not_contains: args.description_not_contains.clone(),
+        description_not_regex: args.description_not_regex.clone(),
+        transaction_type: args.transaction_type.as_ref().map(|tt| match tt {
+            crate::TransactionType::debit => model::TransactionType::Debit,
+            crate::TransactionType::credit => model::TransactionType::Credit,
+            crate::TransactionType::direct_debit => model::TransactionType::DirectDebit,
+        }),
+        amount_min,
+        amount_max,
+        from_date,
+        to_date,
+        day_of_month_min,
+        day_of_month_max,
+        day_of_week,
+        every_n_months: args.every_n_months,
+        condition_groups,
+    })
+}
+
 fn parse_period(period_option: Option<&str>) -> Result<(Option<NaiveDate>, Option<NaiveDate>)> {
     if let Some(period) = period_option {
         if let Ok((m, y)) = parse_month(period) {
@@ -679,6 +1840,37 @@ fn parse_amount_range(amount_range: Option<&str>) -> Result<(Option<f64>, Option
     }
 }
 
+/// Parse a day of the month, or a range of them. Unlike other ranges, the first day can be larger
+/// than the last, for ranges that wrap around the end of the month.
+fn parse_day_of_month_range(day_of_month: Option<&str>) -> Result<(Option<u32>, Option<u32>)> {
+    let Some(range_string) = day_of_month else {
+        return Ok((None, None));
+    };
+    let parse_day = |day: &str| -> Result<u32> {
+        match day.trim().parse() {
+            Ok(day) if (1..=31).contains(&day) => Ok(day),
+            _ => bail!("'{day}' is not a day of the month (1-31)."),
+        }
+    };
+    let (first, last) = match range_string.split_once('-') {
+        Some((first, last)) => (parse_day(first)?, parse_day(last)?),
+        None => {
+            let day = parse_day(range_string)?;
+            (day, day)
+        }
+    };
+    Ok((Some(first), Some(last)))
+}
+
+fn parse_day_of_week(day_of_week: Option<&str>) -> Result<Option<chrono::Weekday>> {
+    day_of_week
+        .map(|d| {
+            d.parse()
+                .map_err(|_| anyhow!("'{d}' is not a day of the week."))
+        })
+        .transpose()
+}
+
 fn parse_date_range(date_range: &str) -> Result<(Option<NaiveDate>, Option<NaiveDate>)> {
     parse_range(date_range, |(i, part)| {
         if part == ":" {
@@ -708,14 +1900,20 @@ where
     T: Copy + Clone + PartialOrd,
     F: Fn((usize, &str)) -> Result<Option<T>>,
 {
-    let lo_and_hi: Vec<_> = range_string
-        .splitn(2, "-")
+    // The separator is the first '-' that isn't a leading minus sign, so that negative values can
+    // be used, as in '-50--10'.
+    let Some(separator) = range_string
+        .char_indices()
+        .skip(1)
+        .find_map(|(i, c)| (c == '-').then_some(i))
+    else {
+        bail!("could not split provided range string (must contain one '-')");
+    };
+    let lo_and_hi: Vec<_> = [&range_string[..separator], &range_string[separator + 1..]]
+        .into_iter()
         .enumerate()
         .map(parse_part)
         .collect();
-    if lo_and_hi.len() != 2 {
-        bail!("could not split provided range string (must contain one '-')");
-    }
     match (&lo_and_hi[0], &lo_and_hi[1]) {
         (Err(_), _) | (_, Err(_)) => {
             bail!("invalid value(s) in range.\n\n{TIP} Tip: use ':' to mean infinity, as in ':-max' or 'min-:'.");
@@ -770,6 +1968,17 @@ fn parse_month(month: &str) -> Result<(u32, i32)> {
     }
 }
 
+/// Parse the month of an envelope operation, defaulting to the current month.
+fn parse_envelope_month(month: Option<&str>) -> Result<NaiveDate> {
+    match month {
+        Some(month) => {
+            let (m, y) = parse_month(month)?;
+            first_day_of_month(y, m)
+        }
+        None => first_day_of_month(now().year(), now().month()),
+    }
+}
+
 fn first_day_of_month(year: i32, month: u32) -> Result<NaiveDate> {
     NaiveDate::from_ymd_opt(year, month, 1)
         .ok_or_else(|| anyhow!("failed to compute the first day of the month. This is a bug."))
@@ -787,10 +1996,10 @@ fn last_day_of_month(year: i32, month: u32) -> Result<NaiveDate> {
 fn confirm(prompt: &str) -> Result<bool> {
     print!("{prompt} Proceed? [y/N] ")?;
     io::stdout().flush()?;
-    let mut yn = [0; 1];
-    io::stdin().read(&mut yn)?;
-    let yn = yn[0] as char;
-    Ok(yn == 'y' || yn == 'Y')
+    // Read the whole line, so the newline isn't left over for the next prompt.
+    let mut yn = String::new();
+    io::stdin().read_line(&mut yn)?;
+    Ok(yn.trim() == "y" || yn.trim() == "Y")
 }
 
 #[cfg(not(test))]
@@ -807,6 +2016,21 @@ where
     Ok(())
 }
 
+#[cfg(not(test))]
+fn ask(prompt: &str) -> Result<String> {
+    print!("{prompt} ")?;
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+#[cfg(test)]
+fn ask(prompt: &str) -> Result<String> {
+    let _ = prompt;
+    Ok(String::new())
+}
+
 #[cfg(test)]
 fn with_confirmation<F>(prompt: &str, action: F) -> Result<()>
 where
@@ -1012,4 +2236,55 @@ mod parse_amount_range_tests {
             (Some(50.into()), Some(f64::MAX))
         );
     }
+
+    #[test]
+    fn parse_amount_range_signed() {
+        assert_eq!(
+            parse_amount_range(Some("-50--10")).unwrap(),
+            (Some(-50.0), Some(-10.0))
+        );
+        assert_eq!(
+            parse_amount_range(Some("-50-10")).unwrap(),
+            (Some(-50.0), Some(10.0))
+        );
+        assert_eq!(
+            parse_amount_range(Some(":--10")).unwrap(),
+            (Some(f64::MIN), Some(-10.0))
+        );
+        assert!(parse_amount_range(Some("-10--50")).is_err());
+        assert!(parse_amount_range(Some("-50")).is_err());
+    }
+
+    #[test]
+    fn parse_day_of_month_range_single_day_and_wrap() {
+        assert_eq!(parse_day_of_month_range(None).unwrap(), (None, None));
+        assert_eq!(
+            parse_day_of_month_range(Some("15")).unwrap(),
+            (Some(15), Some(15))
+        );
+        assert_eq!(
+            parse_day_of_month_range(Some("25-28")).unwrap(),
+            (Some(25), Some(28))
+        );
+        assert_eq!(
+            parse_day_of_month_range(Some("28-3")).unwrap(),
+            (Some(28), Some(3))
+        );
+        assert!(parse_day_of_month_range(Some("0-3")).is_err());
+        assert!(parse_day_of_month_range(Some("25-32")).is_err());
+        assert!(parse_day_of_month_range(Some(":-3")).is_err());
+    }
+
+    #[test]
+    fn parse_day_of_week_names() {
+        assert_eq!(
+            parse_day_of_week(Some("friday")).unwrap(),
+            Some(chrono::Weekday::Fri)
+        );
+        assert_eq!(
+            parse_day_of_week(Some("Sat")).unwrap(),
+            Some(chrono::Weekday::Sat)
+        );
+        assert!(parse_day_of_week(Some("someday")).is_err());
+    }
 }
@@ -1,6 +1,7 @@
 use crate::database;
 use crate::TimeAggregation;
 use anyhow::Result;
+use chrono::NaiveDate;
 use std::io::Write;
 
 pub mod chart;
@@ -21,11 +22,19 @@ where
         &mut self,
         rows: &[database::DebitsRow],
         show_transaction_id: bool,
+        order: &database::QueryOrder,
     ) -> Result<OutputForTesting>;
     fn render_credits(
         &mut self,
         rows: &[database::CreditsRow],
         show_transaction_id: bool,
+        order: &database::QueryOrder,
+    ) -> Result<OutputForTesting>;
+    fn render_transactions(
+        &mut self,
+        rows: &[database::TransactionsRow],
+        show_transaction_id: bool,
+        order: &database::QueryOrder,
     ) -> Result<OutputForTesting>;
     fn render_by_time(
         &mut self,
@@ -34,4 +43,77 @@ where
         aggregation: &TimeAggregation,
     ) -> Result<OutputForTesting>;
     fn render_by_tag(&mut self, rows: &[database::ByTagRow]) -> Result<OutputForTesting>;
+    fn render_by_tag_tree(&mut self, rows: &[database::ByTagTreeRow]) -> Result<OutputForTesting>;
+    fn render_by_tag_time(
+        &mut self,
+        rows: &[database::ByTagTimeRow],
+        aggregation: &TimeAggregation,
+    ) -> Result<OutputForTesting>;
+    fn render_compare(
+        &mut self,
+        rows: &[database::CompareRow],
+        overall: &database::CompareRow,
+    ) -> Result<OutputForTesting>;
+    fn render_budget(&mut self, rows: &[database::BudgetRow]) -> Result<OutputForTesting>;
+    fn render_recurring(&mut self, rows: &[database::RecurringRow]) -> Result<OutputForTesting>;
+    fn render_anomalies(&mut self, rows: &[database::AnomalyRow]) -> Result<OutputForTesting>;
+    fn render_forecast(
+        &mut self,
+        history: &[database::ByTimeRow],
+        rows: &[database::ForecastRow],
+        floor: f64,
+    ) -> Result<OutputForTesting>;
+}
+
+/// The children of a tag in the tag hierarchy, or the top-level tags if `parent` is `None`,
+/// sorted by their cumulative debits.
+fn tag_tree_children<'a>(
+    rows: &'a [database::ByTagTreeRow],
+    parent: Option<&str>,
+) -> Vec<&'a database::ByTagTreeRow> {
+    let mut children: Vec<_> = rows
+        .iter()
+        .filter(|row| {
+            let row_parent = row
+                .tag
+                .rsplit_once('/')
+                .map(|(p, _)| p)
+                .filter(|p| rows.iter().any(|r| r.tag == *p));
+            row_parent == parent
+        })
+        .collect();
+    children.sort_by(|a, b| {
+        b.tag_debit_cumulative
+            .total_cmp(&a.tag_debit_cumulative)
+            .then_with(|| a.tag.cmp(&b.tag))
+    });
+    children
+}
+
+/// The amounts of each tag in each time window, as a matrix with a row per tag, sorted by the
+/// tag's total, and a column per time window, in chronological order.
+fn tag_time_matrix(rows: &[database::ByTagTimeRow]) -> (Vec<NaiveDate>, Vec<(&str, Vec<f64>)>) {
+    let mut windows: Vec<_> = rows.iter().map(|r| r.aggregation_window_end).collect();
+    windows.sort();
+    windows.dedup();
+    let mut tags: Vec<_> = rows.iter().map(|r| r.tag.as_str()).collect();
+    tags.sort();
+    tags.dedup();
+
+    let mut matrix: Vec<_> = tags
+        .into_iter()
+        .map(|tag| (tag, vec![0.0; windows.len()]))
+        .collect();
+    for row in rows {
+        let tag_idx = matrix.iter().position(|(t, _)| *t == row.tag).unwrap();
+        let window_idx = windows.binary_search(&row.aggregation_window_end).unwrap();
+        matrix[tag_idx].1[window_idx] += row.amount;
+    }
+    matrix.sort_by(|(a_tag, a), (b_tag, b)| {
+        b.iter()
+            .sum::<f64>()
+            .total_cmp(&a.iter().sum::<f64>())
+            .then_with(|| a_tag.cmp(b_tag))
+    });
+    (windows, matrix)
 }
@@ -1,12 +1,13 @@
 use anyhow::{bail, Result};
 use charming;
+use chrono::Datelike;
 use std::collections;
 use std::io::Write;
 use std::process;
 
 use crate::database;
 use crate::output::format::ToOutputFormat;
-use crate::output::{Output, OutputForTesting};
+use crate::output::{tag_time_matrix, tag_tree_children, Output, OutputForTesting};
 use crate::TimeAggregation;
 
 const FONT_SIZE: f64 = 25.0;
@@ -57,10 +58,6 @@ fn default_label() -> charming::element::Label {
     charming::element::Label::new().font_size(FONT_SIZE)
 }
 
-fn default_label_line() -> charming::element::LabelLine {
-    charming::element::LabelLine::new().line_style(charming::element::LineStyle::new().width(3))
-}
-
 fn category_axis(data: &Vec<String>) -> charming::component::Axis {
     charming::component::Axis::new()
         .type_(charming::element::AxisType::Category)
@@ -220,6 +217,116 @@ fn by_time_rows_to_chart(
     Ok(chart)
 }
 
+/// The balance line of the by-time chart, continued by a dashed line with the projected balance at
+/// the end of each month, with the large outflows marked on it.
+fn forecast_rows_to_chart(
+    history: &[database::ByTimeRow],
+    rows: &[database::ForecastRow],
+    floor: f64,
+) -> Result<charming::Chart> {
+    let month_end = |date: chrono::NaiveDate| {
+        date.with_day(1).unwrap() + chrono::Months::new(1) - chrono::Days::new(1)
+    };
+    let mut windows: Vec<_> = history
+        .iter()
+        .map(|r| r.aggregation_window_end)
+        .chain(rows.iter().map(|r| month_end(r.date)))
+        .collect();
+    windows.sort();
+    windows.dedup();
+    let mut accounts: Vec<_> = history
+        .iter()
+        .map(|r| r.account_name.as_str())
+        .chain(rows.iter().map(|r| r.account_name.as_str()))
+        .collect();
+    accounts.sort();
+    accounts.dedup();
+
+    let categories = windows
+        .iter()
+        .map(|w| w.format("%b/%Y").to_string())
+        .collect();
+    let mut chart = default_chart()
+        .legend(default_legend())
+        .grid(charming::component::Grid::new().top("17%"))
+        .x_axis(category_axis(&categories))
+        .y_axis(value_axis(Some("Balance")));
+    for account_name in accounts {
+        let mut balances = vec![f64::NAN; windows.len()];
+        let mut projected_balances = vec![f64::NAN; windows.len()];
+        let mut last_history_window = None;
+        for row in history.iter().filter(|r| r.account_name == account_name) {
+            let i = windows.binary_search(&row.aggregation_window_end).unwrap();
+            balances[i] = row.balance;
+            last_history_window = last_history_window.max(Some(i));
+        }
+        // Carry balances over the windows without transactions, and start the projection from
+        // the last one.
+        if let Some(last) = last_history_window {
+            for i in 1..=last {
+                if balances[i].is_nan() {
+                    balances[i] = balances[i - 1];
+                }
+            }
+            projected_balances[last] = balances[last];
+        }
+        let mut large_outflows = vec![];
+        for row in rows.iter().filter(|r| r.account_name == account_name) {
+            let i = windows.binary_search(&month_end(row.date)).unwrap();
+            // Rows are in date order, so the last one of each month wins. The month of the last
+            // transaction keeps its real balance, so that both lines meet.
+            if Some(i) > last_history_window {
+                projected_balances[i] = row.balance;
+            }
+            for event in row.events.iter().filter(|e| e.large_outflow) {
+                large_outflows.push(
+                    charming::element::MarkPointData::new()
+                        .name(event.description.as_str())
+                        .x_axis(i as f64)
+                        .y_axis(row.balance),
+                );
+            }
+        }
+
+        let color = string_to_color(account_name);
+        chart = chart
+            .series(
+                charming::series::Line::new()
+                    .name(format!("{account_name} (b)"))
+                    .line_style(
+                        charming::element::LineStyle::new()
+                            .width(4)
+                            .color(color.as_str()),
+                    )
+                    .item_style(charming::element::ItemStyle::new().color(color.as_str()))
+                    .symbol_size(16)
+                    .data(balances),
+            )
+            .series(
+                charming::series::Line::new()
+                    .name(format!("{account_name} (forecast)"))
+                    .line_style(
+                        charming::element::LineStyle::new()
+                            .width(4)
+                            .color(color.as_str())
+                            .type_(charming::element::LineStyleType::Dashed),
+                    )
+                    .item_style(charming::element::ItemStyle::new().color(color.as_str()))
+                    .symbol_size(16)
+                    .mark_point(charming::element::MarkPoint::new().data(large_outflows))
+                    .mark_line(charming::element::MarkLine::new().data(vec![
+                        charming::element::MarkLineVariant::Simple(
+                            charming::element::MarkLineData::new()
+                                .name("Floor")
+                                .y_axis(floor),
+                        ),
+                    ]))
+                    .data(projected_balances),
+            );
+    }
+    Ok(chart)
+}
+
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
@@ -235,45 +342,129 @@ fn string_to_color(input: &str) -> String {
     format!("#{:02x}{:02x}{:02x}", r, g, b)
 }
 
-fn by_tag_rows_to_chart(rows: &[database::ByTagRow]) -> Result<charming::Chart> {
-    let make_pie_chart = |name, center_x, data| {
-        charming::series::Pie::new()
+fn by_tag_tree_rows_to_chart(rows: &[database::ByTagTreeRow]) -> Result<charming::Chart> {
+    // Build the nodes of a tag and its descendants, leaving out the ones that are too small to
+    // show.
+    fn make_nodes(
+        rows: &[database::ByTagTreeRow],
+        parent: Option<&str>,
+        amount: &dyn Fn(&database::ByTagTreeRow) -> f64,
+        total: f64,
+    ) -> Vec<charming::series::SunburstNode> {
+        tag_tree_children(rows, parent)
+            .into_iter()
+            .filter(|row| total > 0.0 && 100.0 * amount(row) / total >= 2.0)
+            .map(|row| {
+                let name = match row.tag.rsplit_once('/') {
+                    _ if row.tag == "" => "(untagged)",
+                    Some((_, name)) if parent.is_some() => name,
+                    _ => &row.tag,
+                };
+                charming::series::SunburstNode::new(format!(
+                    "{name}\n({})",
+                    amount(row).to_output_format()
+                ))
+                .value(amount(row))
+                .item_style(
+                    charming::element::item_style::ItemStyle::new()
+                        .color(string_to_color(&row.tag)),
+                )
+                .children(make_nodes(rows, Some(&row.tag), amount, total))
+            })
+            .collect()
+    }
+    let max_depth = rows
+        .iter()
+        .map(|r| r.tag.matches('/').count() + 1)
+        .max()
+        .unwrap_or(1);
+    let make_sunburst_chart = |name, center_x, amount: &dyn Fn(&database::ByTagTreeRow) -> f64| {
+        let total: f64 = tag_tree_children(rows, None).into_iter().map(amount).sum();
+        charming::series::Sunburst::new()
             .name(name)
-            .radius("55%")
-            .center(vec![center_x, "50%"])
-            .label(default_label())
-            .label_line(default_label_line())
-            .data(data)
+            .radius(("0%", "40%"))
+            .center((center_x, "55%"))
+            .levels(
+                (0..=max_depth)
+                    .map(|_| charming::series::SunburstLevel::new().label(default_label()))
+                    .collect(),
+            )
+            .data(make_nodes(rows, None, amount, total))
     };
-    let filter_row_by_pct = |pct: f64, amount: f64, tag: &str| {
-        let normalized_tag = if tag == "" { "(untagged)" } else { tag };
-        let normalized_tag = format!("{normalized_tag}\n({})", amount.to_output_format());
-        if pct >= 2.0 {
-            Some(charming::datatype::datapoint::DataPoint::Item(
-                charming::datatype::datapoint::DataPointItem::new(amount)
-                    .name(&normalized_tag)
-                    .item_style(
-                        charming::element::item_style::ItemStyle::new()
-                            .color(string_to_color(&tag)),
-                    ),
-            ))
-        } else {
-            None
-        }
+    Ok(default_chart()
+        .title(default_title().text("Debits").top("5%").left("25%"))
+        .title(default_title().text("Credits").top("5%").left("75%"))
+        .series(make_sunburst_chart("Debits", "25%", &|r| {
+            r.tag_debit_cumulative
+        }))
+        .series(make_sunburst_chart("Credits", "75%", &|r| {
+            r.tag_credit_cumulative
+        })))
+}
+
+fn by_tag_time_rows_to_chart(
+    rows: &[database::ByTagTimeRow],
+    aggregation: &TimeAggregation,
+) -> Result<charming::Chart> {
+    let date_fmt = if *aggregation == TimeAggregation::Month {
+        "%b/%Y"
+    } else if *aggregation == TimeAggregation::Week {
+        "%Y/%m/%d"
+    } else {
+        "%Y"
     };
-    let debits: Vec<_> = rows
+    let (windows, matrix) = tag_time_matrix(rows);
+    let windows = windows
         .iter()
-        .filter_map(|r| filter_row_by_pct(r.tag_debit_pct, r.tag_debit, &r.tag))
+        .map(|w| w.format(date_fmt).to_string())
         .collect();
-    let credits: Vec<_> = rows
+
+    let mut chart = default_chart()
+        .legend(default_legend())
+        .grid(charming::component::Grid::new().top("17%"))
+        .x_axis(category_axis(&windows))
+        .y_axis(value_axis(None));
+    for (tag, amounts) in matrix {
+        chart = chart.series(
+            charming::series::Bar::new()
+                .name(if tag == "" { "(untagged)" } else { tag })
+                .stack("tags")
+                .item_style(
+                    charming::element::item_style::ItemStyle::new().color(string_to_color(tag)),
+                )
+                .data(amounts),
+        );
+    }
+    Ok(chart)
+}
+
+fn compare_rows_to_chart(
+    rows: &[database::CompareRow],
+    overall: &database::CompareRow,
+) -> Result<charming::Chart> {
+    // The largest changes go at the top, which is the end of a vertical category axis.
+    let rows: Vec<_> = [overall].into_iter().chain(rows).rev().collect();
+    let tags = rows
         .iter()
-        .filter_map(|r| filter_row_by_pct(r.tag_credit_pct, r.tag_credit, &r.tag))
+        .map(|r| if r.tag == "" { "(untagged)" } else { &r.tag }.to_string())
         .collect();
     Ok(default_chart()
-        .title(default_title().text("Debits").top("10%").left("25%"))
-        .title(default_title().text("Credits").top("10%").left("75%"))
-        .series(make_pie_chart("Debits", "25%", debits))
-        .series(make_pie_chart("Credits", "75%", credits)))
+        .legend(default_legend())
+        .grid(charming::component::Grid::new().top("10%").left("20%"))
+        .x_axis(value_axis(Some("Change")))
+        .y_axis(category_axis(&tags))
+        .series(
+            charming::series::Bar::new()
+                .name("Debits")
+                .data(rows.iter().map(|r| r.debit_change()).collect())
+                .label(default_label()),
+        )
+        .series(
+            charming::series::Bar::new()
+                .name("Credits")
+                .data(rows.iter().map(|r| r.credit_change()).collect())
+                .label(default_label()),
+        ))
 }
 
 pub struct ChartOutput<W> {
@@ -299,17 +490,83 @@ where
         Ok(OutputForTesting::Chart(chart.to_string()))
     }
 
-    fn render_by_tag(&mut self, rows: &[database::ByTagRow]) -> Result<OutputForTesting> {
-        let chart = by_tag_rows_to_chart(rows)?;
+    fn render_by_tag(&mut self, _: &[database::ByTagRow]) -> Result<OutputForTesting> {
+        unimplemented!();
+    }
+
+    fn render_by_tag_tree(&mut self, rows: &[database::ByTagTreeRow]) -> Result<OutputForTesting> {
+        let chart = by_tag_tree_rows_to_chart(rows)?;
+        chart_to_sixel(&mut self.writer, &chart)?;
+        Ok(OutputForTesting::Chart(chart.to_string()))
+    }
+
+    fn render_by_tag_time(
+        &mut self,
+        rows: &[database::ByTagTimeRow],
+        aggregation: &TimeAggregation,
+    ) -> Result<OutputForTesting> {
+        let chart = by_tag_time_rows_to_chart(rows, aggregation)?;
+        chart_to_sixel(&mut self.writer, &chart)?;
+        Ok(OutputForTesting::Chart(chart.to_string()))
+    }
+
+    fn render_compare(
+        &mut self,
+        rows: &[database::CompareRow],
+        overall: &database::CompareRow,
+    ) -> Result<OutputForTesting> {
+        let chart = compare_rows_to_chart(rows, overall)?;
+        chart_to_sixel(&mut self.writer, &chart)?;
+        Ok(OutputForTesting::Chart(chart.to_string()))
+    }
+
+    fn render_budget(&mut self, _: &[database::BudgetRow]) -> Result<OutputForTesting> {
+        unimplemented!();
+    }
+
+    fn render_recurring(&mut self, _: &[database::RecurringRow]) -> Result<OutputForTesting> {
+        unimplemented!();
+    }
+
+    fn render_anomalies(&mut self, _: &[database::AnomalyRow]) -> Result<OutputForTesting> {
+        unimplemented!();
+    }
+
+    fn render_forecast(
+        &mut self,
+        history: &[database::ByTimeRow],
+        rows: &[database::ForecastRow],
+        floor: f64,
+    ) -> Result<OutputForTesting> {
+        let chart = forecast_rows_to_chart(history, rows, floor)?;
         chart_to_sixel(&mut self.writer, &chart)?;
         Ok(OutputForTesting::Chart(chart.to_string()))
     }
 
-    fn render_debits(&mut self, _: &[database::DebitsRow], _: bool) -> Result<OutputForTesting> {
+    fn render_debits(
+        &mut self,
+        _: &[database::DebitsRow],
+        _: bool,
+        _: &database::QueryOrder,
+    ) -> Result<OutputForTesting> {
         unimplemented!();
     }
 
-    fn render_credits(&mut self, _: &[database::CreditsRow], _: bool) -> Result<OutputForTesting> {
+    fn render_credits(
+        &mut self,
+        _: &[database::CreditsRow],
+        _: bool,
+        _: &database::QueryOrder,
+    ) -> Result<OutputForTesting> {
+        unimplemented!();
+    }
+
+    fn render_transactions(
+        &mut self,
+        _: &[database::TransactionsRow],
+        _: bool,
+        _: &database::QueryOrder,
+    ) -> Result<OutputForTesting> {
         unimplemented!();
     }
 }
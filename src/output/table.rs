@@ -1,7 +1,9 @@
+use crate::commands::budget::budget_period_name;
+use crate::commands::recurring::cadence_name;
 use crate::database;
 use crate::output::format::ToOutputFormat;
-use crate::output::{Output, OutputForTesting};
-use crate::TimeAggregation;
+use crate::output::{tag_time_matrix, tag_tree_children, Output, OutputForTesting};
+use crate::{SortBy, TimeAggregation};
 use anyhow::{anyhow, bail, Result};
 use pager::Pager;
 use std::cmp::{max, min};
@@ -15,6 +17,18 @@ fn setup_pager() {
     Pager::with_pager("less --quit-if-one-screen").setup()
 }
 
+/// The header of a column that the rows may be sorted by, with ↑ if they are sorted by it in
+/// descending order, or ↓ if in ascending order.
+fn sortable_column(name: &str, column: SortBy, order: &database::QueryOrder) -> String {
+    if order.sort != column {
+        name.to_string()
+    } else if order.ascending {
+        format!("{name} ↓")
+    } else {
+        format!("{name} ↑")
+    }
+}
+
 fn textwrap_and_clone(text: &str, width: usize) -> Vec<String> {
     textwrap::wrap(text, width)
         .iter()
@@ -42,13 +56,14 @@ where
         &mut self,
         rows: &[database::DebitsRow],
         show_transaction_id: bool,
+        order: &database::QueryOrder,
     ) -> Result<OutputForTesting> {
         let mut columns = vec![
-            "Account".to_string(),
+            sortable_column("Account", SortBy::account, order),
             "Tag(s)".to_string(),
-            "Debit Amount ↑".to_string(),
-            "Description".to_string(),
-            "Date".to_string(),
+            sortable_column("Debit Amount", SortBy::amount, order),
+            sortable_column("Description", SortBy::description, order),
+            sortable_column("Date", SortBy::date, order),
             "Debit (cumulative) ↓".to_string(),
             "% (cumulative) ↓".to_string(),
         ];
@@ -80,13 +95,14 @@ where
         &mut self,
         rows: &[database::CreditsRow],
         show_transaction_id: bool,
+        order: &database::QueryOrder,
     ) -> Result<OutputForTesting> {
         let mut columns = vec![
-            "Account".to_string(),
+            sortable_column("Account", SortBy::account, order),
             "Tag(s)".to_string(),
-            "Credit Amount ↑".to_string(),
-            "Description".to_string(),
-            "Date".to_string(),
+            sortable_column("Credit Amount", SortBy::amount, order),
+            sortable_column("Description", SortBy::description, order),
+            sortable_column("Date", SortBy::date, order),
             "Credit (cumulative) ↓".to_string(),
             "% (cumulative) ↓".to_string(),
         ];
@@ -114,6 +130,45 @@ where
         self.render_table(&columns, &rows)
     }
 
+    fn render_transactions(
+        &mut self,
+        rows: &[database::TransactionsRow],
+        show_transaction_id: bool,
+        order: &database::QueryOrder,
+    ) -> Result<OutputForTesting> {
+        let mut columns = vec![
+            sortable_column("Account", SortBy::account, order),
+            sortable_column("Date", SortBy::date, order),
+            sortable_column("Description", SortBy::description, order),
+            sortable_column("Amount", SortBy::amount, order),
+            "Balance".to_string(),
+            "Running Total".to_string(),
+            "Tag(s)".to_string(),
+        ];
+        if show_transaction_id {
+            columns.insert(1, "ID".to_string());
+        }
+        let rows: Vec<Vec<String>> = rows
+            .iter()
+            .map(|r: &database::TransactionsRow| {
+                let mut row = vec![
+                    r.account_name.to_output_format(),
+                    r.posted_date.to_output_format(),
+                    r.description.to_output_format(),
+                    r.amount.to_output_format(),
+                    r.balance.to_output_format(),
+                    r.running_total.to_output_format(),
+                    (&r.tags).to_output_format(),
+                ];
+                if show_transaction_id {
+                    row.insert(1, r.transaction_id.to_output_format());
+                }
+                row
+            })
+            .collect();
+        self.render_table(&columns, &rows)
+    }
+
     fn render_by_time(
         &mut self,
         rows: &[database::ByTimeRow],
@@ -185,12 +240,298 @@ where
             .collect();
         self.render_table(&columns, &rows)
     }
+
+    fn render_by_tag_tree(&mut self, rows: &[database::ByTagTreeRow]) -> Result<OutputForTesting> {
+        let columns = vec![
+            "Tag".to_string(),
+            "Debit Amount".to_string(),
+            "Debit (cumulative) ↑".to_string(),
+            "Credit Amount".to_string(),
+            "Credit (cumulative)".to_string(),
+        ];
+        let mut output = vec![];
+        for row in tag_tree_children(rows, None) {
+            Self::by_tag_tree_rows(rows, row, 0, &mut output);
+        }
+        self.render_table(&columns, &output)
+    }
+
+    fn render_by_tag_time(
+        &mut self,
+        rows: &[database::ByTagTimeRow],
+        aggregation: &TimeAggregation,
+    ) -> Result<OutputForTesting> {
+        let date_fmt = if *aggregation == TimeAggregation::Month {
+            "%Y/%m"
+        } else if *aggregation == TimeAggregation::Week {
+            "%Y/%m/%d"
+        } else {
+            "%Y"
+        };
+        let (windows, matrix) = tag_time_matrix(rows);
+        let mut columns = vec!["Tag".to_string()];
+        columns.extend(windows.iter().map(|w| format!("{}", w.format(date_fmt))));
+        columns.push("Total ↑".to_string());
+
+        let mut window_totals = vec![0.0; windows.len()];
+        let mut output = vec![];
+        for (tag, amounts) in &matrix {
+            let mut row = vec![tag.to_output_format()];
+            for (i, amount) in amounts.iter().enumerate() {
+                row.push(amount.to_output_format());
+                window_totals[i] += amount;
+            }
+            row.push(amounts.iter().sum::<f64>().to_output_format());
+            output.push(row);
+        }
+        let mut totals_row = vec!["Total".to_string()];
+        totals_row.extend(window_totals.iter().map(|t| t.to_output_format()));
+        totals_row.push(window_totals.iter().sum::<f64>().to_output_format());
+        output.push(totals_row);
+        self.render_table(&columns, &output)
+    }
+
+    fn render_compare(
+        &mut self,
+        rows: &[database::CompareRow],
+        overall: &database::CompareRow,
+    ) -> Result<OutputForTesting> {
+        let columns = vec![
+            "Tag".to_string(),
+            "Debit Amount (against)".to_string(),
+            "Debit Amount".to_string(),
+            "Debit Change".to_string(),
+            "Debit Change %".to_string(),
+            "Credit Amount (against)".to_string(),
+            "Credit Amount".to_string(),
+            "Credit Change".to_string(),
+            "Credit Change %".to_string(),
+        ];
+        // The percentage change is undefined if there was nothing to compare against.
+        let pct_change = |change: f64, against: f64| {
+            if against > 0.0 {
+                (100.0 * change / against).to_output_format()
+            } else {
+                "-".to_string()
+            }
+        };
+        let rows: Vec<Vec<String>> = rows
+            .iter()
+            .chain([overall])
+            .map(|r: &database::CompareRow| {
+                vec![
+                    r.tag.to_output_format(),
+                    r.debit_amount_against.to_output_format(),
+                    r.debit_amount.to_output_format(),
+                    r.debit_change().to_output_format(),
+                    pct_change(r.debit_change(), r.debit_amount_against),
+                    r.credit_amount_against.to_output_format(),
+                    r.credit_amount.to_output_format(),
+                    r.credit_change().to_output_format(),
+                    pct_change(r.credit_change(), r.credit_amount_against),
+                ]
+            })
+            .collect();
+        self.render_table(&columns, &rows)
+    }
+
+    fn render_budget(&mut self, rows: &[database::BudgetRow]) -> Result<OutputForTesting> {
+        let columns = vec![
+            "Tag".to_string(),
+            "Period".to_string(),
+            "Budget".to_string(),
+            "Debit Amount".to_string(),
+            "Remaining".to_string(),
+            "Expected So Far".to_string(),
+            "Pace".to_string(),
+        ];
+        let rows: Vec<Vec<String>> = rows
+            .iter()
+            .map(|r: &database::BudgetRow| {
+                let pace = if r.expected_debit_amount > 0.0 {
+                    let pct = 100.0 * r.debit_amount / r.expected_debit_amount;
+                    if r.debit_amount > r.expected_debit_amount {
+                        format!("over ({pct:.0}%)")
+                    } else {
+                        format!("under ({pct:.0}%)")
+                    }
+                } else {
+                    "-".to_string()
+                };
+                vec![
+                    r.tag.to_output_format(),
+                    budget_period_name(r.period).to_string(),
+                    r.budget_amount.to_output_format(),
+                    r.debit_amount.to_output_format(),
+                    (r.budget_amount - r.debit_amount).to_output_format(),
+                    r.expected_debit_amount.to_output_format(),
+                    pace,
+                ]
+            })
+            .collect();
+        self.render_table(&columns, &rows)
+    }
+
+    fn render_anomalies(&mut self, rows: &[database::AnomalyRow]) -> Result<OutputForTesting> {
+        let columns = vec![
+            "Kind".to_string(),
+            "Tag or Description".to_string(),
+            "Date".to_string(),
+            "Amount".to_string(),
+            "Baseline".to_string(),
+            "Score ↑".to_string(),
+        ];
+        let rows: Vec<Vec<String>> = rows
+            .iter()
+            .map(|r: &database::AnomalyRow| {
+                let (kind, subject, samples) = match r.kind {
+                    database::AnomalyKind::Tag => (
+                        "tag",
+                        if r.subject.is_empty() {
+                            "(untagged)".to_string()
+                        } else {
+                            r.subject.clone()
+                        },
+                        format!("{} months", r.baseline.samples),
+                    ),
+                    database::AnomalyKind::Transaction => (
+                        "transaction",
+                        r.subject.clone(),
+                        format!("{} debits", r.baseline.samples),
+                    ),
+                };
+                vec![
+                    kind.to_string(),
+                    subject,
+                    r.date.map(|d| d.to_output_format()).unwrap_or_default(),
+                    r.amount.to_output_format(),
+                    format!(
+                        "{} ± {} over {}",
+                        r.baseline.median.to_output_format(),
+                        r.baseline.spread.to_output_format(),
+                        samples
+                    ),
+                    format!("{:.1}", r.score),
+                ]
+            })
+            .collect();
+        self.render_table(&columns, &rows)
+    }
+
+    fn render_forecast(
+        &mut self,
+        _: &[database::ByTimeRow],
+        rows: &[database::ForecastRow],
+        floor: f64,
+    ) -> Result<OutputForTesting> {
+        let columns = vec![
+            "Account".to_string(),
+            "Date ↓".to_string(),
+            "Description".to_string(),
+            "Amount".to_string(),
+            "Balance".to_string(),
+            "Note".to_string(),
+        ];
+        let mut table_rows: Vec<Vec<String>> = vec![];
+        for (i, r) in rows.iter().enumerate() {
+            let below_floor = r.balance < floor;
+            let row = |description: String, amount: String, large_outflow: bool| {
+                let notes: Vec<_> = [
+                    (large_outflow, "large outflow"),
+                    (below_floor, "below floor"),
+                ]
+                .into_iter()
+                .filter(|(is, _)| *is)
+                .map(|(_, note)| note)
+                .collect();
+                vec![
+                    r.account_name.to_output_format(),
+                    r.date.to_output_format(),
+                    description,
+                    amount,
+                    r.balance.to_output_format(),
+                    notes.join(", "),
+                ]
+            };
+            for event in &r.events {
+                table_rows.push(row(
+                    event.description.clone(),
+                    event.amount.to_output_format(),
+                    event.large_outflow,
+                ));
+            }
+            // The last day of each account's forecast.
+            if rows.get(i + 1).map(|next| &next.account_name) != Some(&r.account_name) {
+                table_rows.push(row("End of forecast".to_string(), "".to_string(), false));
+            }
+        }
+        self.render_table(&columns, &table_rows)
+    }
+
+    fn render_recurring(&mut self, rows: &[database::RecurringRow]) -> Result<OutputForTesting> {
+        let columns = vec![
+            "Account".to_string(),
+            "Description".to_string(),
+            "Cadence".to_string(),
+            "Occurrences".to_string(),
+            "Typical Amount".to_string(),
+            "Last Date".to_string(),
+            "Next Date".to_string(),
+            "Annualized Amount ↑".to_string(),
+            "Price Increase".to_string(),
+        ];
+        let rows: Vec<Vec<String>> = rows
+            .iter()
+            .map(|r: &database::RecurringRow| {
+                vec![
+                    r.account_name.to_output_format(),
+                    r.description.to_output_format(),
+                    cadence_name(r.cadence).to_string(),
+                    (r.occurrences as i64).to_output_format(),
+                    r.typical_amount.to_output_format(),
+                    r.last_date.to_output_format(),
+                    r.next_date.to_output_format(),
+                    r.annualized_amount.to_output_format(),
+                    r.price_increase
+                        .map(|(previous, new, date)| {
+                            format!(
+                                "{} → {} ({})",
+                                previous.to_output_format(),
+                                new.to_output_format(),
+                                date.to_output_format()
+                            )
+                        })
+                        .unwrap_or_default(),
+                ]
+            })
+            .collect();
+        self.render_table(&columns, &rows)
+    }
 }
 
 impl<W> TableOutput<W>
 where
     W: Write,
 {
+    /// Append the rows for a tag and its descendants, depth-first.
+    fn by_tag_tree_rows(
+        rows: &[database::ByTagTreeRow],
+        row: &database::ByTagTreeRow,
+        level: usize,
+        output: &mut Vec<Vec<String>>,
+    ) {
+        output.push(vec![
+            format!("{}{}", "· ".repeat(level), row.tag.to_output_format()),
+            row.tag_debit.to_output_format(),
+            row.tag_debit_cumulative.to_output_format(),
+            row.tag_credit.to_output_format(),
+            row.tag_credit_cumulative.to_output_format(),
+        ]);
+        for child in tag_tree_children(rows, Some(&row.tag)) {
+            Self::by_tag_tree_rows(rows, child, level + 1, output);
+        }
+    }
+
     fn render_header(
         &mut self,
         columns: &Vec<Vec<String>>,